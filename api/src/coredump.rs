@@ -0,0 +1,581 @@
+//! ELF core dumps of processes killed by a signal whose default action is to
+//! dump core, such as `SIGSEGV` or `SIGABRT`.
+//!
+//! Where the core goes is configured by `/proc/sys/kernel/core_pattern`: a
+//! path, or `|` followed by a program that is started with the core piped
+//! into its standard input.
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use axerrno::{AxError, AxResult};
+use axfs_ng::{FS_CONTEXT, OpenOptions};
+use axhal::{paging::MappingFlags, time::wall_time, uspace::UserContext};
+use axio::Buf;
+use axsync::Mutex;
+use axtask::current;
+use lazy_static::lazy_static;
+use linux_raw_sys::general::RLIMIT_CORE;
+use memory_addr::{PAGE_SIZE_4K, VirtAddr};
+use starry_core::{
+    mm::{Vma, read_process_memory, vmas},
+    task::{AsThread, ProcessData, Thread, get_task},
+};
+use starry_process::Pid;
+use starry_signal::{SignalInfo, SignalSet, Signo};
+
+use crate::{
+    file::{FD_TABLE, File, FileDescriptor, FileLike, Pipe, SealedBuf},
+    task::spawn_user_process,
+};
+
+/// The longest `core_pattern` accepted, including the terminating NUL
+const CORENAME_MAX_SIZE: usize = 128;
+
+lazy_static! {
+    static ref CORE_PATTERN: Mutex<String> = Mutex::new("core".to_string());
+}
+
+/// Returns the pattern core files are named by.
+pub fn core_pattern() -> String {
+    CORE_PATTERN.lock().clone()
+}
+
+/// Sets the pattern core files are named by.
+pub fn set_core_pattern(pattern: &str) -> AxResult {
+    if pattern.len() >= CORENAME_MAX_SIZE {
+        return Err(AxError::InvalidInput);
+    }
+    *CORE_PATTERN.lock() = pattern.to_string();
+    Ok(())
+}
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+const NT_AUXV: u32 = 6;
+const NT_SIGINFO: u32 = 0x5349_4749;
+const NT_FILE: u32 = 0x4649_4c45;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        const ELF_MACHINE: u16 = 62;
+        const ELF_NGREG: usize = 27;
+
+        /// Lays out the registers as `struct user_regs_struct`.
+        fn elf_gregs(uctx: &UserContext) -> [u64; ELF_NGREG] {
+            [
+                uctx.r15,
+                uctx.r14,
+                uctx.r13,
+                uctx.r12,
+                uctx.rbp,
+                uctx.rbx,
+                uctx.r11,
+                uctx.r10,
+                uctx.r9,
+                uctx.r8,
+                uctx.rax,
+                uctx.rcx,
+                uctx.rdx,
+                uctx.rsi,
+                uctx.rdi,
+                // orig_rax: not in a system call
+                u64::MAX,
+                uctx.rip,
+                uctx.cs,
+                uctx.rflags,
+                uctx.rsp,
+                uctx.ss,
+                uctx.tls() as u64,
+                uctx.gs_base,
+                0,
+                0,
+                0,
+                0,
+            ]
+        }
+    } else if #[cfg(target_arch = "aarch64")] {
+        const ELF_MACHINE: u16 = 183;
+        const ELF_NGREG: usize = 34;
+
+        /// Lays out the registers as `struct user_pt_regs`.
+        fn elf_gregs(uctx: &UserContext) -> [u64; ELF_NGREG] {
+            let mut regs = [0; ELF_NGREG];
+            regs[..31].copy_from_slice(&uctx.r);
+            regs[31] = uctx.usp;
+            regs[32] = uctx.elr;
+            regs[33] = uctx.spsr;
+            regs
+        }
+    } else if #[cfg(target_arch = "loongarch64")] {
+        const ELF_MACHINE: u16 = 258;
+        const ELF_NGREG: usize = 45;
+
+        /// Lays out the registers as `struct user_pt_regs`.
+        fn elf_gregs(uctx: &UserContext) -> [u64; ELF_NGREG] {
+            let r = &uctx.regs;
+            let mut regs = [0; ELF_NGREG];
+            let gprs = [
+                r.zero, r.ra, r.tp, r.sp, r.a0, r.a1, r.a2, r.a3, r.a4, r.a5, r.a6, r.a7, r.t0,
+                r.t1, r.t2, r.t3, r.t4, r.t5, r.t6, r.t7, r.t8, r.u0, r.fp, r.s0, r.s1, r.s2,
+                r.s3, r.s4, r.s5, r.s6, r.s7, r.s8,
+            ];
+            for (reg, value) in regs.iter_mut().zip(gprs) {
+                *reg = value as u64;
+            }
+            // orig_a0
+            regs[32] = r.a0 as u64;
+            regs[33] = uctx.era as u64;
+            regs
+        }
+    } else {
+        const ELF_MACHINE: u16 = 243;
+        const ELF_NGREG: usize = 32;
+
+        /// Lays out the registers as `struct user_regs_struct`, where the
+        /// program counter takes the place of the zero register.
+        fn elf_gregs(uctx: &UserContext) -> [u64; ELF_NGREG] {
+            let r = &uctx.regs;
+            [
+                uctx.sepc, r.ra, r.sp, r.gp, r.tp, r.t0, r.t1, r.t2, r.s0, r.s1, r.a0, r.a1, r.a2,
+                r.a3, r.a4, r.a5, r.a6, r.a7, r.s2, r.s3, r.s4, r.s5, r.s6, r.s7, r.s8, r.s9,
+                r.s10, r.s11, r.t3, r.t4, r.t5, r.t6,
+            ]
+            .map(|reg| reg as u64)
+        }
+    }
+}
+
+/// Where a core is written to, up to a size limit.
+struct CoreFile {
+    file: Arc<dyn FileLike>,
+    written: u64,
+    limit: u64,
+}
+
+impl CoreFile {
+    fn new(file: Arc<dyn FileLike>, limit: u64) -> Self {
+        Self {
+            file,
+            written: 0,
+            limit,
+        }
+    }
+
+    /// Writes all of `data`, failing if it would take the core past the
+    /// limit.
+    fn write(&mut self, data: &[u8]) -> AxResult {
+        if self.written + data.len() as u64 > self.limit {
+            return Err(AxError::StorageFull);
+        }
+        let mut buf = SealedBuf::from(data);
+        while buf.remaining() > 0 {
+            if self.file.write(&mut buf)? == 0 {
+                return Err(AxError::BrokenPipe);
+            }
+        }
+        self.written += data.len() as u64;
+        Ok(())
+    }
+}
+
+fn sigset_bits(set: &SignalSet) -> u64 {
+    (1..=64)
+        .filter_map(Signo::from_repr)
+        .filter(|signo| set.has(*signo))
+        .fold(0, |mask, signo| mask | (1 << (signo as u32 - 1)))
+}
+
+/// Builds `struct elf_prstatus` for the thread `tid`.
+fn prstatus(thr: &Thread, tid: Pid, uctx: &UserContext, signo: Signo) -> Vec<u8> {
+    let proc = &thr.proc_data.proc;
+    let usage = thr.rusage();
+    let children = thr.proc_data.children_rusage();
+
+    let mut buf = Vec::new();
+    // pr_info: si_signo, si_code and si_errno
+    buf.extend_from_slice(&(signo as i32).to_ne_bytes());
+    buf.extend_from_slice(&[0; 8]);
+    // pr_cursig and padding
+    buf.extend_from_slice(&(signo as i16).to_ne_bytes());
+    buf.extend_from_slice(&[0; 2]);
+    buf.extend_from_slice(&sigset_bits(&thr.signal.pending()).to_ne_bytes());
+    buf.extend_from_slice(&sigset_bits(&thr.signal.blocked()).to_ne_bytes());
+    for id in [
+        tid,
+        proc.parent().map_or(0, |parent| parent.pid()),
+        proc.group().pgid(),
+        proc.group().session().sid(),
+    ] {
+        buf.extend_from_slice(&id.to_ne_bytes());
+    }
+    for time in [usage.utime, usage.stime, children.utime, children.stime] {
+        buf.extend_from_slice(&(time.as_secs() as i64).to_ne_bytes());
+        buf.extend_from_slice(&(time.subsec_micros() as i64).to_ne_bytes());
+    }
+    for reg in elf_gregs(uctx) {
+        buf.extend_from_slice(&reg.to_ne_bytes());
+    }
+    // pr_fpvalid and padding
+    buf.extend_from_slice(&[0; 8]);
+    buf
+}
+
+/// Builds `struct elf_prpsinfo` for the process.
+fn prpsinfo(proc_data: &ProcessData) -> Vec<u8> {
+    let proc = &proc_data.proc;
+    let mut buf = Vec::new();
+    // pr_state, pr_sname, pr_zomb, pr_nice and padding
+    buf.extend_from_slice(&[0, b'R', 0, 0, 0, 0, 0, 0]);
+    // pr_flag, pr_uid and pr_gid
+    buf.extend_from_slice(&[0; 16]);
+    for id in [
+        proc.pid(),
+        proc.parent().map_or(0, |parent| parent.pid()),
+        proc.group().pgid(),
+        proc.group().session().sid(),
+    ] {
+        buf.extend_from_slice(&id.to_ne_bytes());
+    }
+
+    let mut fname = [0; 16];
+    let name = current().name();
+    let len = name.len().min(15);
+    fname[..len].copy_from_slice(&name.as_bytes()[..len]);
+    buf.extend_from_slice(&fname);
+
+    let mut psargs = [0; 80];
+    let args = proc_data.cmdline.read().join(" ");
+    let len = args.len().min(79);
+    psargs[..len].copy_from_slice(&args.as_bytes()[..len]);
+    buf.extend_from_slice(&psargs);
+    buf
+}
+
+/// Builds the `NT_FILE` note listing the files mapped into the process.
+fn file_note(vmas: &[Vma]) -> Vec<u8> {
+    let files: Vec<&Vma> = vmas.iter().filter(|vma| vma.inode != 0).collect();
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&files.len().to_ne_bytes());
+    buf.extend_from_slice(&PAGE_SIZE_4K.to_ne_bytes());
+    for vma in &files {
+        buf.extend_from_slice(&vma.start.as_usize().to_ne_bytes());
+        buf.extend_from_slice(&vma.end.as_usize().to_ne_bytes());
+        buf.extend_from_slice(&(vma.offset as usize / PAGE_SIZE_4K).to_ne_bytes());
+    }
+    for vma in &files {
+        buf.extend_from_slice(vma.name.as_bytes());
+        buf.push(0);
+    }
+    buf
+}
+
+/// Appends an ELF note owned by `CORE`.
+fn push_note(notes: &mut Vec<u8>, ty: u32, desc: &[u8]) {
+    const NAME: &[u8] = b"CORE\0";
+    notes.extend_from_slice(&(NAME.len() as u32).to_ne_bytes());
+    notes.extend_from_slice(&(desc.len() as u32).to_ne_bytes());
+    notes.extend_from_slice(&ty.to_ne_bytes());
+    notes.extend_from_slice(NAME);
+    notes.resize(notes.len().next_multiple_of(4), 0);
+    notes.extend_from_slice(desc);
+    notes.resize(notes.len().next_multiple_of(4), 0);
+}
+
+/// Builds the notes: the status of the dumping thread, the process, the
+/// signal, the auxiliary vector and the mapped files, followed by the status
+/// of the other threads.
+fn build_notes(thr: &Thread, uctx: &UserContext, sig: &SignalInfo, vmas: &[Vma]) -> Vec<u8> {
+    let proc_data = &thr.proc_data;
+    let signo = sig.signo();
+    let tid = current().id().as_u64() as Pid;
+
+    let siginfo = unsafe {
+        core::slice::from_raw_parts((&raw const sig.0).cast::<u8>(), size_of_val(&sig.0))
+    };
+
+    let mut notes = Vec::new();
+    push_note(&mut notes, NT_PRSTATUS, &prstatus(thr, tid, uctx, signo));
+    push_note(&mut notes, NT_PRPSINFO, &prpsinfo(proc_data));
+    push_note(&mut notes, NT_SIGINFO, siginfo);
+    push_note(&mut notes, NT_AUXV, &proc_data.auxv.read());
+    push_note(&mut notes, NT_FILE, &file_note(vmas));
+
+    // Other threads are reported with the registers they last entered the
+    // kernel with.
+    for other in proc_data.proc.threads() {
+        if other == tid {
+            continue;
+        }
+        if let Ok(task) = get_task(other)
+            && let Some(other_thr) = task.try_as_thread()
+            && let Some(other_uctx) = other_thr.user_context()
+        {
+            push_note(
+                &mut notes,
+                NT_PRSTATUS,
+                &prstatus(other_thr, other, &other_uctx, signo),
+            );
+        }
+    }
+    notes
+}
+
+fn elf_header(phnum: u16) -> Vec<u8> {
+    let data = if cfg!(target_endian = "little") { 1 } else { 2 };
+    let mut buf = Vec::with_capacity(ELF_HEADER_SIZE);
+    // e_ident: ELFCLASS64, the byte order, EV_CURRENT and ELFOSABI_NONE
+    buf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, data, 1, 0]);
+    buf.extend_from_slice(&[0; 8]);
+    buf.extend_from_slice(&ET_CORE.to_ne_bytes());
+    buf.extend_from_slice(&ELF_MACHINE.to_ne_bytes());
+    buf.extend_from_slice(&1u32.to_ne_bytes());
+    // e_entry, e_phoff, e_shoff and e_flags
+    buf.extend_from_slice(&0u64.to_ne_bytes());
+    buf.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_ne_bytes());
+    buf.extend_from_slice(&0u64.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    for half in [
+        ELF_HEADER_SIZE as u16,
+        PROGRAM_HEADER_SIZE as u16,
+        phnum,
+        0,
+        0,
+        0,
+    ] {
+        buf.extend_from_slice(&half.to_ne_bytes());
+    }
+    buf
+}
+
+fn push_program_header(
+    buf: &mut Vec<u8>,
+    ty: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    filesz: usize,
+    memsz: usize,
+) {
+    let align = if ty == PT_LOAD { PAGE_SIZE_4K } else { 0 };
+    buf.extend_from_slice(&ty.to_ne_bytes());
+    buf.extend_from_slice(&flags.to_ne_bytes());
+    // p_offset, p_vaddr, p_paddr, p_filesz, p_memsz and p_align
+    for value in [offset, vaddr, 0, filesz, memsz, align] {
+        buf.extend_from_slice(&(value as u64).to_ne_bytes());
+    }
+}
+
+/// Returns how many bytes of `vma` go into the core.
+///
+/// Device memory, secret memory and the signal trampoline don't belong to the
+/// process and are left out, as is memory that can't be read.
+fn dump_size(vma: &Vma) -> usize {
+    if vma.is_linear() || !vma.flags.contains(MappingFlags::READ) {
+        0
+    } else {
+        vma.end - vma.start
+    }
+}
+
+/// Reads the page at `vaddr`, or zeros if it was never touched, without
+/// faulting in memory that isn't there.
+fn read_page(proc_data: &ProcessData, vaddr: VirtAddr, buf: &mut [u8]) {
+    let present = {
+        let aspace = proc_data.aspace.lock();
+        aspace.page_table().query(vaddr).is_ok() || proc_data.swap.lock().contains(vaddr)
+    };
+    if !present || read_process_memory(proc_data, vaddr, buf).ok() != Some(buf.len()) {
+        buf.fill(0);
+    }
+}
+
+/// Writes the core of the process of `thr`, which `sig` killed while it was
+/// running with the registers `uctx`.
+fn write_core(thr: &Thread, uctx: &UserContext, sig: &SignalInfo, out: &mut CoreFile) -> AxResult {
+    let proc_data = &thr.proc_data;
+    let vmas = vmas(proc_data, &proc_data.aspace.lock());
+    let notes = build_notes(thr, uctx, sig, &vmas);
+
+    let phnum = vmas.len() + 1;
+    let notes_offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+    let data_offset = (notes_offset + notes.len()).next_multiple_of(PAGE_SIZE_4K);
+
+    let mut headers = elf_header(u16::try_from(phnum).map_err(|_| AxError::InvalidData)?);
+    push_program_header(&mut headers, PT_NOTE, 0, notes_offset, 0, notes.len(), 0);
+    let mut offset = data_offset;
+    for vma in &vmas {
+        let mut flags = 0;
+        for (flag, bit) in [
+            (MappingFlags::READ, PF_R),
+            (MappingFlags::WRITE, PF_W),
+            (MappingFlags::EXECUTE, PF_X),
+        ] {
+            if vma.flags.contains(flag) {
+                flags |= bit;
+            }
+        }
+        let size = dump_size(vma);
+        push_program_header(
+            &mut headers,
+            PT_LOAD,
+            flags,
+            offset,
+            vma.start.as_usize(),
+            size,
+            vma.end - vma.start,
+        );
+        offset += size;
+    }
+
+    out.write(&headers)?;
+    out.write(&notes)?;
+    out.write(&vec![0; data_offset - notes_offset - notes.len()])?;
+
+    let mut page = vec![0; PAGE_SIZE_4K];
+    for vma in &vmas {
+        for page_offset in (0..dump_size(vma)).step_by(PAGE_SIZE_4K) {
+            read_page(proc_data, vma.start + page_offset, &mut page);
+            out.write(&page)?;
+        }
+    }
+    Ok(())
+}
+
+/// Expands the `%` specifiers of a `core_pattern`.
+fn expand_pattern(pattern: &str, proc_data: &ProcessData, signo: Signo, limit: u64) -> String {
+    let curr = current();
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let _ = match chars.next() {
+            Some('%') => write!(out, "%"),
+            Some('p' | 'P') => write!(out, "{}", proc_data.proc.pid()),
+            Some('i' | 'I') => write!(out, "{}", curr.id().as_u64()),
+            // Every task runs as root.
+            Some('u' | 'g') => write!(out, "0"),
+            Some('d') => write!(out, "{}", proc_data.is_dumpable() as u8),
+            Some('s') => write!(out, "{}", signo as u32),
+            Some('t') => write!(out, "{}", wall_time().as_secs()),
+            Some('h') => write!(out, "starry"),
+            Some('e') => write!(out, "{}", curr.name().replace('/', "!")),
+            Some('E') => write!(out, "{}", proc_data.exe_path.read().replace('/', "!")),
+            Some('c') => write!(out, "{limit}"),
+            // Unknown specifiers and a trailing `%` are dropped.
+            _ => Ok(()),
+        };
+    }
+    out
+}
+
+/// Starts the program `args` names, as a child of init, and returns the pipe
+/// to its standard input.
+///
+/// Like the helpers Linux starts, it is reaped by the kernel rather than left
+/// as a zombie for init, which doesn't know about it.
+fn spawn_pipe_helper(proc_data: &ProcessData, args: Vec<String>) -> AxResult<Arc<dyn FileLike>> {
+    let envs = [
+        "HOME=/".to_string(),
+        "PATH=/sbin:/bin:/usr/sbin:/usr/bin".to_string(),
+    ];
+    let mut init = proc_data.proc.clone();
+    while let Some(parent) = init.parent() {
+        init = parent;
+    }
+
+    let (read_end, write_end) = Pipe::new();
+    spawn_user_process(
+        &args,
+        &envs,
+        |pid| init.fork(pid),
+        None,
+        |helper| {
+            helper.set_autoreap();
+            let mut scope = helper.scope.write();
+            FD_TABLE
+                .scope_mut(&mut scope)
+                .write()
+                .add(FileDescriptor {
+                    inner: Arc::new(read_end),
+                    cloexec: false,
+                })
+                .map_err(|_| AxError::TooManyOpenFiles)?;
+            FS_CONTEXT
+                .scope_mut(&mut scope)
+                .lock()
+                .clone_from(&FS_CONTEXT.lock());
+            Ok(())
+        },
+    )?;
+
+    Ok(Arc::new(write_end))
+}
+
+/// Dumps the core of the process of `thr`, which `sig` is killing, where
+/// `core_pattern` says.
+///
+/// Returns whether a complete core was written.
+pub fn do_coredump(thr: &Thread, uctx: &UserContext, sig: &SignalInfo) -> bool {
+    let proc_data = &thr.proc_data;
+    if !proc_data.is_dumpable() {
+        return false;
+    }
+    let signo = sig.signo();
+    let limit = proc_data.rlim.read()[RLIMIT_CORE].current;
+    let pattern = core_pattern();
+
+    let out = if let Some(command) = pattern.strip_prefix('|') {
+        // The size limit doesn't apply to pipes, but a limit of 1 disables
+        // them.
+        if limit == 1 {
+            return false;
+        }
+        let args = command
+            .split_ascii_whitespace()
+            .map(|arg| expand_pattern(arg, proc_data, signo, limit))
+            .collect();
+        spawn_pipe_helper(proc_data, args).map(|file| CoreFile::new(file, u64::MAX))
+    } else {
+        // Nothing useful fits in less than a page.
+        if pattern.is_empty() || limit < PAGE_SIZE_4K as u64 {
+            return false;
+        }
+        let path = expand_pattern(&pattern, proc_data, signo, limit);
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .no_follow(true)
+            .mode(0o600)
+            .open(&FS_CONTEXT.lock(), &path)
+            .and_then(|result| result.into_file())
+            .map(|file| CoreFile::new(Arc::new(File::new(file)), limit))
+    };
+
+    match out.and_then(|mut out| write_core(thr, uctx, sig, &mut out)) {
+        Ok(()) => true,
+        Err(err) => {
+            warn!("Failed to dump core of {:?}: {err:?}", proc_data.proc);
+            false
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Signals for synchronous faults of user space.
+
+use axhal::{
+    paging::MappingFlags,
+    uspace::{ExceptionInfo, ExceptionKind, UserContext},
+};
+use linux_raw_sys::general::{
+    BUS_ADRALN, BUS_ADRERR, ILL_ILLOPC, ILL_PRVOPC, SEGV_ACCERR, SEGV_MAPERR, SEGV_PKUERR,
+    SI_KERNEL, TRAP_BRKPT,
+};
+use memory_addr::VirtAddr;
+use starry_core::{mm::pkey_fault, task::Thread};
+use starry_signal::{SignalInfo, Signo};
+
+/// Offsets of the memory fault fields of `siginfo_t`
+const SI_CODE_OFFSET: usize = 8;
+const SI_ADDR_OFFSET: usize = 16;
+const SI_PKEY_OFFSET: usize = 32;
+
+/// Builds a fault signal reporting `code` as `si_code` and `addr` as
+/// `si_addr`.
+fn fault_info(signo: Signo, code: u32, addr: usize) -> SignalInfo {
+    let mut sig = SignalInfo::new_kernel(signo);
+    let raw = (&raw mut sig.0).cast::<u8>();
+    unsafe {
+        raw.add(SI_CODE_OFFSET).cast::<i32>().write(code as i32);
+        raw.add(SI_ADDR_OFFSET).cast::<usize>().write(addr);
+    }
+    sig
+}
+
+/// Builds the `SIGSEGV` for a page fault at `addr` that couldn't be handled.
+///
+/// An address no mapping covers is reported with `SEGV_MAPERR`, an access the
+/// mapping doesn't permit with `SEGV_ACCERR`, and a protection key violation
+/// with `SEGV_PKUERR` and the key.
+pub fn segv_info(thr: &Thread, addr: VirtAddr, access_flags: MappingFlags) -> SignalInfo {
+    let aspace = thr.proc_data.aspace.lock();
+    if let Some(pkey) = pkey_fault(&aspace, addr, access_flags) {
+        let mut sig = fault_info(Signo::SIGSEGV, SEGV_PKUERR, addr.as_usize());
+        unsafe {
+            (&raw mut sig.0)
+                .cast::<u8>()
+                .add(SI_PKEY_OFFSET)
+                .cast::<u32>()
+                .write(pkey);
+        }
+        return sig;
+    }
+    let code = if aspace.find_area(addr).is_some() {
+        SEGV_ACCERR
+    } else {
+        SEGV_MAPERR
+    };
+    fault_info(Signo::SIGSEGV, code, addr.as_usize())
+}
+
+/// Builds the `SIGBUS` for an access at `addr` to a page of a mapped file
+/// beyond its end.
+pub fn bus_info(addr: VirtAddr) -> SignalInfo {
+    fault_info(Signo::SIGBUS, BUS_ADRERR, addr.as_usize())
+}
+
+/// Builds the signal for an exception other than a page fault taken by the
+/// user context `uctx`.
+pub fn exception_info(uctx: &UserContext, exc_info: &ExceptionInfo) -> SignalInfo {
+    let ip = uctx.ip();
+    match exc_info.kind() {
+        ExceptionKind::Misaligned => {
+            fault_info(Signo::SIGBUS, BUS_ADRALN, misaligned_addr(exc_info, ip))
+        }
+        ExceptionKind::Breakpoint => breakpoint_info(ip),
+        ExceptionKind::IllegalInstruction => {
+            let code = if privileged_insn(ip) {
+                ILL_PRVOPC
+            } else {
+                ILL_ILLOPC
+            };
+            fault_info(Signo::SIGILL, code, ip)
+        }
+        _ => other_exception_info(exc_info, ip),
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        use linux_raw_sys::general::{FPE_FLTDIV, FPE_INTDIV};
+
+        const DIVIDE_ERROR_VECTOR: u8 = 0;
+        const GENERAL_PROTECTION_FAULT_VECTOR: u8 = 13;
+        const X87_FPU_VECTOR: u8 = 16;
+        const SIMD_FLOATING_POINT_VECTOR: u8 = 19;
+
+        /// `#AC` doesn't report the misaligned address.
+        fn misaligned_addr(_exc_info: &ExceptionInfo, ip: usize) -> usize {
+            ip
+        }
+
+        /// `int3` is a single byte and leaves `rip` after it.
+        fn breakpoint_info(ip: usize) -> SignalInfo {
+            fault_info(Signo::SIGTRAP, TRAP_BRKPT, ip - 1)
+        }
+
+        /// Privileged instructions raise `#GP` rather than `#UD`.
+        fn privileged_insn(_ip: usize) -> bool {
+            false
+        }
+
+        fn other_exception_info(exc_info: &ExceptionInfo, ip: usize) -> SignalInfo {
+            match exc_info.vector {
+                DIVIDE_ERROR_VECTOR => fault_info(Signo::SIGFPE, FPE_INTDIV, ip),
+                // The exception flags are not decoded; division by zero is by
+                // far the most common cause.
+                X87_FPU_VECTOR | SIMD_FLOATING_POINT_VECTOR => {
+                    fault_info(Signo::SIGFPE, FPE_FLTDIV, ip)
+                }
+                GENERAL_PROTECTION_FAULT_VECTOR => fault_info(Signo::SIGSEGV, SI_KERNEL, 0),
+                _ => fault_info(Signo::SIGTRAP, SI_KERNEL, 0),
+            }
+        }
+    } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+        use starry_vm::VmPtr;
+
+        fn misaligned_addr(exc_info: &ExceptionInfo, _ip: usize) -> usize {
+            exc_info.stval
+        }
+
+        /// `ebreak` leaves `sepc` at itself.
+        fn breakpoint_info(ip: usize) -> SignalInfo {
+            fault_info(Signo::SIGTRAP, TRAP_BRKPT, ip)
+        }
+
+        /// Returns whether the instruction at `ip` is a `SYSTEM` instruction
+        /// other than `ecall` and `ebreak`, such as `sret`, `wfi` or
+        /// `sfence.vma`.
+        fn privileged_insn(ip: usize) -> bool {
+            let Ok(insn) = (ip as *const u32).vm_read() else {
+                return false;
+            };
+            insn & 0x7f == 0x73 && (insn >> 12) & 0x7 == 0 && insn >> 20 > 1
+        }
+
+        /// Arithmetic doesn't trap on RISC-V.
+        fn other_exception_info(_exc_info: &ExceptionInfo, _ip: usize) -> SignalInfo {
+            fault_info(Signo::SIGTRAP, SI_KERNEL, 0)
+        }
+    } else if #[cfg(target_arch = "aarch64")] {
+        use starry_vm::VmPtr;
+
+        /// `FAR_EL1` holds the misaligned address, which is the program
+        /// counter itself for a PC alignment fault.
+        fn misaligned_addr(exc_info: &ExceptionInfo, _ip: usize) -> usize {
+            exc_info.far
+        }
+
+        /// `brk` leaves `ELR_EL1` at itself.
+        fn breakpoint_info(ip: usize) -> SignalInfo {
+            fault_info(Signo::SIGTRAP, TRAP_BRKPT, ip)
+        }
+
+        /// Returns whether the instruction at `ip` is `eret`, `hvc`, `smc`, or
+        /// a system instruction or register access not meant for EL0.
+        fn privileged_insn(ip: usize) -> bool {
+            let Ok(insn) = (ip as *const u32).vm_read() else {
+                return false;
+            };
+            // eret, eretaa, eretab
+            if insn & 0xffff_fbff == 0xd69f_0bff || insn == 0xd69f_03e0 {
+                return true;
+            }
+            // hvc, smc
+            if insn & 0xffe0_001f == 0xd400_0002 || insn & 0xffe0_001f == 0xd400_0003 {
+                return true;
+            }
+            // The system instruction class with an `op1` other than the one of
+            // EL0 accessible registers and hints
+            insn & 0xffc0_0000 == 0xd500_0000 && (insn >> 16) & 0x7 != 0x3
+        }
+
+        /// Floating-point exceptions are not trapped and integer division by
+        /// zero yields zero.
+        fn other_exception_info(_exc_info: &ExceptionInfo, _ip: usize) -> SignalInfo {
+            fault_info(Signo::SIGTRAP, SI_KERNEL, 0)
+        }
+    } else if #[cfg(target_arch = "loongarch64")] {
+        use linux_raw_sys::general::{FPE_INTDIV, FPE_INTOVF};
+        use starry_vm::VmPtr;
+
+        /// The `break` code compilers use for integer overflow checks
+        const BRK_OVERFLOW: u32 = 6;
+        /// The `break` code compilers use for integer division by zero checks
+        const BRK_DIVZERO: u32 = 7;
+
+        fn misaligned_addr(_exc_info: &ExceptionInfo, ip: usize) -> usize {
+            ip
+        }
+
+        /// Integer division checks end in a `break` with a well-known code,
+        /// which is reported as the arithmetic error it stands for.
+        fn breakpoint_info(ip: usize) -> SignalInfo {
+            let code = (ip as *const u32)
+                .vm_read()
+                .ok()
+                .filter(|insn| insn & 0xffff_8000 == 0x002a_0000)
+                .map(|insn| insn & 0x7fff);
+            match code {
+                Some(BRK_DIVZERO) => fault_info(Signo::SIGFPE, FPE_INTDIV, ip),
+                Some(BRK_OVERFLOW) => fault_info(Signo::SIGFPE, FPE_INTOVF, ip),
+                _ => fault_info(Signo::SIGTRAP, TRAP_BRKPT, ip),
+            }
+        }
+
+        /// Returns whether the instruction at `ip` accesses a CSR or is one of
+        /// `iocsr*`, `tlb*`, `ertn` and `idle`.
+        fn privileged_insn(ip: usize) -> bool {
+            let Ok(insn) = (ip as *const u32).vm_read() else {
+                return false;
+            };
+            insn >> 24 == 0x04 || insn >> 22 == 0x19
+        }
+
+        /// The instruction privilege exception is not told apart by its kind.
+        fn other_exception_info(_exc_info: &ExceptionInfo, ip: usize) -> SignalInfo {
+            if privileged_insn(ip) {
+                fault_info(Signo::SIGILL, ILL_PRVOPC, ip)
+            } else {
+                fault_info(Signo::SIGTRAP, SI_KERNEL, 0)
+            }
+        }
+    }
+}
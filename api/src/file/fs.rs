@@ -8,15 +8,21 @@ use core::{
 };
 
 use axerrno::{AxError, AxResult};
-use axfs_ng::{FS_CONTEXT, FsContext};
+use axfs_ng::{FS_CONTEXT, FileFlags, FsContext};
 use axfs_ng_vfs::{Location, Metadata, NodeFlags};
+use axio::{Buf, Seek, SeekFrom};
 use axpoll::{IoEvents, Pollable};
 use axsync::Mutex;
 use axtask::future::Poller;
-use linux_raw_sys::general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW};
+use linux_raw_sys::general::{
+    AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW, F_SEAL_FUTURE_WRITE, F_SEAL_GROW, F_SEAL_WRITE,
+};
 
 use super::{FileLike, Kstat, get_file_like};
-use crate::file::{SealedBuf, SealedBufMut};
+use crate::{
+    file::{SealedBuf, SealedBufMut},
+    vfs::file_seals,
+};
 
 pub fn with_fs<R>(dirfd: c_int, f: impl FnOnce(&mut FsContext) -> AxResult<R>) -> AxResult<R> {
     let mut fs = FS_CONTEXT.lock();
@@ -115,6 +121,29 @@ impl File {
         &self.inner
     }
 
+    /// Checks a write of `len` bytes at `offset`, or at the file position if
+    /// `None`, against the seals of the file.
+    pub fn check_seals(&self, offset: Option<u64>, len: usize) -> AxResult {
+        let Ok(seals) = file_seals(self.inner.location()) else {
+            return Ok(());
+        };
+        if seals & (F_SEAL_WRITE | F_SEAL_FUTURE_WRITE) != 0 {
+            return Err(AxError::OperationNotPermitted);
+        }
+        if seals & F_SEAL_GROW != 0 {
+            let size = self.inner.location().len()?;
+            let offset = match offset {
+                Some(offset) => offset,
+                None if self.inner.access(FileFlags::APPEND).is_ok() => size,
+                None => self.inner.seek(SeekFrom::Current(0))?,
+            };
+            if offset + len as u64 > size {
+                return Err(AxError::OperationNotPermitted);
+            }
+        }
+        Ok(())
+    }
+
     fn is_blocking(&self) -> bool {
         self.inner.location().flags().contains(NodeFlags::BLOCKING)
     }
@@ -138,6 +167,7 @@ impl FileLike for File {
     }
 
     fn write(&self, src: &mut SealedBuf) -> AxResult<usize> {
+        self.check_seals(None, src.remaining())?;
         let inner = self.inner();
         if likely(self.is_blocking()) {
             inner.write(src)
@@ -4,7 +4,9 @@ mod fs;
 mod net;
 mod pidfd;
 mod pipe;
+mod secretmem;
 pub mod signalfd;
+pub mod userfaultfd;
 
 use alloc::{borrow::Cow, sync::Arc};
 use core::{any::Any, ffi::c_int, time::Duration};
@@ -26,6 +28,7 @@ pub use self::{
     net::Socket,
     pidfd::PidFd,
     pipe::Pipe,
+    secretmem::SecretMem,
 };
 use crate::{
     io::IoVectorBufIo,
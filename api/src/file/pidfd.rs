@@ -7,16 +7,19 @@ use core::task::Context;
 use axerrno::{AxError, AxResult};
 use axpoll::{IoEvents, PollSet, Pollable};
 use starry_core::task::ProcessData;
+use starry_process::Pid;
 
 use crate::file::{FileLike, Kstat, SealedBuf, SealedBufMut};
 
 pub struct PidFd {
+    pid: Pid,
     proc_data: Weak<ProcessData>,
     exit_event: Arc<PollSet>,
 }
 impl PidFd {
     pub fn new(proc_data: &Arc<ProcessData>) -> Self {
         Self {
+            pid: proc_data.proc.pid(),
             proc_data: Arc::downgrade(proc_data),
             exit_event: proc_data.exit_event.clone(),
         }
@@ -25,6 +28,11 @@ impl PidFd {
     pub fn process_data(&self) -> AxResult<Arc<ProcessData>> {
         self.proc_data.upgrade().ok_or(AxError::NoSuchProcess)
     }
+
+    /// The PID of the process, which stays valid until it is reaped.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
 }
 impl FileLike for PidFd {
     fn read(&self, _dst: &mut SealedBufMut) -> AxResult<usize> {
@@ -0,0 +1,90 @@
+use alloc::{borrow::Cow, sync::Arc};
+use core::{any::Any, task::Context};
+
+use axerrno::{AxError, AxResult};
+use axpoll::{IoEvents, Pollable};
+use axsync::Mutex;
+use linux_raw_sys::general::S_IFREG;
+use starry_core::mm::SecretRegion;
+
+use crate::file::{FileLike, Kstat, SealedBuf, SealedBufMut};
+
+struct SecretMemInner {
+    size: usize,
+    /// Allocated by the first mmap, after which the size is fixed
+    region: Option<Arc<SecretRegion>>,
+}
+
+/// A `memfd_secret` file, whose pages can only be accessed through its
+/// mappings.
+pub struct SecretMem {
+    inner: Mutex<SecretMemInner>,
+}
+
+impl SecretMem {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(SecretMemInner {
+                size: 0,
+                region: None,
+            }),
+        })
+    }
+
+    /// Resizes the file, which is only possible before it is first mapped.
+    pub fn set_len(&self, len: u64) -> AxResult {
+        let mut inner = self.inner.lock();
+        if inner.region.is_some() {
+            return Err(AxError::ResourceBusy);
+        }
+        inner.size = usize::try_from(len).map_err(|_| AxError::InvalidInput)?;
+        Ok(())
+    }
+
+    /// Returns the pages backing `[offset, offset + len)` of the file,
+    /// allocating them on first use.
+    pub fn region(&self, offset: usize, len: usize) -> AxResult<Arc<SecretRegion>> {
+        let mut inner = self.inner.lock();
+        if offset.checked_add(len).is_none_or(|end| end > inner.size) {
+            return Err(AxError::InvalidInput);
+        }
+        if inner.region.is_none() {
+            inner.region = Some(SecretRegion::new(inner.size)?);
+        }
+        Ok(inner.region.clone().unwrap())
+    }
+}
+
+impl FileLike for SecretMem {
+    fn read(&self, _dst: &mut SealedBufMut) -> AxResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> AxResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat {
+            mode: S_IFREG | 0o600,
+            size: self.inner.lock().size as u64,
+            ..Default::default()
+        })
+    }
+
+    fn path(&self) -> Cow<str> {
+        "/secretmem (deleted)".into()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl Pollable for SecretMem {
+    fn poll(&self) -> IoEvents {
+        IoEvents::empty()
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
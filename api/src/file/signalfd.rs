@@ -112,8 +112,10 @@ impl Signalfd {
     fn dequeue_signal(&self) -> Option<SignalInfo> {
         let mask = self.mask();
         let curr = current();
-        let signal = &curr.as_thread().signal;
-        signal.dequeue_signal(&mask)
+        let thr = curr.as_thread();
+        let sig = thr.signal.dequeue_signal(&mask)?;
+        thr.proc_data.queued.remove(sig.signo());
+        Some(sig)
     }
 }
 
@@ -0,0 +1,513 @@
+use alloc::{
+    borrow::Cow,
+    sync::{Arc, Weak},
+    vec,
+};
+use core::{
+    any::Any,
+    mem::{self, MaybeUninit},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::Context,
+};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axio::{BufMut, Write};
+use axmm::backend::Backend;
+use axpoll::{IoEvents, Pollable};
+use axtask::future::Poller;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+use starry_core::{
+    mm::{UserFaultCtx, UserFaultMode, touch_page},
+    task::ProcessData,
+};
+use starry_vm::vm_read_slice;
+use zerocopy::{Immutable, IntoBytes};
+
+use crate::{
+    file::{FileLike, Kstat, SealedBuf, SealedBufMut},
+    mm::UserPtr,
+};
+
+/// The userfaultfd API version.
+const UFFD_API: u64 = 0xaa;
+
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+
+const UFFD_FEATURE_PAGEFAULT_FLAG_WP: u64 = 1 << 0;
+const UFFD_FEATURE_THREAD_ID: u64 = 1 << 8;
+const UFFD_FEATURES: u64 = UFFD_FEATURE_PAGEFAULT_FLAG_WP | UFFD_FEATURE_THREAD_ID;
+
+const _UFFDIO_REGISTER: u32 = 0x00;
+const _UFFDIO_UNREGISTER: u32 = 0x01;
+const _UFFDIO_WAKE: u32 = 0x02;
+const _UFFDIO_COPY: u32 = 0x03;
+const _UFFDIO_ZEROPAGE: u32 = 0x04;
+const _UFFDIO_WRITEPROTECT: u32 = 0x06;
+const _UFFDIO_API: u32 = 0x3f;
+
+const UFFDIO_REGISTER: u32 = 0xc020_aa00;
+const UFFDIO_UNREGISTER: u32 = 0x8010_aa01;
+const UFFDIO_WAKE: u32 = 0x8010_aa02;
+const UFFDIO_COPY: u32 = 0xc028_aa03;
+const UFFDIO_ZEROPAGE: u32 = 0xc020_aa04;
+const UFFDIO_WRITEPROTECT: u32 = 0xc018_aa06;
+const UFFDIO_API: u32 = 0xc018_aa3f;
+
+const UFFD_API_IOCTLS: u64 = 1 << _UFFDIO_REGISTER | 1 << _UFFDIO_UNREGISTER | 1 << _UFFDIO_API;
+const UFFD_API_RANGE_IOCTLS: u64 = 1 << _UFFDIO_WAKE | 1 << _UFFDIO_COPY | 1 << _UFFDIO_ZEROPAGE;
+
+const UFFDIO_COPY_MODE_DONTWAKE: u64 = 1 << 0;
+const UFFDIO_COPY_MODE_WP: u64 = 1 << 1;
+const UFFDIO_ZEROPAGE_MODE_DONTWAKE: u64 = 1 << 0;
+const UFFDIO_WRITEPROTECT_MODE_WP: u64 = 1 << 0;
+const UFFDIO_WRITEPROTECT_MODE_DONTWAKE: u64 = 1 << 1;
+
+#[repr(C)]
+#[derive(Immutable, IntoBytes)]
+struct UffdMsg {
+    event: u8,
+    reserved1: u8,
+    reserved2: u16,
+    reserved3: u32,
+    flags: u64,
+    address: u64,
+    ptid: u32,
+    _pad: u32,
+}
+
+const _: [(); 32] = [(); mem::size_of::<UffdMsg>()];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioApi {
+    api: u64,
+    features: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioRange {
+    start: u64,
+    len: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioRegister {
+    range: UffdioRange,
+    mode: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioCopy {
+    dst: u64,
+    src: u64,
+    len: u64,
+    mode: u64,
+    copy: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioZeropage {
+    range: UffdioRange,
+    mode: u64,
+    zeropage: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioWriteprotect {
+    range: UffdioRange,
+    mode: u64,
+}
+
+/// A userfaultfd, through which user space resolves the page faults of the
+/// ranges registered with it.
+pub struct UserFaultFd {
+    ctx: Arc<UserFaultCtx>,
+    /// The process whose address space the fd serves
+    proc_data: Weak<ProcessData>,
+    /// The features enabled by `UFFDIO_API`
+    features: AtomicU64,
+    /// Whether the `UFFDIO_API` handshake has been done
+    api_done: AtomicBool,
+    non_blocking: AtomicBool,
+}
+
+impl UserFaultFd {
+    pub fn new(proc_data: &Arc<ProcessData>) -> Arc<Self> {
+        Arc::new(Self {
+            ctx: UserFaultCtx::new(),
+            proc_data: Arc::downgrade(proc_data),
+            features: AtomicU64::new(0),
+            api_done: AtomicBool::new(false),
+            non_blocking: AtomicBool::new(false),
+        })
+    }
+
+    fn proc_data(&self) -> AxResult<Arc<ProcessData>> {
+        self.proc_data.upgrade().ok_or(AxError::NoSuchProcess)
+    }
+
+    /// Validates a page-aligned range of the served address space.
+    fn range(&self, range: &UffdioRange) -> AxResult<(VirtAddr, VirtAddr)> {
+        let start = VirtAddr::from(range.start as usize);
+        let len = range.len as usize;
+        if !start.is_aligned_4k() || !len.is_multiple_of(PAGE_SIZE_4K) || len == 0 {
+            return Err(AxError::InvalidInput);
+        }
+        let end = start.checked_add(len).ok_or(AxError::InvalidInput)?;
+        Ok((start, end))
+    }
+
+    fn api(&self, arg: usize) -> AxResult<usize> {
+        let api = UserPtr::<UffdioApi>::from(arg).get_as_mut()?;
+        if api.api != UFFD_API || api.features & !UFFD_FEATURES != 0 {
+            api.features = 0;
+            api.ioctls = 0;
+            return Err(AxError::InvalidInput);
+        }
+        if self.api_done.swap(true, Ordering::AcqRel) {
+            return Err(AxError::InvalidInput);
+        }
+        self.features.store(api.features, Ordering::Release);
+        api.features = UFFD_FEATURES;
+        api.ioctls = UFFD_API_IOCTLS;
+        Ok(0)
+    }
+
+    fn register(&self, arg: usize) -> AxResult<usize> {
+        let reg = UserPtr::<UffdioRegister>::from(arg).get_as_mut()?;
+        let (start, end) = self.range(&reg.range)?;
+        let mode = UserFaultMode::from_bits(reg.mode)
+            .filter(|mode| !mode.is_empty())
+            .ok_or(AxError::InvalidInput)?;
+
+        let proc_data = self.proc_data()?;
+        let aspace = proc_data.aspace.lock();
+        // Only anonymous and shared memory can have its pages supplied.
+        let mut addr = start;
+        while addr < end {
+            let area = aspace.find_area(addr).ok_or(AxError::NoMemory)?;
+            if !matches!(area.backend(), Backend::Cow { .. } | Backend::Shared(_)) {
+                return Err(AxError::InvalidInput);
+            }
+            addr = area.end();
+        }
+        proc_data
+            .uffd
+            .lock()
+            .register(start, end, mode, &self.ctx)?;
+
+        reg.ioctls = UFFD_API_RANGE_IOCTLS;
+        if mode.contains(UserFaultMode::WP) {
+            reg.ioctls |= 1 << _UFFDIO_WRITEPROTECT;
+        }
+        Ok(0)
+    }
+
+    fn unregister(&self, arg: usize) -> AxResult<usize> {
+        let range = UserPtr::<UffdioRange>::from(arg).get_as_mut()?;
+        let (start, end) = self.range(range)?;
+        let proc_data = self.proc_data()?;
+        let mut aspace = proc_data.aspace.lock();
+        let mut uffd = proc_data.uffd.lock();
+        uffd.write_protect(&proc_data, &mut aspace, start, end, false)?;
+        uffd.unregister(start, end);
+        proc_data
+            .pkeys
+            .lock()
+            .apply(&proc_data, &mut aspace, start, end);
+        self.ctx.wake(start, end - start);
+        Ok(0)
+    }
+
+    /// Checks that `[start, end)` is registered with this fd.
+    fn check_registered(
+        &self,
+        proc_data: &ProcessData,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) -> AxResult {
+        if proc_data.uffd.lock().is_registered(start, end, &self.ctx) {
+            Ok(())
+        } else {
+            Err(AxError::NotFound)
+        }
+    }
+
+    /// Supplies the missing page at `page`, filled from `data` if given.
+    fn fill_page(&self, proc_data: &ProcessData, page: VirtAddr, data: Option<&[u8]>) -> AxResult {
+        let mut aspace = proc_data.aspace.lock();
+        if aspace.page_table().query(page).is_ok() || proc_data.swap.lock().contains(page) {
+            return Err(AxError::AlreadyExists);
+        }
+        let area = aspace.find_area(page).ok_or(AxError::NotFound)?;
+        let access_flags = area.flags();
+        let anon = matches!(area.backend(), Backend::Cow { .. });
+        proc_data.populate_area(&mut aspace, page, PAGE_SIZE_4K, access_flags)?;
+        proc_data
+            .pkeys
+            .lock()
+            .apply(proc_data, &mut aspace, page, page + PAGE_SIZE_4K);
+        if let Some(data) = data {
+            aspace.write(page, data)?;
+        }
+        drop(aspace);
+        if anon {
+            touch_page(proc_data.proc.pid(), page);
+        }
+        Ok(())
+    }
+
+    fn copy(&self, arg: usize) -> AxResult<usize> {
+        let copy = UserPtr::<UffdioCopy>::from(arg).get_as_mut()?;
+        let (start, end) = self.range(&UffdioRange {
+            start: copy.dst,
+            len: copy.len,
+        })?;
+        if copy.mode & !(UFFDIO_COPY_MODE_DONTWAKE | UFFDIO_COPY_MODE_WP) != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        let proc_data = self.proc_data()?;
+        self.check_registered(&proc_data, start, end)?;
+
+        let mut buf = vec![0; PAGE_SIZE_4K];
+        let mut copied = 0;
+        let result = (|| -> AxResult {
+            for page in (start.as_usize()..end.as_usize()).step_by(PAGE_SIZE_4K) {
+                let src = (copy.src as usize + copied) as *const u8;
+                vm_read_slice(src, unsafe {
+                    mem::transmute::<&mut [u8], &mut [MaybeUninit<u8>]>(buf.as_mut_slice())
+                })?;
+                let page = VirtAddr::from(page);
+                self.fill_page(&proc_data, page, Some(&buf))?;
+                if copy.mode & UFFDIO_COPY_MODE_WP != 0 {
+                    let mut aspace = proc_data.aspace.lock();
+                    proc_data.uffd.lock().write_protect(
+                        &proc_data,
+                        &mut aspace,
+                        page,
+                        page + PAGE_SIZE_4K,
+                        true,
+                    )?;
+                    proc_data.pkeys.lock().apply(
+                        &proc_data,
+                        &mut aspace,
+                        page,
+                        page + PAGE_SIZE_4K,
+                    );
+                }
+                copied += PAGE_SIZE_4K;
+            }
+            Ok(())
+        })();
+
+        if copied > 0 && copy.mode & UFFDIO_COPY_MODE_DONTWAKE == 0 {
+            self.ctx.wake(start, copied);
+        }
+        match result {
+            Ok(()) => {
+                copy.copy = copied as i64;
+                Ok(0)
+            }
+            Err(err) if copied > 0 => {
+                copy.copy = copied as i64;
+                Err(err)
+            }
+            Err(err) => {
+                copy.copy = -(LinuxError::from(err).code() as i64);
+                Err(err)
+            }
+        }
+    }
+
+    fn zeropage(&self, arg: usize) -> AxResult<usize> {
+        let zeropage = UserPtr::<UffdioZeropage>::from(arg).get_as_mut()?;
+        let (start, end) = self.range(&zeropage.range)?;
+        if zeropage.mode & !UFFDIO_ZEROPAGE_MODE_DONTWAKE != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        let proc_data = self.proc_data()?;
+        self.check_registered(&proc_data, start, end)?;
+
+        let mut filled = 0;
+        let result = (start.as_usize()..end.as_usize())
+            .step_by(PAGE_SIZE_4K)
+            .try_for_each(|page| {
+                // Freshly populated anonymous and shared pages are zeroed.
+                self.fill_page(&proc_data, VirtAddr::from(page), None)?;
+                filled += PAGE_SIZE_4K;
+                Ok(())
+            });
+
+        if filled > 0 && zeropage.mode & UFFDIO_ZEROPAGE_MODE_DONTWAKE == 0 {
+            self.ctx.wake(start, filled);
+        }
+        match result {
+            Ok(()) => {
+                zeropage.zeropage = filled as i64;
+                Ok(0)
+            }
+            Err(err) if filled > 0 => {
+                zeropage.zeropage = filled as i64;
+                Err(err)
+            }
+            Err(err) => {
+                zeropage.zeropage = -(LinuxError::from(err).code() as i64);
+                Err(err)
+            }
+        }
+    }
+
+    fn writeprotect(&self, arg: usize) -> AxResult<usize> {
+        let wp = UserPtr::<UffdioWriteprotect>::from(arg).get_as_mut()?;
+        let (start, end) = self.range(&wp.range)?;
+        if wp.mode & !(UFFDIO_WRITEPROTECT_MODE_WP | UFFDIO_WRITEPROTECT_MODE_DONTWAKE) != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        let protect = wp.mode & UFFDIO_WRITEPROTECT_MODE_WP != 0;
+        let proc_data = self.proc_data()?;
+        {
+            let mut aspace = proc_data.aspace.lock();
+            let mut uffd = proc_data.uffd.lock();
+            let registered = uffd.is_registered(start, end, &self.ctx)
+                && uffd
+                    .find(start)
+                    .is_some_and(|(mode, _)| mode.contains(UserFaultMode::WP));
+            if !registered {
+                return Err(AxError::NotFound);
+            }
+            uffd.write_protect(&proc_data, &mut aspace, start, end, protect)?;
+            proc_data
+                .pkeys
+                .lock()
+                .apply(&proc_data, &mut aspace, start, end);
+        }
+        if !protect && wp.mode & UFFDIO_WRITEPROTECT_MODE_DONTWAKE == 0 {
+            self.ctx.wake(start, end - start);
+        }
+        Ok(0)
+    }
+
+    fn wake(&self, arg: usize) -> AxResult<usize> {
+        let range = UserPtr::<UffdioRange>::from(arg).get_as_mut()?;
+        let (start, end) = self.range(range)?;
+        self.ctx.wake(start, end - start);
+        Ok(0)
+    }
+}
+
+impl Drop for UserFaultFd {
+    fn drop(&mut self) {
+        self.ctx.release();
+        if let Some(proc_data) = self.proc_data.upgrade() {
+            let mut aspace = proc_data.aspace.lock();
+            let _ = proc_data
+                .uffd
+                .lock()
+                .release(&proc_data, &mut aspace, &self.ctx);
+        }
+    }
+}
+
+impl FileLike for UserFaultFd {
+    fn read(&self, dst: &mut SealedBufMut) -> AxResult<usize> {
+        if !self.api_done.load(Ordering::Acquire) {
+            return Err(AxError::InvalidInput);
+        }
+        if dst.remaining_mut() < size_of::<UffdMsg>() {
+            return Err(AxError::InvalidInput);
+        }
+        let thread_id = self.features.load(Ordering::Acquire) & UFFD_FEATURE_THREAD_ID != 0;
+
+        Poller::new(self, IoEvents::IN)
+            .non_blocking(self.nonblocking())
+            .poll(|| {
+                let mut read = 0;
+                while dst.remaining_mut() >= size_of::<UffdMsg>() {
+                    let Some(fault) = self.ctx.pop_event() else {
+                        break;
+                    };
+                    let msg = UffdMsg {
+                        event: UFFD_EVENT_PAGEFAULT,
+                        reserved1: 0,
+                        reserved2: 0,
+                        reserved3: 0,
+                        flags: fault.flags,
+                        address: fault.address.as_usize() as u64,
+                        ptid: if thread_id { fault.tid } else { 0 },
+                        _pad: 0,
+                    };
+                    dst.write(msg.as_bytes())?;
+                    read += size_of::<UffdMsg>();
+                }
+                if read > 0 {
+                    Ok(read)
+                } else {
+                    Err(AxError::WouldBlock)
+                }
+            })
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> AxResult<usize> {
+        Err(AxError::BadFileDescriptor)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat::default())
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
+        if cmd == UFFDIO_API {
+            return self.api(arg);
+        }
+        if !self.api_done.load(Ordering::Acquire) {
+            return Err(AxError::InvalidInput);
+        }
+        match cmd {
+            UFFDIO_REGISTER => self.register(arg),
+            UFFDIO_UNREGISTER => self.unregister(arg),
+            UFFDIO_WAKE => self.wake(arg),
+            UFFDIO_COPY => self.copy(arg),
+            UFFDIO_ZEROPAGE => self.zeropage(arg),
+            UFFDIO_WRITEPROTECT => self.writeprotect(arg),
+            _ => Err(AxError::InvalidInput),
+        }
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, non_blocking: bool) -> AxResult {
+        self.non_blocking.store(non_blocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn path(&self) -> Cow<str> {
+        "anon_inode:[userfaultfd]".into()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl Pollable for UserFaultFd {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, self.ctx.has_events());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.ctx.register_reader(context);
+        }
+    }
+}
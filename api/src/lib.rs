@@ -10,6 +10,8 @@ extern crate axlog;
 
 extern crate alloc;
 
+pub mod coredump;
+pub mod fault;
 pub mod file;
 pub mod io;
 pub mod mm;
@@ -1,10 +1,11 @@
-use alloc::string::String;
+use alloc::{string::String, sync::Arc};
 use core::{
     alloc::Layout,
     ffi::c_char,
     hint::unlikely,
     mem::{MaybeUninit, transmute},
     ptr, slice, str,
+    sync::atomic::AtomicU32,
 };
 
 use axerrno::{AxError, AxResult};
@@ -13,12 +14,19 @@ use axhal::{
     trap::{PAGE_FAULT, register_trap_handler},
 };
 use axio::{Buf, BufMut, Read, Write};
+use axmm::backend::Backend;
 use axtask::current;
 use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
 use starry_core::{
-    mm::{access_user_memory, is_accessing_user_memory},
-    task::AsThread,
+    mm::{
+        HPAGE_SIZE, SWAP_CLUSTER_MAX, UFFD_PAGEFAULT_FLAG_WP, UFFD_PAGEFAULT_FLAG_WRITE, UserFault,
+        UserFaultCtx, UserFaultMode, VmEvent, access_user_memory, can_alloc_page, count_vm_event,
+        count_vm_events, is_accessing_user_memory, out_of_memory, pkey_fault, reclaim_pages,
+        swap_in_page, swap_in_range, touch_page, wait_oom_victim,
+    },
+    task::{AsThread, Thread},
 };
+use starry_process::Pid;
 use starry_vm::{vm_load_until_nul, vm_read_slice, vm_write_slice};
 
 fn check_region(start: VirtAddr, layout: Layout, access_flags: MappingFlags) -> AxResult<()> {
@@ -28,15 +36,39 @@ fn check_region(start: VirtAddr, layout: Layout, access_flags: MappingFlags) ->
     }
 
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let thr = curr.as_thread();
+    let proc_data = &thr.proc_data;
 
-    if !aspace.can_access_range(start, layout.size(), access_flags) {
-        return Err(AxError::BadAddress);
+    // Pages user space has to supply are waited for before the kernel touches
+    // them, since faults while copying user memory can't block.
+    let mut page = start.align_down_4k();
+    while page < start + layout.size() {
+        if let Some((ctx, fault)) = pending_user_fault(thr, page, access_flags) {
+            ctx.wait_fault(fault)?;
+            continue;
+        }
+        page += PAGE_SIZE_4K;
     }
 
+    let mut aspace = proc_data.aspace.lock();
+    swap_in_range(proc_data, &mut aspace, start, layout.size())?;
     let page_start = start.align_down_4k();
     let page_end = (start + layout.size()).align_up_4k();
-    aspace.populate_area(page_start, page_end - page_start, access_flags)?;
+    if access_flags.contains(MappingFlags::WRITE) {
+        proc_data
+            .soft_dirty
+            .lock()
+            .mark_dirty(&mut aspace, page_start, page_end)?;
+    }
+    if !aspace.can_access_range(start, layout.size(), access_flags) {
+        return Err(AxError::BadAddress);
+    }
+
+    proc_data.populate_area(&mut aspace, page_start, page_end - page_start, access_flags)?;
+    proc_data
+        .pkeys
+        .lock()
+        .apply(proc_data, &mut aspace, page_start, page_end);
 
     Ok(())
 }
@@ -71,7 +103,20 @@ fn check_null_terminated<T: PartialEq + Default>(
                 // querying the page table since the page might has not been
                 // allocated yet.
                 let curr = current();
-                let aspace = curr.as_thread().proc_data.aspace.lock();
+                let proc_data = &curr.as_thread().proc_data;
+                let mut aspace = proc_data.aspace.lock();
+                swap_in_page(proc_data, &mut aspace, page)?;
+                if access_flags.contains(MappingFlags::WRITE) {
+                    proc_data.soft_dirty.lock().mark_dirty(
+                        &mut aspace,
+                        page,
+                        page + PAGE_SIZE_4K,
+                    )?;
+                }
+                proc_data
+                    .pkeys
+                    .lock()
+                    .apply(proc_data, &mut aspace, page, page + PAGE_SIZE_4K);
                 if !aspace.can_access_range(page, PAGE_SIZE_4K, access_flags) {
                     return Err(AxError::BadAddress);
                 }
@@ -153,6 +198,15 @@ impl<T> UserPtr<T> {
     }
 }
 
+impl UserPtr<u32> {
+    /// Get the pointer as an atomic word shared with user space, validating
+    /// the memory region.
+    pub fn get_as_atomic(self) -> AxResult<&'static AtomicU32> {
+        let word = self.get_as_mut()?;
+        Ok(unsafe { AtomicU32::from_ptr(word) })
+    }
+}
+
 /// An immutable pointer to user space memory.
 #[repr(transparent)]
 #[derive(PartialEq, Clone, Copy)]
@@ -248,11 +302,204 @@ fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
     let Some(thr) = curr.try_as_thread() else {
         return false;
     };
+    // User memory is copied with interrupts disabled, so user space can't be
+    // waited for here; the copy fails instead.
+    if pending_user_fault(thr, vaddr, access_flags).is_some() {
+        return false;
+    }
 
-    thr.proc_data
-        .aspace
-        .lock()
-        .handle_page_fault(vaddr, access_flags)
+    // User memory is copied with interrupts disabled, so we can neither swap
+    // pages out nor pick an OOM victim here; the copy fails instead.
+    try_handle_user_page_fault(thr, vaddr, access_flags).is_ok()
+}
+
+/// Handles a page fault in the user address space of `thr`, recording it in
+/// the thread's fault counters.
+///
+/// If the fault can't be serviced for lack of memory, pages are swapped out
+/// or, failing that, the OOM killer picks a victim once, and the fault is
+/// retried.
+///
+/// Fails with [`AxError::OutOfRange`] for an access to a page of a mapped
+/// file beyond its end, which raises `SIGBUS` rather than `SIGSEGV`.
+pub fn handle_user_page_fault(
+    thr: &Thread,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> AxResult<()> {
+    if let Some((ctx, fault)) = pending_user_fault(thr, vaddr, access_flags) {
+        // Whether the page was supplied, the wait was interrupted by a signal
+        // or the userfaultfd was closed, the access is simply retried.
+        let _ = ctx.wait_fault(fault);
+        return Ok(());
+    }
+    let mut killed = false;
+    loop {
+        match try_handle_user_page_fault(thr, vaddr, access_flags) {
+            Ok(()) => return Ok(()),
+            Err(AxError::NoMemory) if reclaim_pages(SWAP_CLUSTER_MAX) > 0 => continue,
+            Err(AxError::NoMemory) if !killed => {}
+            Err(err) => return Err(err),
+        }
+        killed = true;
+        match out_of_memory() {
+            // The pending SIGKILL ends the thread before it returns to user
+            // space.
+            Some(victim) if victim == thr.proc_data.proc.pid() => return Ok(()),
+            Some(victim) => wait_oom_victim(victim),
+            None => return Err(AxError::NoMemory),
+        }
+    }
+}
+
+/// Returns the userfaultfd that has to resolve a fault at `vaddr`, and the
+/// fault to report to it.
+fn pending_user_fault(
+    thr: &Thread,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> Option<(Arc<UserFaultCtx>, UserFault)> {
+    let aspace = thr.proc_data.aspace.lock();
+    let uffd = thr.proc_data.uffd.lock();
+    let (mode, ctx) = uffd.find(vaddr)?;
+    let write = access_flags.contains(MappingFlags::WRITE);
+    let flags = if mode.contains(UserFaultMode::WP) && write && uffd.is_write_protected(vaddr) {
+        UFFD_PAGEFAULT_FLAG_WRITE | UFFD_PAGEFAULT_FLAG_WP
+    } else if mode.contains(UserFaultMode::MISSING)
+        && aspace.page_table().query(vaddr).is_err()
+        && !thr.proc_data.swap.lock().contains(vaddr)
+    {
+        if write { UFFD_PAGEFAULT_FLAG_WRITE } else { 0 }
+    } else {
+        return None;
+    };
+    let fault = UserFault {
+        address: vaddr.align_down_4k(),
+        flags,
+        tid: current().id().as_u64() as Pid,
+    };
+    Some((ctx, fault))
+}
+
+fn try_handle_user_page_fault(
+    thr: &Thread,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> AxResult<()> {
+    let mut aspace = thr.proc_data.aspace.lock();
+    // A swapped-out page has no page table entry, so bring it back first.
+    let swapped_in = swap_in_page(&thr.proc_data, &mut aspace, vaddr)?;
+    // Pages whose soft-dirty bit was cleared are write-protected until the
+    // first write.
+    let dirtied = access_flags.contains(MappingFlags::WRITE)
+        && thr.proc_data.soft_dirty.lock().mark_dirty(
+            &mut aspace,
+            vaddr.align_down_4k(),
+            vaddr.align_down_4k() + PAGE_SIZE_4K,
+        )?;
+    // Both put pages back into the page table without their protection key.
+    if swapped_in || dirtied {
+        thr.proc_data.pkeys.lock().apply(
+            &thr.proc_data,
+            &mut aspace,
+            vaddr.align_down_4k(),
+            vaddr.align_down_4k() + PAGE_SIZE_4K,
+        );
+    }
+    // A page the PKRU of the thread denies access to is not to be populated
+    // or copied; the access fails.
+    if pkey_fault(&aspace, vaddr, access_flags).is_some() {
+        return Err(AxError::BadAddress);
+    }
+    if swapped_in {
+        thr.record_page_fault(true);
+        return if aspace
+            .find_area(vaddr)
+            .is_some_and(|area| area.flags().contains(access_flags))
+        {
+            Ok(())
+        } else {
+            Err(AxError::BadAddress)
+        };
+    }
+
+    let present = aspace
+        .page_table()
+        .query(vaddr)
+        .ok()
+        .map(|(_, flags, _)| flags);
+    if dirtied && present.is_some_and(|flags| flags.contains(access_flags)) {
+        thr.record_page_fault(false);
+        return Ok(());
+    }
+    // Populating a file mapping goes through the page cache and is counted as
+    // a major fault; anonymous memory and COW breaks are minor.
+    let major = present.is_none()
+        && aspace
+            .find_area(vaddr)
+            .is_some_and(|area| matches!(area.backend(), Backend::File(_)));
+    // Pages shared after fork are mapped read-only in writable areas, so a
+    // write fault on a present read-only page is a COW break, unless the page
+    // was only write-protected to track its soft-dirty bit.
+    let cow = access_flags.contains(MappingFlags::WRITE)
+        && !dirtied
+        && present.is_some_and(|flags| !flags.contains(MappingFlags::WRITE));
+    let anon = aspace
+        .find_area(vaddr)
+        .is_some_and(|area| matches!(area.backend(), Backend::Cow { .. }));
+    let permitted = aspace
+        .find_area(vaddr)
+        .is_some_and(|area| area.flags().contains(access_flags));
+    if permitted && present.is_none() && thr.proc_data.mapped_files.lock().beyond_eof(vaddr) {
+        return Err(AxError::OutOfRange);
+    }
+    let mut handled = aspace.handle_page_fault(vaddr, access_flags);
+    let mut split = false;
+    if !handled && permitted {
+        // No huge page could be allocated, fall back to base pages.
+        let mut thp_areas = thr.proc_data.thp.lock();
+        if thp_areas.is_huge(vaddr) && thp_areas.split(&mut aspace, vaddr).is_ok() {
+            split = true;
+            handled = aspace.handle_page_fault(vaddr, access_flags);
+        }
+    }
+    if handled {
+        let (start, end) = if split {
+            (
+                vaddr.align_down(HPAGE_SIZE),
+                vaddr.align_down(HPAGE_SIZE) + HPAGE_SIZE,
+            )
+        } else {
+            (vaddr.align_down_4k(), vaddr.align_down_4k() + PAGE_SIZE_4K)
+        };
+        thr.proc_data
+            .pkeys
+            .lock()
+            .apply(&thr.proc_data, &mut aspace, start, end);
+        thr.record_page_fault(major);
+        if anon {
+            touch_page(thr.proc_data.proc.pid(), vaddr);
+        }
+        if present.is_none()
+            && let Ok((_, _, size)) = aspace.page_table().query(vaddr)
+        {
+            thr.proc_data.add_rss(size as usize / PAGE_SIZE_4K);
+        }
+        // Anonymous memory and COW copies are backed by newly allocated pages.
+        if ((anon && present.is_none()) || cow)
+            && let Ok((_, _, size)) = aspace.page_table().query(vaddr)
+        {
+            count_vm_events(VmEvent::PageAlloc, size as usize / PAGE_SIZE_4K);
+            if size as usize > PAGE_SIZE_4K && thr.proc_data.thp.lock().is_huge(vaddr) {
+                count_vm_event(VmEvent::ThpFaultAlloc);
+            }
+        }
+        Ok(())
+    } else if permitted && !can_alloc_page() {
+        Err(AxError::NoMemory)
+    } else {
+        Err(AxError::BadAddress)
+    }
 }
 
 pub fn vm_load_string(ptr: *const c_char) -> AxResult<String> {
@@ -3,41 +3,70 @@ use core::sync::atomic::{AtomicBool, Ordering};
 use axerrno::AxResult;
 use axhal::uspace::UserContext;
 use axtask::current;
-use starry_core::task::{AsThread, Thread};
-use starry_signal::{SignalOSAction, SignalSet};
+use starry_core::task::{
+    AsThread, StopEvent, Thread, get_task, notify_parent_stop, signaled_status,
+};
+use starry_process::Pid;
+use starry_signal::{SignalOSAction, SignalSet, Signo};
 
-use crate::task::do_exit;
+use crate::{coredump::do_coredump, task::do_exit};
 
+/// Takes the next pending signal of `thr` and carries out its action.
+///
+/// Returns the action, or `None` if no signal was pending.
 pub fn check_signals(
     thr: &Thread,
     uctx: &mut UserContext,
     restore_blocked: Option<SignalSet>,
-) -> bool {
-    let Some((sig, os_action)) = thr.signal.check_signals(uctx, restore_blocked) else {
-        return false;
-    };
+) -> Option<SignalOSAction> {
+    let (sig, os_action) = thr.signal.check_signals(uctx, restore_blocked)?;
 
     let signo = sig.signo();
+    thr.proc_data.queued.remove(signo);
     match os_action {
         SignalOSAction::Terminate => {
-            do_exit(signo as i32, true);
+            do_exit(signaled_status(signo, false), true);
         }
         SignalOSAction::CoreDump => {
-            // TODO: implement core dump
-            do_exit(128 + signo as i32, true);
+            let core_dumped = do_coredump(thr, uctx, &sig);
+            do_exit(signaled_status(signo, core_dumped), true);
         }
         SignalOSAction::Stop => {
-            // TODO: implement stop
-            do_exit(1, true);
+            stop_process(thr, signo);
         }
         SignalOSAction::Continue => {
-            // TODO: implement continue
+            // The process was already continued when SIGCONT was sent.
         }
         SignalOSAction::Handler => {
             // do nothing
         }
     }
-    true
+    Some(os_action)
+}
+
+/// Stops the process of `thr` because of `signo` and waits to be continued.
+fn stop_process(thr: &Thread, signo: Signo) {
+    let proc_data = &thr.proc_data;
+    if proc_data.stop.stop(signo) {
+        notify_parent_stop(proc_data, StopEvent::Stopped(signo));
+        // Get the other threads out of blocking calls so they stop too.
+        let curr = current();
+        for tid in proc_data.proc.threads() {
+            if tid != curr.id().as_u64() as Pid
+                && let Ok(task) = get_task(tid)
+            {
+                task.interrupt();
+            }
+        }
+    }
+    proc_data.stop.wait_resumed();
+}
+
+/// Blocks the current thread while its process is stopped.
+///
+/// Returns whether it had to wait.
+pub fn wait_while_stopped(thr: &Thread) -> bool {
+    thr.proc_data.stop.wait_resumed()
 }
 
 static BLOCK_NEXT_SIGNAL_CHECK: AtomicBool = AtomicBool::new(false);
@@ -6,7 +6,7 @@ use core::{
 };
 
 use axerrno::{AxError, AxResult};
-use axfs_ng::{FS_CONTEXT, FileBackend, OpenOptions, OpenResult};
+use axfs_ng::{FS_CONTEXT, FileBackend, FileFlags, OpenOptions, OpenResult};
 use axfs_ng_vfs::{DirEntry, FileNode, Location, NodePermission, NodeType, Reference};
 use axtask::current;
 use bitflags::bitflags;
@@ -20,7 +20,7 @@ use crate::{
     },
     mm::{UserPtr, vm_load_string},
     syscall::sys::{sys_getegid, sys_geteuid},
-    vfs::dev::tty,
+    vfs::{add_file_seals, dev::tty, file_seals},
 };
 
 /// Convert open flags to [`OpenOptions`].
@@ -306,6 +306,29 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> AxResult<isize> {
                 .cloexec = cloexec;
             Ok(0)
         }
+        F_ADD_SEALS => {
+            const SEALS: u32 = F_SEAL_SEAL
+                | F_SEAL_SHRINK
+                | F_SEAL_GROW
+                | F_SEAL_WRITE
+                | F_SEAL_FUTURE_WRITE
+                | F_SEAL_EXEC;
+            let seals = arg as u32;
+            if seals & !SEALS != 0 {
+                return Err(AxError::InvalidInput);
+            }
+            let f = File::from_fd(fd).map_err(|_| AxError::InvalidInput)?;
+            let file = f.inner();
+            if file.access(FileFlags::WRITE).is_err() {
+                return Err(AxError::OperationNotPermitted);
+            }
+            add_file_seals(file.location(), seals)?;
+            Ok(0)
+        }
+        F_GET_SEALS => {
+            let f = File::from_fd(fd).map_err(|_| AxError::InvalidInput)?;
+            Ok(file_seals(f.inner().location())? as _)
+        }
         F_GETPIPE_SZ => {
             let pipe = Pipe::from_fd(fd)?;
             Ok(pipe.capacity() as _)
@@ -14,7 +14,7 @@ use starry_vm::{VmMutPtr, VmPtr};
 use syscalls::Sysno;
 
 use crate::{
-    file::{File, FileLike, Pipe, SealedBuf, SealedBufMut, get_file_like},
+    file::{File, FileLike, Pipe, SealedBuf, SealedBufMut, SecretMem, get_file_like},
     io::{IoVec, IoVectorBuf},
     mm::{UserConstPtr, VmBytes, VmBytesMut},
 };
@@ -126,6 +126,13 @@ pub fn sys_truncate(path: UserConstPtr<c_char>, length: __kernel_off_t) -> AxRes
 
 pub fn sys_ftruncate(fd: c_int, length: __kernel_off_t) -> AxResult<isize> {
     debug!("sys_ftruncate <= {fd} {length}");
+    if let Ok(secret) = SecretMem::from_fd(fd) {
+        if length < 0 {
+            return Err(AxError::InvalidInput);
+        }
+        secret.set_len(length as _)?;
+        return Ok(0);
+    }
     let f = File::from_fd(fd)?;
     f.inner().access(FileFlags::WRITE)?.set_len(length as _)?;
     Ok(0)
@@ -213,6 +220,7 @@ pub fn sys_pwrite64(
         return Ok(0);
     }
     let f = File::from_fd(fd)?;
+    f.check_seals(Some(offset as _), len)?;
     let write = f
         .inner()
         .write_at(&mut VmBytes::new(buf, len), offset as _)?;
@@ -296,6 +304,7 @@ impl SendFile {
             SendFile::Direct(file) => file.write(&mut buf.into()),
             SendFile::Offset(file, offset) => {
                 let off = offset.vm_read()?;
+                file.check_seals(Some(off), buf.len())?;
                 let bytes_written = file.inner().write_at(&mut buf, off)?;
                 offset.vm_write(off + bytes_written as u64)?;
                 Ok(bytes_written)
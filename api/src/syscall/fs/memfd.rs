@@ -3,30 +3,83 @@ use core::ffi::c_char;
 
 use axerrno::{AxError, AxResult};
 use axfs_ng::{FS_CONTEXT, OpenOptions};
-use linux_raw_sys::general::MFD_CLOEXEC;
+use linux_raw_sys::general::{
+    F_SEAL_EXEC, F_SEAL_SEAL, MFD_ALLOW_SEALING, MFD_CLOEXEC, MFD_EXEC, MFD_HUGE_MASK,
+    MFD_HUGE_SHIFT, MFD_HUGETLB, MFD_NOEXEC_SEAL, O_CLOEXEC,
+};
 
 use crate::{
-    file::{File, FileLike},
+    file::{File, FileLike, SecretMem, add_file_like},
     mm::UserConstPtr,
+    vfs::init_file_seals,
 };
 
-// TODO: correct memfd implementation
+/// The longest name a memfd can be given.
+const MFD_NAME_MAX_LEN: usize = 249;
+
+pub fn sys_memfd_create(name: UserConstPtr<c_char>, flags: u32) -> AxResult<isize> {
+    let name = name.get_as_str()?;
+    debug!("sys_memfd_create <= name: {name:?}, flags: {flags:#x}");
+    if name.len() > MFD_NAME_MAX_LEN {
+        return Err(AxError::InvalidInput);
+    }
+
+    let mut known = MFD_CLOEXEC | MFD_ALLOW_SEALING | MFD_HUGETLB | MFD_NOEXEC_SEAL | MFD_EXEC;
+    if flags & MFD_HUGETLB != 0 {
+        known |= MFD_HUGE_MASK << MFD_HUGE_SHIFT;
+    }
+    if flags & !known != 0 || flags & (MFD_NOEXEC_SEAL | MFD_EXEC) == MFD_NOEXEC_SEAL | MFD_EXEC {
+        return Err(AxError::InvalidInput);
+    }
+
+    // Huge page memfds live on the default hugetlbfs mount, so only its page
+    // size is available.
+    let dir = if flags & MFD_HUGETLB != 0 {
+        match (flags >> MFD_HUGE_SHIFT) & MFD_HUGE_MASK {
+            0 | 21 => "/dev/hugepages",
+            _ => return Err(AxError::InvalidInput),
+        }
+    } else {
+        "/tmp"
+    };
+    let mode = if flags & MFD_NOEXEC_SEAL != 0 {
+        0o666
+    } else {
+        0o777
+    };
+    // `MFD_NOEXEC_SEAL` implies `MFD_ALLOW_SEALING`.
+    let seals = if flags & MFD_NOEXEC_SEAL != 0 {
+        F_SEAL_EXEC
+    } else if flags & MFD_ALLOW_SEALING != 0 {
+        0
+    } else {
+        F_SEAL_SEAL
+    };
 
-pub fn sys_memfd_create(_name: UserConstPtr<c_char>, flags: u32) -> AxResult<isize> {
     // This is cursed
     for id in 0..0xffff {
-        let name = format!("/tmp/memfd-{id:04x}");
+        let name = format!("{dir}/memfd-{id:04x}");
         let fs = FS_CONTEXT.lock().clone();
         if fs.resolve(&name).is_err() {
             let file = OpenOptions::new()
                 .read(true)
                 .write(true)
                 .create(true)
+                .mode(mode)
                 .open(&fs, &name)?
                 .into_file()?;
+            init_file_seals(file.location(), seals)?;
             let cloexec = flags & MFD_CLOEXEC != 0;
             return File::new(file).add_to_fd_table(cloexec).map(|fd| fd as _);
         }
     }
     Err(AxError::TooManyOpenFiles)
 }
+
+pub fn sys_memfd_secret(flags: u32) -> AxResult<isize> {
+    debug!("sys_memfd_secret <= flags: {flags:#x}");
+    if flags & !O_CLOEXEC != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    add_file_like(SecretMem::new() as _, flags & O_CLOEXEC != 0).map(|fd| fd as _)
+}
@@ -2,6 +2,7 @@ use core::ffi::{c_char, c_void};
 
 use axerrno::{AxError, AxResult};
 use axfs_ng::FS_CONTEXT;
+use axhal::paging::PageSize;
 
 use crate::{mm::vm_load_string, vfs::MemoryFs};
 
@@ -10,18 +11,18 @@ pub fn sys_mount(
     target: *const c_char,
     fs_type: *const c_char,
     _flags: i32,
-    _data: *const c_void,
+    data: *const c_void,
 ) -> AxResult<isize> {
     let source = vm_load_string(source)?;
     let target = vm_load_string(target)?;
     let fs_type = vm_load_string(fs_type)?;
     debug!("sys_mount <= source: {source:?}, target: {target:?}, fs_type: {fs_type:?}");
 
-    if fs_type != "tmpfs" {
-        return Err(AxError::NoSuchDevice);
-    }
-
-    let fs = MemoryFs::new();
+    let fs = match fs_type.as_str() {
+        "tmpfs" => MemoryFs::new(),
+        "hugetlbfs" => MemoryFs::new_hugetlbfs(hugetlbfs_page_size(data)?),
+        _ => return Err(AxError::NoSuchDevice),
+    };
 
     let target = FS_CONTEXT.lock().resolve(target)?;
     target.mount(&fs)?;
@@ -29,6 +30,40 @@ pub fn sys_mount(
     Ok(0)
 }
 
+/// Parses the `pagesize=` option of a hugetlbfs mount, defaulting to 2M.
+fn hugetlbfs_page_size(data: *const c_void) -> AxResult<PageSize> {
+    let mut page_size = PageSize::Size2M;
+    if data.is_null() {
+        return Ok(page_size);
+    }
+    for option in vm_load_string(data.cast())?.split(',') {
+        let Some(value) = option.strip_prefix("pagesize=") else {
+            continue;
+        };
+        let (digits, unit) = value.split_at(
+            value
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(value.len()),
+        );
+        let shift = match unit {
+            "" => 0,
+            "k" | "K" => 10,
+            "m" | "M" => 20,
+            "g" | "G" => 30,
+            _ => return Err(AxError::InvalidInput),
+        };
+        let size = digits.parse::<usize>().map_err(|_| AxError::InvalidInput)? << shift;
+        page_size = if size == PageSize::Size2M as usize {
+            PageSize::Size2M
+        } else if size == PageSize::Size1G as usize {
+            PageSize::Size1G
+        } else {
+            return Err(AxError::InvalidInput);
+        };
+    }
+    Ok(page_size)
+}
+
 pub fn sys_umount2(target: *const c_char, _flags: i32) -> AxResult<isize> {
     let target = vm_load_string(target)?;
     debug!("sys_umount2 <= target: {target:?}");
@@ -9,8 +9,9 @@ use axmm::backend::{Backend, SharedPages};
 use axsync::Mutex;
 use axtask::current;
 use linux_raw_sys::general::*;
-use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use starry_core::{
+    mm::resident_pages_in,
     shm::{SHM_MANAGER, ShmInner, ShmidDs},
     task::AsThread,
 };
@@ -40,6 +41,13 @@ const IPC_SET: u32 = 1;
 
 const IPC_STAT: u32 = 2;
 
+/// flags for sys_shmget: segment will use huge pages
+const SHM_HUGETLB: usize = 0o4000;
+
+const SHM_HUGE_SHIFT: usize = 26;
+
+const SHM_HUGE_MASK: usize = 0x3f;
+
 pub fn sys_shmget(key: i32, size: usize, shmflg: usize) -> AxResult<isize> {
     let page_num = memory_addr::align_up_4k(size) / PAGE_SIZE_4K;
     if page_num == 0 {
@@ -73,13 +81,17 @@ pub fn sys_shmget(key: i32, size: usize, shmflg: usize) -> AxResult<isize> {
 
     // Create a new shm_inner
     let shmid = next_ipc_id();
-    let shm_inner = Arc::new(Mutex::new(ShmInner::new(
-        key,
-        shmid,
-        size,
-        mapping_flags,
-        cur_pid,
-    )));
+    let mut shm_inner = ShmInner::new(key, shmid, size, mapping_flags, cur_pid);
+    if shmflg & SHM_HUGETLB != 0 {
+        // The huge page size is encoded as its log2 above SHM_HUGE_SHIFT
+        let page_size = match (shmflg >> SHM_HUGE_SHIFT) & SHM_HUGE_MASK {
+            0 | 21 => PageSize::Size2M,
+            30 => PageSize::Size1G,
+            _ => return Err(AxError::InvalidInput),
+        };
+        shm_inner.use_huge_pages(page_size)?;
+    }
+    let shm_inner = Arc::new(Mutex::new(shm_inner));
     shm_manager.insert_key_shmid(key, shmid);
     shm_manager.insert_shmid_inner(shmid, shm_inner);
 
@@ -106,25 +118,29 @@ pub fn sys_shmat(shmid: i32, addr: usize, shmflg: u32) -> AxResult<isize> {
     let pid = proc_data.proc.pid();
     let mut aspace = proc_data.aspace.lock();
 
-    let start_aligned = memory_addr::align_down_4k(addr);
+    let page_size = shm_inner.page_size;
+    let start_aligned = addr.align_down(page_size);
     let length = shm_inner.page_num * PAGE_SIZE_4K;
+    // leave room to align the start address for huge pages
+    let search_length = length + (page_size as usize - PAGE_SIZE_4K);
 
     // alloc the virtual address range
     assert!(shm_inner.get_addr_range(pid).is_none());
     let start_addr = aspace
         .find_free_area(
             VirtAddr::from(start_aligned),
-            length,
+            search_length,
             VirtAddrRange::new(aspace.base(), aspace.end()),
         )
         .or_else(|| {
             aspace.find_free_area(
                 aspace.base(),
-                length,
+                search_length,
                 VirtAddrRange::new(aspace.base(), aspace.end()),
             )
         })
-        .ok_or(AxError::NoMemory)?;
+        .ok_or(AxError::NoMemory)?
+        .align_up(page_size);
     let end_addr = VirtAddr::from(start_addr.as_usize() + length);
     let va_range = VirtAddrRange::new(start_addr, end_addr);
 
@@ -141,12 +157,11 @@ pub fn sys_shmat(shmid: i32, addr: usize, shmflg: u32) -> AxResult<isize> {
     // map the virtual address range to the physical address
     if let Some(phys_pages) = shm_inner.phys_pages.clone() {
         // Another proccess has attached the shared memory
-        // TODO(mivik): shm page size
         let backend = Backend::new_shared(start_addr, phys_pages);
         aspace.map(start_addr, length, mapping_flags, false, backend)?;
     } else {
         // This is the first process to attach the shared memory
-        let pages = Arc::new(SharedPages::new(length, PageSize::Size4K)?);
+        let pages = Arc::new(SharedPages::new(length, page_size)?);
         let backend = Backend::new_shared(start_addr, pages.clone());
         aspace.map(start_addr, length, mapping_flags, false, backend)?;
 
@@ -220,7 +235,9 @@ pub fn sys_shmdt(shmaddr: usize) -> AxResult<isize> {
     let va_range = shm_inner.get_addr_range(pid).ok_or(AxError::InvalidInput)?;
 
     let mut aspace = proc_data.aspace.lock();
+    let resident = resident_pages_in(&aspace, va_range.start, va_range.end);
     aspace.unmap(va_range.start, va_range.size())?;
+    proc_data.sub_rss(resident);
 
     let mut shm_manager = SHM_MANAGER.lock();
     shm_manager.remove_shmaddr(pid, shmaddr);
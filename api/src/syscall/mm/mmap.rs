@@ -1,19 +1,30 @@
-use alloc::sync::Arc;
+use alloc::{string::ToString, sync::Arc, vec::Vec};
 
 use axerrno::{AxError, AxResult};
 use axfs_ng::FileBackend;
+use axfs_ng_vfs::{Location, NodeType};
 use axhal::paging::{MappingFlags, PageSize};
-use axmm::backend::{Backend, SharedPages};
+use axmm::{
+    AddrSpace,
+    backend::{Backend, SharedPages},
+};
 use axtask::current;
 use linux_raw_sys::general::*;
 use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange, align_up_4k};
 use starry_core::{
-    task::AsThread,
+    mm::{
+        HPAGE_SIZE, MappedFile, PKEY_DISABLE_ACCESS, PKEY_DISABLE_WRITE, ThpAreas, read_pkru,
+        resident_pages_in, swap_in_range, write_pkru,
+    },
+    task::{AsThread, ProcessData},
     vfs::{Device, DeviceMmap},
 };
 use starry_vm::{vm_load, vm_write_slice};
 
-use crate::file::{File, FileLike};
+use crate::{
+    file::{File, FileLike, SecretMem},
+    vfs::{add_shared_mapping, file_seals, hugetlbfs_page_size, hugetlbfs_pages},
+};
 
 bitflags::bitflags! {
     /// `PROT_*` flags for use with [`sys_mmap`].
@@ -100,7 +111,8 @@ pub fn sys_mmap(
     }
 
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
     let permission_flags = MmapProt::from_bits_truncate(prot);
     // TODO: check illegal flags for mmap
     let map_flags = match MmapFlags::from_bits(flags) {
@@ -132,15 +144,36 @@ pub fn sys_mmap(
     }
 
     info!(
-        "[MMAP] sys_mmap <= addr: {addr:#x?}, length: {length:#x?}, prot: {permission_flags:?}, flags: \
-         {map_flags:?}, fd: {fd:?}, offset: {offset:?}"
+        "[MMAP] sys_mmap <= addr: {addr:#x?}, length: {length:#x?}, prot: {permission_flags:?}, \
+         flags: {map_flags:?}, fd: {fd:?}, offset: {offset:?}"
     );
     debug!(
         "sys_mmap <= addr: {addr:#x?}, length: {length:#x?}, prot: {permission_flags:?}, flags: \
          {map_flags:?}, fd: {fd:?}, offset: {offset:?}"
     );
 
-    let page_size = if map_flags.contains(MmapFlags::HUGE_1GB) {
+    let secret = if fd > 0 {
+        SecretMem::from_fd(fd).ok()
+    } else {
+        None
+    };
+    let file = if fd > 0 && secret.is_none() {
+        Some(File::from_fd(fd)?)
+    } else {
+        None
+    };
+    // Files on a hugetlbfs are always mapped with the huge page size of the
+    // mount.
+    let hugetlbfs = file
+        .as_ref()
+        .and_then(|file| hugetlbfs_page_size(file.inner().location()));
+    if hugetlbfs.is_some_and(|page_size| !page_size.is_aligned(offset)) {
+        return Err(AxError::InvalidInput);
+    }
+
+    let page_size = if let Some(page_size) = hugetlbfs {
+        page_size
+    } else if map_flags.contains(MmapFlags::HUGE_1GB) {
         PageSize::Size1G
     } else if map_flags.contains(MmapFlags::HUGE) {
         PageSize::Size2M
@@ -152,34 +185,108 @@ pub fn sys_mmap(
     let end = (addr + length).align_up(page_size);
     let mut length = end - start;
 
+    // Private anonymous mappings large enough to hold a huge page are placed
+    // on a huge page boundary and backed by transparent huge pages.
+    let thp = map_type == MmapFlags::PRIVATE
+        && fd <= 0
+        && matches!(page_size, PageSize::Size4K)
+        && length >= HPAGE_SIZE;
+
     let start = if map_flags.intersects(MmapFlags::FIXED | MmapFlags::FIXED_NOREPLACE) {
         let dst_addr = VirtAddr::from(start);
         if !map_flags.contains(MmapFlags::FIXED_NOREPLACE) {
+            proc_data
+                .thp
+                .lock()
+                .split_boundaries(&mut aspace, dst_addr, dst_addr + length)?;
+            let resident = resident_pages_in(&aspace, dst_addr, dst_addr + length);
             aspace.unmap(dst_addr, length)?;
+            proc_data.sub_rss(resident);
+            // The state of a mapping replaced by `MAP_FIXED` goes with it.
+            proc_data.forget_range(&mut aspace, dst_addr, dst_addr + length);
         }
         dst_addr
     } else {
-        aspace
+        // Huge pages need a start address aligned to their size.
+        let align = if thp { HPAGE_SIZE } else { page_size as usize };
+        let search_length = if align > PageSize::Size4K as usize {
+            length + align
+        } else {
+            length
+        };
+        let free_area = aspace
             .find_free_area(
                 VirtAddr::from(start),
-                length,
+                search_length,
                 VirtAddrRange::new(aspace.base(), aspace.end()),
             )
             .or(aspace.find_free_area(
                 aspace.base(),
-                length,
+                search_length,
                 VirtAddrRange::new(aspace.base(), aspace.end()),
             ))
-            .ok_or(AxError::NoMemory)?
+            .ok_or(AxError::NoMemory)?;
+        free_area.align_up(align)
     };
 
-    let file = if fd > 0 {
-        Some(File::from_fd(fd)?)
+    if let Some(file) = &file
+        && map_type != MmapFlags::PRIVATE
+    {
+        // Memfds sealed against writes can't be mapped shared and writable.
+        add_shared_mapping(
+            file.inner().location(),
+            &proc_data.aspace,
+            start,
+            permission_flags.contains(MmapProt::WRITE),
+        )?;
+    }
+
+    let mapped_file = if let Some(file) = &file {
+        let loc = file.inner().location();
+        let metadata = loc.metadata()?;
+        Some(MappedFile {
+            path: loc.absolute_path()?.to_string(),
+            offset: offset as u64,
+            device: metadata.device,
+            inode: metadata.inode,
+            location: (metadata.node_type == NodeType::RegularFile).then(|| loc.clone()),
+        })
+    } else if secret.is_some() {
+        Some(MappedFile::anonymous("/secretmem (deleted)"))
+    } else if map_type != MmapFlags::PRIVATE && !matches!(page_size, PageSize::Size4K) {
+        Some(MappedFile::anonymous("/anon_hugepage (deleted)"))
+    } else if map_type != MmapFlags::PRIVATE {
+        Some(MappedFile::anonymous("/dev/zero (deleted)"))
     } else {
         None
     };
 
+    let mut secret_region = None;
     let backend = match map_type {
+        MmapFlags::SHARED | MmapFlags::SHARED_VALIDATE if secret.is_some() => {
+            // The pages are mapped by physical address and kept alive by the
+            // process, which can't be told apart from another process sharing
+            // the address space.
+            if Arc::strong_count(&proc_data.aspace) > 1 {
+                return Err(AxError::OperationNotSupported);
+            }
+            let region = secret.unwrap().region(offset, length)?;
+            let paddr = region.paddr() + offset;
+            secret_region = Some(region);
+            Backend::new_linear(start.as_usize() as isize - paddr.as_usize() as isize)
+        }
+        MmapFlags::PRIVATE if secret.is_some() => return Err(AxError::InvalidInput),
+        MmapFlags::SHARED | MmapFlags::SHARED_VALIDATE if hugetlbfs.is_some() => {
+            let file = file.ok_or(AxError::BadFileDescriptor)?;
+            let pages = hugetlbfs_pages(file.inner().location(), offset + length)?;
+            // The backend maps the page as far into the pages as the address
+            // is from its start, so starting early maps `offset` at `start`.
+            let base = start
+                .as_usize()
+                .checked_sub(offset)
+                .ok_or(AxError::InvalidInput)?;
+            Backend::new_shared(VirtAddr::from(base), pages)
+        }
         MmapFlags::SHARED | MmapFlags::SHARED_VALIDATE => {
             if let Some(file) = file {
                 let file = file.inner();
@@ -229,7 +336,7 @@ pub fn sys_mmap(
                     }
                 }
             } else {
-                Backend::new_shared(start, Arc::new(SharedPages::new(length, PageSize::Size4K)?))
+                Backend::new_shared(start, Arc::new(SharedPages::new(length, page_size)?))
             }
         }
         MmapFlags::PRIVATE => {
@@ -244,8 +351,60 @@ pub fn sys_mmap(
         _ => return Err(AxError::InvalidInput),
     };
 
+    // Anonymous hugetlb mappings take their pages from the huge page pool.
+    let hugetlb = fd <= 0 && !matches!(page_size, PageSize::Size4K);
+    if hugetlb {
+        proc_data.hugetlb.lock().reserve(
+            start,
+            start + length,
+            page_size,
+            map_type != MmapFlags::PRIVATE,
+        )?;
+    }
+
+    let mut thp_areas = proc_data.thp.lock();
     let populate = map_flags.contains(MmapFlags::POPULATE);
-    let result = aspace.map(start, length, permission_flags.into(), populate, backend);
+    let result = if thp {
+        map_anon_thp(
+            &mut aspace,
+            &mut thp_areas,
+            start,
+            length,
+            permission_flags.into(),
+            populate,
+        )
+    } else {
+        aspace.map(start, length, permission_flags.into(), populate, backend)
+    };
+    if result.is_ok() && map_type == MmapFlags::PRIVATE && fd <= 0 {
+        thp_areas.add_anon(start, start + length);
+    }
+    if result.is_err() && hugetlb {
+        proc_data.hugetlb.lock().remove(start, start + length);
+    }
+    if let Some(mapped_file) = mapped_file
+        && result.is_ok()
+    {
+        proc_data
+            .mapped_files
+            .lock()
+            .insert(start, start + length, mapped_file);
+    }
+    if let Some(region) = secret_region
+        && result.is_ok()
+    {
+        proc_data
+            .secret
+            .lock()
+            .insert(start, start + length, region);
+    }
+
+    if result.is_ok() {
+        proc_data.update_vm_peak(aspace.areas().map(|area| area.size()).sum());
+        if populate {
+            proc_data.add_rss(resident_pages_in(&aspace, start, start + length));
+        }
+    }
 
     match &result {
         Ok(_) => info!("[MMAP] mmap SUCCESS: addr={:#x}", start.as_usize()),
@@ -256,36 +415,213 @@ pub fn sys_mmap(
     Ok(start.as_usize() as _)
 }
 
+/// Maps private anonymous memory, using huge pages for the part of the range
+/// that covers whole, aligned huge pages.
+fn map_anon_thp(
+    aspace: &mut AddrSpace,
+    thp_areas: &mut ThpAreas,
+    start: VirtAddr,
+    length: usize,
+    flags: MappingFlags,
+    populate: bool,
+) -> AxResult {
+    let end = start + length;
+    let huge_start = start.align_up(HPAGE_SIZE);
+    let huge_end = end.align_down(HPAGE_SIZE);
+    if huge_start >= huge_end {
+        return aspace.map(
+            start,
+            length,
+            flags,
+            populate,
+            Backend::new_alloc(start, PageSize::Size4K),
+        );
+    }
+
+    for (seg_start, seg_end, page_size) in [
+        (start, huge_start, PageSize::Size4K),
+        (huge_start, huge_end, PageSize::Size2M),
+        (huge_end, end, PageSize::Size4K),
+    ] {
+        if seg_start < seg_end {
+            aspace.map(
+                seg_start,
+                seg_end - seg_start,
+                flags,
+                populate,
+                Backend::new_alloc(seg_start, page_size),
+            )?;
+        }
+    }
+    thp_areas.add_huge(huge_start, huge_end);
+    Ok(())
+}
+
 pub fn sys_munmap(addr: usize, length: usize) -> AxResult<isize> {
     debug!("sys_munmap <= addr: {addr:#x}, length: {length:x}");
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
     let length = align_up_4k(length);
     let start_addr = VirtAddr::from(addr);
+    proc_data
+        .thp
+        .lock()
+        .split_boundaries(&mut aspace, start_addr, start_addr + length)?;
+    let resident = resident_pages_in(&aspace, start_addr, start_addr + length);
     aspace.unmap(start_addr, length)?;
+    proc_data.sub_rss(resident);
+    proc_data.forget_range(&mut aspace, start_addr, start_addr + length);
     Ok(0)
 }
 
 pub fn sys_mprotect(addr: usize, length: usize, prot: u32) -> AxResult<isize> {
+    do_mprotect(addr, length, prot, -1)
+}
+
+pub fn sys_pkey_mprotect(addr: usize, length: usize, prot: u32, pkey: i32) -> AxResult<isize> {
+    do_mprotect(addr, length, prot, pkey)
+}
+
+/// Changes the permissions of `[addr, addr + length)` and, unless `pkey` is
+/// -1, its protection key.
+fn do_mprotect(addr: usize, length: usize, prot: u32, pkey: i32) -> AxResult<isize> {
     // TODO: implement PROT_GROWSUP & PROT_GROWSDOWN
     let Some(permission_flags) = MmapProt::from_bits(prot) else {
         return Err(AxError::InvalidInput);
     };
-    debug!("sys_mprotect <= addr: {addr:#x}, length: {length:x}, prot: {permission_flags:?}");
+    debug!(
+        "sys_mprotect <= addr: {addr:#x}, length: {length:x}, prot: {permission_flags:?}, pkey: \
+         {pkey}"
+    );
 
     if permission_flags.contains(MmapProt::GROWDOWN | MmapProt::GROWSUP) {
         return Err(AxError::InvalidInput);
     }
 
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    if pkey != -1 && !proc_data.pkeys.lock().is_allocated(pkey) {
+        return Err(AxError::InvalidInput);
+    }
+
+    let mut aspace = proc_data.aspace.lock();
     let length = align_up_4k(length);
     let start_addr = VirtAddr::from(addr);
+    let writable = permission_flags.contains(MmapProt::WRITE);
+    // Memfds sealed against writes can't be made writable through their
+    // shared mappings either.
+    if writable
+        && shared_files(proc_data, &aspace, start_addr, start_addr + length)
+            .into_iter()
+            .any(|(_, _, loc)| {
+                file_seals(&loc)
+                    .is_ok_and(|seals| seals & (F_SEAL_WRITE | F_SEAL_FUTURE_WRITE) != 0)
+            })
+    {
+        return Err(AxError::PermissionDenied);
+    }
+    proc_data
+        .thp
+        .lock()
+        .split_boundaries(&mut aspace, start_addr, start_addr + length)?;
     aspace.protect(start_addr, length, permission_flags.into())?;
+    if writable {
+        for (area_start, _, loc) in
+            shared_files(proc_data, &aspace, start_addr, start_addr + length)
+        {
+            add_shared_mapping(&loc, &proc_data.aspace, area_start, true)?;
+        }
+    }
+    proc_data
+        .swap
+        .lock()
+        .protect(start_addr, start_addr + length, permission_flags.into());
+    proc_data
+        .soft_dirty
+        .lock()
+        .protect(proc_data, &mut aspace, start_addr, start_addr + length)?;
+    // Changing the permissions, and splitting huge pages at the ends of the
+    // range, rewrote page table entries and dropped their keys.
+    let mut pkeys = proc_data.pkeys.lock();
+    if pkey != -1 {
+        pkeys.set(
+            proc_data,
+            &mut aspace,
+            start_addr,
+            start_addr + length,
+            pkey as u8,
+        );
+    }
+    pkeys.apply(
+        proc_data,
+        &mut aspace,
+        start_addr.align_down(HPAGE_SIZE),
+        (start_addr + length).align_up(HPAGE_SIZE),
+    );
 
     Ok(0)
 }
 
+/// Returns the regular files mapped shared within `[start, end)` of
+/// `aspace`, the address space of `proc_data`, along with the start of each
+/// area mapping them and whether it is writable.
+fn shared_files(
+    proc_data: &ProcessData,
+    aspace: &AddrSpace,
+    start: VirtAddr,
+    end: VirtAddr,
+) -> Vec<(VirtAddr, bool, Location)> {
+    let files = proc_data.mapped_files.lock();
+    aspace
+        .areas()
+        .filter(|area| area.start() < end && area.end() > start)
+        .filter(|area| matches!(area.backend(), Backend::File(_) | Backend::Shared(_)))
+        .filter_map(|area| {
+            let loc = files.find(area.start())?.location?;
+            Some((
+                area.start(),
+                area.flags().contains(MappingFlags::WRITE),
+                loc,
+            ))
+        })
+        .collect()
+}
+
+/// Records the writable shared mappings of memfds that `proc_data` inherited
+/// with its copy of the address space, so that sealing the files against
+/// writes sees them.
+pub fn add_inherited_mappings(proc_data: &ProcessData) -> AxResult {
+    let aspace = proc_data.aspace.lock();
+    for (start, writable, loc) in shared_files(proc_data, &aspace, aspace.base(), aspace.end()) {
+        if writable {
+            add_shared_mapping(&loc, &proc_data.aspace, start, true)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn sys_pkey_alloc(flags: u32, access_rights: u32) -> AxResult<isize> {
+    debug!("sys_pkey_alloc <= flags: {flags:#x}, access_rights: {access_rights:#x}");
+    if flags != 0 || access_rights & !(PKEY_DISABLE_ACCESS | PKEY_DISABLE_WRITE) != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let pkey = current().as_thread().proc_data.pkeys.lock().alloc()?;
+    // Only the calling thread gets the initial rights; other threads keep
+    // whatever their PKRU says about the key.
+    if let Some(pkru) = read_pkru() {
+        let shift = 2 * pkey;
+        write_pkru((pkru & !(0b11 << shift)) | (access_rights << shift));
+    }
+    Ok(pkey as _)
+}
+
+pub fn sys_pkey_free(pkey: i32) -> AxResult<isize> {
+    debug!("sys_pkey_free <= pkey: {pkey}");
+    current().as_thread().proc_data.pkeys.lock().free(pkey)?;
+    Ok(0)
+}
+
 pub fn sys_mremap(addr: usize, old_size: usize, new_size: usize, flags: u32) -> AxResult<isize> {
     debug!(
         "sys_mremap <= addr: {addr:#x}, old_size: {old_size:x}, new_size: {new_size:x}, flags: \
@@ -300,11 +636,18 @@ pub fn sys_mremap(addr: usize, old_size: usize, new_size: usize, flags: u32) ->
     let addr = VirtAddr::from(addr);
 
     let curr = current();
-    let aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    let aspace = proc_data.aspace.lock();
     let old_size = align_up_4k(old_size);
     let new_size = align_up_4k(new_size);
+    // Moving is done by copying into anonymous memory, which would leak the
+    // contents of secret memory.
+    if proc_data.secret.lock().overlaps(addr, old_size) {
+        return Err(AxError::InvalidInput);
+    }
 
     let flags = aspace.find_area(addr).ok_or(AxError::NoMemory)?.flags();
+    let pkey = proc_data.pkeys.lock().key(addr);
     drop(aspace);
     let new_addr = sys_mmap(
         addr.as_usize(),
@@ -321,11 +664,55 @@ pub fn sys_mremap(addr: usize, old_size: usize, new_size: usize, flags: u32) ->
 
     sys_munmap(addr.as_usize(), old_size)?;
 
+    // The new mapping starts out with the default key.
+    if pkey != 0 {
+        let new_start = VirtAddr::from(new_addr);
+        let mut aspace = proc_data.aspace.lock();
+        proc_data.pkeys.lock().set(
+            proc_data,
+            &mut aspace,
+            new_start,
+            new_start + new_size,
+            pkey,
+        );
+    }
+
     Ok(new_addr as isize)
 }
 
 pub fn sys_madvise(addr: usize, length: usize, advice: i32) -> AxResult<isize> {
     debug!("sys_madvise <= addr: {addr:#x}, length: {length:x}, advice: {advice:#x}");
+
+    match advice as u32 {
+        MADV_HUGEPAGE | MADV_NOHUGEPAGE => {
+            if addr % PageSize::Size4K as usize != 0 {
+                return Err(AxError::InvalidInput);
+            }
+            let start = VirtAddr::from(addr);
+            let end = start + align_up_4k(length);
+
+            let curr = current();
+            let proc_data = &curr.as_thread().proc_data;
+            let mut aspace = proc_data.aspace.lock();
+            let mut thp_areas = proc_data.thp.lock();
+            if advice as u32 == MADV_HUGEPAGE {
+                swap_in_range(proc_data, &mut aspace, start, end - start)?;
+                thp_areas.collapse_range(&mut aspace, start, end)?;
+                // Collapsing fills in the base pages that were missing.
+                proc_data.sync_rss(&aspace);
+            } else {
+                thp_areas.split_range(&mut aspace, start, end)?;
+            }
+            // Collapsing and splitting remap the pages without their keys.
+            proc_data.pkeys.lock().apply(
+                proc_data,
+                &mut aspace,
+                start.align_down(HPAGE_SIZE),
+                end.align_up(HPAGE_SIZE),
+            );
+        }
+        _ => {}
+    }
     Ok(0)
 }
 
@@ -1,4 +1,7 @@
 mod brk;
 mod mmap;
+mod process_vm;
+mod swap;
+mod userfaultfd;
 
-pub use self::{brk::*, mmap::*};
+pub use self::{brk::*, mmap::*, process_vm::*, swap::*, userfaultfd::*};
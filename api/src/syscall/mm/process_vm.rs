@@ -0,0 +1,123 @@
+use alloc::{vec, vec::Vec};
+
+use axerrno::{AxError, AxResult};
+use axio::{Buf, BufMut, Read, Write};
+use memory_addr::{PAGE_SIZE_4K, VirtAddr};
+use starry_core::{
+    mm::{may_access_memory, read_process_memory, write_process_memory},
+    task::get_process_data,
+};
+use starry_process::Pid;
+use starry_vm::VmPtr;
+
+use crate::io::{IoVec, IoVectorBuf};
+
+/// The maximum number of iovecs in a single call
+const IOV_MAX: usize = 1024;
+
+fn load_remote_iovecs(iov: *const IoVec, iovcnt: usize) -> AxResult<Vec<IoVec>> {
+    if iovcnt > IOV_MAX {
+        return Err(AxError::InvalidInput);
+    }
+    (0..iovcnt)
+        .map(|i| {
+            let iov = iov.wrapping_add(i).vm_read()?;
+            if iov.iov_len < 0 {
+                return Err(AxError::InvalidInput);
+            }
+            Ok(iov)
+        })
+        .collect()
+}
+
+/// Copies between the local iovecs and the remote iovecs of process `pid`,
+/// stopping at the first remote or local address that can't be accessed.
+fn process_vm_rw(
+    pid: Pid,
+    local_iov: *const IoVec,
+    liovcnt: usize,
+    remote_iov: *const IoVec,
+    riovcnt: usize,
+    flags: usize,
+    write: bool,
+) -> AxResult<isize> {
+    if flags != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let mut local = IoVectorBuf::new(local_iov, liovcnt)?.into_io();
+    let remote = load_remote_iovecs(remote_iov, riovcnt)?;
+    if pid == 0 {
+        return Err(AxError::NoSuchProcess);
+    }
+    let proc_data = get_process_data(pid)?;
+    may_access_memory(&proc_data)?;
+
+    let mut buf = vec![0; PAGE_SIZE_4K];
+    let mut count = 0;
+    'outer: for iov in remote {
+        let mut offset = 0;
+        while offset < iov.iov_len as usize {
+            let local_len = if write {
+                local.remaining()
+            } else {
+                local.remaining_mut()
+            };
+            let len = (iov.iov_len as usize - offset)
+                .min(buf.len())
+                .min(local_len);
+            if len == 0 {
+                break 'outer;
+            }
+            let addr = VirtAddr::from(iov.iov_base.wrapping_add(offset) as usize);
+            let result = if write {
+                local.read(&mut buf[..len]).and_then(|read| {
+                    write_process_memory(&proc_data, addr, &buf[..read], false).map(|n| (read, n))
+                })
+            } else {
+                read_process_memory(&proc_data, addr, &mut buf[..len])
+                    .and_then(|read| local.write(&buf[..read]).map(|n| (read, n)))
+            };
+            let (moved, done) = match result {
+                Ok(result) => result,
+                Err(err) if count == 0 => return Err(err),
+                Err(_) => break 'outer,
+            };
+            count += done;
+            offset += done;
+            if done < moved || moved < len {
+                break 'outer;
+            }
+        }
+    }
+    Ok(count as _)
+}
+
+pub fn sys_process_vm_readv(
+    pid: Pid,
+    local_iov: *const IoVec,
+    liovcnt: usize,
+    remote_iov: *const IoVec,
+    riovcnt: usize,
+    flags: usize,
+) -> AxResult<isize> {
+    debug!(
+        "sys_process_vm_readv <= pid: {pid}, liovcnt: {liovcnt}, riovcnt: {riovcnt}, flags: \
+         {flags:#x}"
+    );
+    process_vm_rw(pid, local_iov, liovcnt, remote_iov, riovcnt, flags, false)
+}
+
+pub fn sys_process_vm_writev(
+    pid: Pid,
+    local_iov: *const IoVec,
+    liovcnt: usize,
+    remote_iov: *const IoVec,
+    riovcnt: usize,
+    flags: usize,
+) -> AxResult<isize> {
+    debug!(
+        "sys_process_vm_writev <= pid: {pid}, liovcnt: {liovcnt}, riovcnt: {riovcnt}, flags: \
+         {flags:#x}"
+    );
+    process_vm_rw(pid, local_iov, liovcnt, remote_iov, riovcnt, flags, true)
+}
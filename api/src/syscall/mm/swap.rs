@@ -0,0 +1,42 @@
+use alloc::string::ToString;
+use core::ffi::c_char;
+
+use axerrno::AxResult;
+use axfs_ng::{FS_CONTEXT, OpenOptions};
+use starry_core::mm::{swapoff, swapon};
+
+use crate::mm::vm_load_string;
+
+/// flags for sys_swapon: use the priority in the low bits
+const SWAP_FLAG_PREFER: u32 = 0x8000;
+
+const SWAP_FLAG_PRIO_MASK: u32 = 0x7fff;
+
+pub fn sys_swapon(path: *const c_char, flags: i32) -> AxResult<isize> {
+    let path = vm_load_string(path)?;
+    debug!("sys_swapon <= path: {path:?}, flags: {flags:#x}");
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&FS_CONTEXT.lock(), &path)?
+        .into_file()?;
+    let path = file.location().absolute_path()?.to_string();
+    let flags = flags as u32;
+    let priority = (flags & SWAP_FLAG_PREFER != 0).then(|| (flags & SWAP_FLAG_PRIO_MASK) as i32);
+    swapon(file.backend()?.clone(), path, priority)?;
+    Ok(0)
+}
+
+pub fn sys_swapoff(path: *const c_char) -> AxResult<isize> {
+    let path = vm_load_string(path)?;
+    debug!("sys_swapoff <= path: {path:?}");
+
+    let path = FS_CONTEXT
+        .lock()
+        .resolve(&path)?
+        .absolute_path()?
+        .to_string();
+    swapoff(&path)?;
+    Ok(0)
+}
@@ -0,0 +1,32 @@
+use axerrno::{AxError, AxResult};
+use axtask::current;
+use bitflags::bitflags;
+use linux_raw_sys::general::{O_CLOEXEC, O_NONBLOCK};
+use starry_core::task::AsThread;
+
+use crate::file::{FileLike, add_file_like, userfaultfd::UserFaultFd};
+
+bitflags! {
+    /// Flags for the `userfaultfd` syscall.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct UserFaultFdFlags: u32 {
+        /// Create a file descriptor that is closed on `exec`.
+        const CLOEXEC = O_CLOEXEC;
+        /// Create a non-blocking userfaultfd.
+        const NONBLOCK = O_NONBLOCK;
+        /// Only handle faults from user space.
+        const USER_MODE_ONLY = 1;
+    }
+}
+
+pub fn sys_userfaultfd(flags: u32) -> AxResult<isize> {
+    debug!("sys_userfaultfd <= flags: {flags:#x}");
+
+    // `USER_MODE_ONLY` is accepted but not enforced: pages the kernel is about
+    // to access are still reported to user space.
+    let flags = UserFaultFdFlags::from_bits(flags).ok_or(AxError::InvalidInput)?;
+
+    let uffd = UserFaultFd::new(&current().as_thread().proc_data);
+    uffd.set_nonblocking(flags.contains(UserFaultFdFlags::NONBLOCK))?;
+    add_file_like(uffd as _, flags.contains(UserFaultFdFlags::CLOEXEC)).map(|fd| fd as _)
+}
@@ -12,6 +12,9 @@ mod time;
 
 use axerrno::{AxError, LinuxError};
 use axhal::uspace::UserContext;
+use linux_raw_sys::general::{
+    FUTEX_CMD_MASK, FUTEX_LOCK_PI, FUTEX_LOCK_PI2, FUTEX_WAIT, FUTEX_WAIT_BITSET,
+};
 use syscalls::Sysno;
 
 use self::{
@@ -296,6 +299,7 @@ pub fn handle_syscall(uctx: &mut UserContext) {
 
         // memfd
         Sysno::memfd_create => sys_memfd_create(uctx.arg0().into(), uctx.arg1() as _),
+        Sysno::memfd_secret => sys_memfd_secret(uctx.arg0() as _),
 
         // fs stat
         #[cfg(target_arch = "x86_64")]
@@ -347,6 +351,14 @@ pub fn handle_syscall(uctx: &mut UserContext) {
         ),
         Sysno::munmap => sys_munmap(uctx.arg0(), uctx.arg1() as _),
         Sysno::mprotect => sys_mprotect(uctx.arg0(), uctx.arg1() as _, uctx.arg2() as _),
+        Sysno::pkey_mprotect => sys_pkey_mprotect(
+            uctx.arg0(),
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+        ),
+        Sysno::pkey_alloc => sys_pkey_alloc(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::pkey_free => sys_pkey_free(uctx.arg0() as _),
         Sysno::mremap => sys_mremap(
             uctx.arg0(),
             uctx.arg1() as _,
@@ -357,6 +369,25 @@ pub fn handle_syscall(uctx: &mut UserContext) {
         Sysno::msync => sys_msync(uctx.arg0(), uctx.arg1() as _, uctx.arg2() as _),
         Sysno::mlock => sys_mlock(uctx.arg0(), uctx.arg1() as _),
         Sysno::mlock2 => sys_mlock2(uctx.arg0(), uctx.arg1() as _, uctx.arg2() as _),
+        Sysno::swapon => sys_swapon(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::swapoff => sys_swapoff(uctx.arg0() as _),
+        Sysno::userfaultfd => sys_userfaultfd(uctx.arg0() as _),
+        Sysno::process_vm_readv => sys_process_vm_readv(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+            uctx.arg4() as _,
+            uctx.arg5() as _,
+        ),
+        Sysno::process_vm_writev => sys_process_vm_writev(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+            uctx.arg4() as _,
+            uctx.arg5() as _,
+        ),
 
         // task info
         Sysno::getpid => sys_getpid(),
@@ -431,9 +462,22 @@ pub fn handle_syscall(uctx: &mut UserContext) {
         Sysno::fork => sys_fork(uctx),
         Sysno::exit => sys_exit(uctx.arg0() as _),
         Sysno::exit_group => sys_exit_group(uctx.arg0() as _),
-        Sysno::wait4 => sys_waitpid(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
+        Sysno::wait4 => sys_waitpid(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+        ),
+        Sysno::waitid => sys_waitid(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+            uctx.arg4() as _,
+        ),
         Sysno::getsid => sys_getsid(uctx.arg0() as _),
         Sysno::setsid => sys_setsid(),
+        Sysno::vhangup => sys_vhangup(),
         Sysno::getpgid => sys_getpgid(uctx.arg0() as _),
         Sysno::setpgid => sys_setpgid(uctx.arg0() as _, uctx.arg1() as _),
 
@@ -485,6 +529,13 @@ pub fn handle_syscall(uctx: &mut UserContext) {
             uctx.arg4() as _,
             uctx.arg5() as _,
         ),
+        Sysno::futex_waitv => sys_futex_waitv(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+            uctx.arg4() as _,
+        ),
         Sysno::get_robust_list => {
             sys_get_robust_list(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _)
         }
@@ -594,14 +645,12 @@ pub fn handle_syscall(uctx: &mut UserContext) {
         Sysno::timerfd_create
         | Sysno::fanotify_init
         | Sysno::inotify_init1
-        | Sysno::userfaultfd
         | Sysno::perf_event_open
         | Sysno::io_uring_setup
         | Sysno::bpf
         | Sysno::fsopen
         | Sysno::fspick
-        | Sysno::open_tree
-        | Sysno::memfd_secret => sys_dummy_fd(sysno),
+        | Sysno::open_tree => sys_dummy_fd(sysno),
 
         Sysno::timer_create | Sysno::timer_gettime | Sysno::timer_settime => Ok(0),
 
@@ -614,3 +663,50 @@ pub fn handle_syscall(uctx: &mut UserContext) {
 
     uctx.set_retval(result.unwrap_or_else(|err| -LinuxError::from(err).code() as _) as _);
 }
+
+/// Returns whether the system call `uctx` is about to make is made again when
+/// a signal that runs no handler, such as one that stops the process,
+/// interrupts it, as Linux does for calls returning `ERESTARTSYS`.
+///
+/// Calls that return a plain `EINTR`, and waits with a relative timeout,
+/// which Linux resumes with the remaining time through `restart_syscall(2)`,
+/// fail with `EINTR` instead.
+pub fn is_restartable(uctx: &UserContext) -> bool {
+    let Some(sysno) = Sysno::new(uctx.sysno()) else {
+        return false;
+    };
+    match sysno {
+        Sysno::read
+        | Sysno::readv
+        | Sysno::pread64
+        | Sysno::preadv
+        | Sysno::preadv2
+        | Sysno::write
+        | Sysno::writev
+        | Sysno::pwrite64
+        | Sysno::pwritev
+        | Sysno::pwritev2
+        | Sysno::openat
+        | Sysno::ioctl
+        | Sysno::fcntl
+        | Sysno::flock
+        | Sysno::wait4
+        | Sysno::waitid
+        | Sysno::accept
+        | Sysno::accept4
+        | Sysno::connect
+        | Sysno::recvfrom
+        | Sysno::recvmsg
+        | Sysno::sendto
+        | Sysno::sendmsg => true,
+        #[cfg(target_arch = "x86_64")]
+        Sysno::open => true,
+        Sysno::futex => match uctx.arg1() as u32 & FUTEX_CMD_MASK {
+            FUTEX_WAIT | FUTEX_WAIT_BITSET => uctx.arg3() == 0,
+            // The timeout is absolute.
+            FUTEX_LOCK_PI | FUTEX_LOCK_PI2 => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
@@ -158,6 +158,11 @@ pub fn sys_tgkill(tgid: Pid, tid: Pid, signo: u32) -> AxResult<isize> {
     Ok(0)
 }
 
+/// Reads the `siginfo_t` user space supplies to queue `signo` for the
+/// process `tgid`.
+///
+/// Only a process signalling itself may use the `si_code` of the kernel or of
+/// `kill` and `tgkill`, since those vouch for the sender.
 pub(crate) fn make_queue_signal_info(
     tgid: Pid,
     signo: u32,
@@ -241,9 +246,10 @@ pub fn sys_rt_sigtimedwait(
     uctx.set_retval(-LinuxError::EINTR.code() as usize);
     let fut = poll_fn(|context| {
         if let Some(sig) = signal.dequeue_signal(&set) {
+            thr.proc_data.queued.remove(sig.signo());
             signal.set_blocked(old_blocked);
             Poll::Ready(Some(sig))
-        } else if check_signals(thr, uctx, Some(old_blocked)) {
+        } else if check_signals(thr, uctx, Some(old_blocked)).is_some() {
             Poll::Ready(None)
         } else {
             curr.on_interrupt(context.waker());
@@ -284,7 +290,7 @@ pub fn sys_rt_sigsuspend(
     uctx.set_retval(-LinuxError::EINTR.code() as usize);
 
     block_on(poll_fn(|context| {
-        if check_signals(thr, uctx, Some(old_blocked)) {
+        if check_signals(thr, uctx, Some(old_blocked)).is_some() {
             return Poll::Ready(());
         }
         curr.on_interrupt(context.waker());
@@ -1,18 +1,29 @@
-use core::sync::atomic::Ordering;
+use alloc::{collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    sync::atomic::{AtomicU32, Ordering},
+    task::Waker,
+};
 
 use axerrno::{AxError, AxResult, LinuxError};
-use axtask::current;
+use axhal::time::{TimeValue, monotonic_time, wall_time};
+use axtask::{AxTaskRef, current};
 use linux_raw_sys::general::{
-    FUTEX_CMD_MASK, FUTEX_CMP_REQUEUE, FUTEX_REQUEUE, FUTEX_WAIT, FUTEX_WAIT_BITSET, FUTEX_WAKE,
-    FUTEX_WAKE_BITSET, robust_list_head, timespec,
+    __kernel_clockid_t, CLOCK_MONOTONIC, CLOCK_REALTIME, FUTEX_CLOCK_REALTIME, FUTEX_CMD_MASK,
+    FUTEX_CMP_REQUEUE, FUTEX_CMP_REQUEUE_PI, FUTEX_LOCK_PI, FUTEX_LOCK_PI2, FUTEX_OP_ADD,
+    FUTEX_OP_ANDN, FUTEX_OP_CMP_EQ, FUTEX_OP_CMP_GE, FUTEX_OP_CMP_GT, FUTEX_OP_CMP_LE,
+    FUTEX_OP_CMP_LT, FUTEX_OP_CMP_NE, FUTEX_OP_OPARG_SHIFT, FUTEX_OP_OR, FUTEX_OP_SET,
+    FUTEX_OP_XOR, FUTEX_OWNER_DIED, FUTEX_REQUEUE, FUTEX_TID_MASK, FUTEX_TRYLOCK_PI,
+    FUTEX_UNLOCK_PI, FUTEX_WAIT, FUTEX_WAIT_BITSET, FUTEX_WAIT_REQUEUE_PI, FUTEX_WAITERS,
+    FUTEX_WAITV_MAX, FUTEX_WAKE, FUTEX_WAKE_BITSET, FUTEX_WAKE_OP, FUTEX2_PRIVATE, FUTEX2_SIZE_U32,
+    futex_waitv, robust_list_head, timespec,
 };
 use starry_core::{
-    futex::FutexKey,
+    futex::{FutexKey, WaitQueue},
     task::{AsThread, get_task},
 };
 use starry_vm::{VmMutPtr, VmPtr};
 
-use crate::time::TimeValueLike;
+use crate::{mm::UserPtr, time::TimeValueLike};
 
 fn assert_unsigned(value: u32) -> AxResult<u32> {
     if (value as i32) < 0 {
@@ -22,6 +33,257 @@ fn assert_unsigned(value: u32) -> AxResult<u32> {
     }
 }
 
+/// Reads the absolute timeout of a priority-inheritance operation, measured
+/// by `CLOCK_REALTIME` if `realtime` and by `CLOCK_MONOTONIC` otherwise, and
+/// turns it into a deadline on the monotonic clock.
+fn read_deadline(timeout: *const timespec, realtime: bool) -> AxResult<Option<TimeValue>> {
+    let Some(ts) = timeout.nullable() else {
+        return Ok(None);
+    };
+    // FIXME: AnyBitPattern
+    let ts = unsafe { ts.vm_read_uninit()?.assume_init() }.try_into_time_value()?;
+    Ok(Some(if realtime {
+        monotonic_time() + ts.saturating_sub(wall_time())
+    } else {
+        ts
+    }))
+}
+
+/// Returns the time left until `deadline`.
+fn time_left(deadline: Option<TimeValue>) -> Option<TimeValue> {
+    deadline.map(|deadline| deadline.saturating_sub(monotonic_time()))
+}
+
+/// The longest chain of owners waiting for each other that a priority is lent
+/// down, as Linux's default `max_lock_depth`
+const PI_CHAIN_MAX: usize = 1024;
+
+/// Lends the priority of the current thread to the owner of a
+/// priority-inheritance futex while it waits for the futex, and on to the
+/// owners of the futexes that owner waits for in turn.
+struct PriorityBoost(Vec<AxTaskRef>);
+
+impl PriorityBoost {
+    /// Fails with `EDEADLK` if the chain of owners leads back to the current
+    /// thread.
+    fn new(owner: AxTaskRef) -> AxResult<Self> {
+        let curr = current();
+        let owner_tid = owner.id().as_u64() as u32;
+        let mut boost = Self(Vec::new());
+        let mut next = Some(owner);
+        while let Some(task) = next.take() {
+            if task.id() == curr.id() || boost.0.len() >= PI_CHAIN_MAX {
+                return Err(AxError::from(LinuxError::EDEADLK));
+            }
+            let Some(thr) = task.try_as_thread() else {
+                break;
+            };
+            thr.boost_priority();
+            next = thr.pi_blocked_on().and_then(|tid| get_task(tid).ok());
+            boost.0.push(task);
+        }
+        curr.as_thread().set_pi_blocked_on(Some(owner_tid));
+        Ok(boost)
+    }
+}
+
+impl Drop for PriorityBoost {
+    fn drop(&mut self) {
+        current().as_thread().set_pi_blocked_on(None);
+        for task in &self.0 {
+            if let Some(thr) = task.try_as_thread() {
+                thr.unboost_priority();
+            }
+        }
+    }
+}
+
+/// Returns the index of the waiter a priority-inheritance futex is handed over
+/// to: the one with the highest priority, the longest waiting among equals.
+fn top_pi_waiter(waiters: &VecDeque<(u32, Waker)>) -> Option<usize> {
+    waiters
+        .iter()
+        .enumerate()
+        .min_by_key(|(index, (tid, _))| {
+            let priority = get_task(*tid)
+                .ok()
+                .and_then(|task| task.try_as_thread().map(|thr| thr.effective_priority()));
+            (priority.unwrap_or(isize::MAX), *index)
+        })
+        .map(|(index, _)| index)
+}
+
+fn cmpxchg(word: &AtomicU32, current: u32, new: u32) -> bool {
+    word.compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+}
+
+/// Takes the priority-inheritance futex at `uaddr` for the current thread.
+///
+/// The futex word holds the TID of the owner, with `FUTEX_WAITERS` set while
+/// threads wait in the kernel so that the owner releases it through
+/// `FUTEX_UNLOCK_PI`. The owner, and whoever it waits for in turn, runs with
+/// the priority of its waiters until then. A futex whose owner died is taken
+/// over with `FUTEX_OWNER_DIED` left set for user space to notice.
+fn lock_pi(uaddr: *const u32, deadline: Option<TimeValue>, trylock: bool) -> AxResult<isize> {
+    let curr = current();
+    let thr = curr.as_thread();
+    let tid = curr.id().as_u64() as u32;
+    let word = UserPtr::from(uaddr.cast_mut()).get_as_atomic()?;
+
+    let key = FutexKey::new_current(uaddr.addr());
+    let futex_table = thr.proc_data.futex_table_for(&key);
+    let futex = futex_table.get_or_insert(&key);
+
+    loop {
+        let val = word.load(Ordering::SeqCst);
+        let owner = val & FUTEX_TID_MASK;
+        if owner == 0 {
+            let mut new = tid | (val & FUTEX_OWNER_DIED);
+            if !futex.wq.is_empty() {
+                new |= FUTEX_WAITERS;
+            }
+            if cmpxchg(word, val, new) {
+                // The word tells the new owner that the previous one died.
+                futex.owner_dead.store(false, Ordering::SeqCst);
+                thr.add_pi_futex(uaddr.addr());
+                return Ok(0);
+            }
+            continue;
+        }
+        if owner == tid {
+            return Err(AxError::from(LinuxError::EDEADLK));
+        }
+        if trylock {
+            return Err(AxError::WouldBlock);
+        }
+
+        let owner = get_task(owner)?;
+        let waiting = val | FUTEX_WAITERS;
+        if waiting != val && !cmpxchg(word, val, waiting) {
+            continue;
+        }
+        // The owner may have taken the futex in user space, so it is only
+        // known now, and is released by the kernel should the owner exit.
+        if let Some(owner_thr) = owner.try_as_thread()
+            && Arc::ptr_eq(&owner_thr.proc_data, &thr.proc_data)
+        {
+            owner_thr.add_pi_futex(uaddr.addr());
+        }
+        let boost = PriorityBoost::new(owner)?;
+        let mut queued = false;
+        let waited = futex.wq.wait_if_queued(
+            u32::MAX,
+            time_left(deadline),
+            || word.load(Ordering::SeqCst) == waiting,
+            |waker| {
+                futex.pi_waiters.lock().push_back((tid, waker.clone()));
+                queued = true;
+            },
+        );
+        drop(boost);
+        if queued && !futex.remove_pi_waiter(tid) {
+            // The owner handed the futex over while the thread waited.
+            thr.add_pi_futex(uaddr.addr());
+            return Ok(0);
+        }
+        waited?;
+    }
+}
+
+/// Releases the priority-inheritance futex at `uaddr` held by the current
+/// thread.
+///
+/// The futex is handed over to the top waiter by writing its TID into the
+/// futex word, so that no other thread can take it in between. Waiters
+/// requeued by `FUTEX_CMP_REQUEUE_PI` that haven't blocked on the futex yet
+/// are woken to take it over when they retry.
+fn unlock_pi(uaddr: *const u32) -> AxResult<isize> {
+    let curr = current();
+    let thr = curr.as_thread();
+    let tid = curr.id().as_u64() as u32;
+    let word = UserPtr::from(uaddr.cast_mut()).get_as_atomic()?;
+
+    let key = FutexKey::new_current(uaddr.addr());
+    let futex_table = thr.proc_data.futex_table_for(&key);
+    let futex = futex_table.get(&key);
+
+    let mut waiters = futex.as_ref().map(|futex| futex.pi_waiters.lock());
+    let new_owner = loop {
+        let val = word.load(Ordering::SeqCst);
+        if val & FUTEX_TID_MASK != tid {
+            return Err(AxError::OperationNotPermitted);
+        }
+        let top = waiters.as_deref().and_then(top_pi_waiter);
+        let new = match (top, waiters.as_deref()) {
+            (Some(index), Some(waiters)) if waiters.len() > 1 => waiters[index].0 | FUTEX_WAITERS,
+            (Some(index), Some(waiters)) => waiters[index].0,
+            _ if futex.as_ref().is_some_and(|futex| !futex.wq.is_empty()) => FUTEX_WAITERS,
+            _ => 0,
+        };
+        if cmpxchg(word, val, new) {
+            break top
+                .zip(waiters.as_mut())
+                .and_then(|(index, waiters)| waiters.remove(index));
+        }
+    };
+    drop(waiters);
+    thr.remove_pi_futex(uaddr.addr());
+
+    if let Some(futex) = futex {
+        // Only the new owner is woken; the other waiters keep waiting for it.
+        match new_owner {
+            Some((_, waker)) => {
+                futex.wq.wake_waker(&waker);
+            }
+            None => {
+                futex.wq.wake(1, u32::MAX);
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// Applies the operation `encoded_op` of `FUTEX_WAKE_OP` to `word`
+/// atomically, and returns whether the old value passes its comparison.
+///
+/// The operation takes the top 4 bits, the comparison the next 4, and their
+/// signed 12-bit arguments the rest.
+fn futex_wake_op(word: &AtomicU32, encoded_op: u32) -> AxResult<bool> {
+    let op = (encoded_op >> 28) & 0x7;
+    let cmp = (encoded_op >> 24) & 0xf;
+    let mut oparg = ((encoded_op << 8) as i32 >> 20) as u32;
+    let cmparg = (encoded_op << 20) as i32 >> 20;
+    if encoded_op & (FUTEX_OP_OPARG_SHIFT << 28) != 0 {
+        oparg = 1 << (oparg & 31);
+    }
+
+    let apply: fn(u32, u32) -> u32 = match op {
+        FUTEX_OP_SET => |_, arg| arg,
+        FUTEX_OP_ADD => u32::wrapping_add,
+        FUTEX_OP_OR => |val, arg| val | arg,
+        FUTEX_OP_ANDN => |val, arg| val & !arg,
+        FUTEX_OP_XOR => |val, arg| val ^ arg,
+        _ => return Err(AxError::from(LinuxError::ENOSYS)),
+    };
+    let compare: fn(&i32, &i32) -> bool = match cmp {
+        FUTEX_OP_CMP_EQ => i32::eq,
+        FUTEX_OP_CMP_NE => i32::ne,
+        FUTEX_OP_CMP_LT => i32::lt,
+        FUTEX_OP_CMP_LE => i32::le,
+        FUTEX_OP_CMP_GT => i32::gt,
+        FUTEX_OP_CMP_GE => i32::ge,
+        _ => return Err(AxError::from(LinuxError::ENOSYS)),
+    };
+
+    let old = word
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |val| {
+            Some(apply(val, oparg))
+        })
+        .unwrap_or_else(|val| val);
+    Ok(compare(&(old as i32), &cmparg))
+}
+
 pub fn sys_futex(
     uaddr: *const u32,
     futex_op: u32,
@@ -114,10 +376,160 @@ pub fn sys_futex(
             }
             Ok(count as _)
         }
+        FUTEX_WAKE_OP => {
+            let value2 = timeout.addr() as u32;
+            let word2 = UserPtr::from(uaddr2).get_as_atomic()?;
+            let wake2 = futex_wake_op(word2, value3)?;
+
+            let mut count = 0;
+            if let Some(futex) = futex_table.get(&key) {
+                count += futex.wq.wake(value as _, u32::MAX);
+            }
+            if wake2 {
+                let key2 = FutexKey::new_current(uaddr2.addr());
+                let table2 = proc_data.futex_table_for(&key2);
+                if let Some(futex2) = table2.get(&key2) {
+                    count += futex2.wq.wake(value2 as _, u32::MAX);
+                }
+            }
+            Ok(count as _)
+        }
+        FUTEX_LOCK_PI | FUTEX_LOCK_PI2 => {
+            let realtime = command == FUTEX_LOCK_PI || futex_op & FUTEX_CLOCK_REALTIME as u32 != 0;
+            lock_pi(uaddr, read_deadline(timeout, realtime)?, false)
+        }
+        FUTEX_TRYLOCK_PI => lock_pi(uaddr, None, true),
+        FUTEX_UNLOCK_PI => unlock_pi(uaddr),
+        FUTEX_WAIT_REQUEUE_PI => {
+            if uaddr.addr() == uaddr2.addr() {
+                return Err(AxError::InvalidInput);
+            }
+            let deadline = read_deadline(timeout, futex_op & FUTEX_CLOCK_REALTIME as u32 != 0)?;
+            if uaddr.vm_read()? != value {
+                return Err(AxError::WouldBlock);
+            }
+
+            // Whether woken here or after being requeued to the PI futex, the
+            // waiter returns owning the PI futex.
+            let futex = futex_table.get_or_insert(&key);
+            if !futex.wq.wait_if(u32::MAX, time_left(deadline), || {
+                uaddr.vm_read() == Ok(value)
+            })? {
+                return Err(AxError::WouldBlock);
+            }
+            drop(futex);
+            lock_pi(uaddr2, deadline, false)
+        }
+        FUTEX_CMP_REQUEUE_PI => {
+            if value != 1 || uaddr.addr() == uaddr2.addr() {
+                return Err(AxError::InvalidInput);
+            }
+            if uaddr.vm_read()? != value3 {
+                return Err(AxError::WouldBlock);
+            }
+            let value2 = assert_unsigned(timeout.addr() as u32)?;
+
+            let futex = futex_table.get(&key);
+            let key2 = FutexKey::new_current(uaddr2.addr());
+            let table2 = proc_data.futex_table_for(&key2);
+            let futex2 = table2.get_or_insert(&key2);
+
+            let mut count = 0;
+            if let Some(futex) = futex {
+                count = futex.wq.wake(1, u32::MAX);
+                let requeued = futex.wq.requeue(value2 as _, &futex2.wq);
+                if requeued > 0 {
+                    // The requeued waiters are woken as the PI futex is
+                    // released, which has to go through the kernel for that.
+                    let word2 = UserPtr::from(uaddr2).get_as_atomic()?;
+                    if word2.fetch_or(FUTEX_WAITERS, Ordering::SeqCst) & FUTEX_TID_MASK == 0 {
+                        futex2.wq.wake(1, u32::MAX);
+                    }
+                }
+                count += requeued;
+            }
+            Ok(count as _)
+        }
         _ => Err(AxError::Unsupported),
     }
 }
 
+/// Waits on up to `FUTEX_WAITV_MAX` futexes at once, until any of them is
+/// woken or the absolute `timeout`, measured by `clockid`, passes.
+///
+/// Returns the index of the futex that was woken.
+pub fn sys_futex_waitv(
+    waiters: *const futex_waitv,
+    nr_futexes: u32,
+    flags: u32,
+    timeout: *const timespec,
+    clockid: __kernel_clockid_t,
+) -> AxResult<isize> {
+    debug!(
+        "sys_futex_waitv <= waiters: {waiters:?}, nr_futexes: {nr_futexes}, flags: {flags}, \
+         clockid: {clockid}"
+    );
+
+    if flags != 0 || nr_futexes == 0 || nr_futexes > FUTEX_WAITV_MAX {
+        return Err(AxError::InvalidInput);
+    }
+    let deadline = if timeout.is_null() {
+        None
+    } else {
+        let realtime = match clockid as u32 {
+            CLOCK_REALTIME => true,
+            CLOCK_MONOTONIC => false,
+            _ => return Err(AxError::InvalidInput),
+        };
+        read_deadline(timeout, realtime)?
+    };
+
+    let mut futexes = Vec::with_capacity(nr_futexes as usize);
+    for i in 0..nr_futexes as usize {
+        // FIXME: AnyBitPattern
+        let waiter = unsafe { waiters.wrapping_add(i).vm_read_uninit()?.assume_init() };
+        if waiter.flags & !FUTEX2_PRIVATE != FUTEX2_SIZE_U32
+            || waiter.__reserved != 0
+            || waiter.uaddr % 4 != 0
+        {
+            return Err(AxError::InvalidInput);
+        }
+        let value = u32::try_from(waiter.val).map_err(|_| AxError::InvalidInput)?;
+        futexes.push((waiter.uaddr as usize as *const u32, value));
+    }
+
+    // Fast path
+    for (uaddr, value) in &futexes {
+        if uaddr.vm_read()? != *value {
+            return Err(AxError::WouldBlock);
+        }
+    }
+
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let keys: Vec<_> = futexes
+        .iter()
+        .map(|(uaddr, _)| FutexKey::new_current(uaddr.addr()))
+        .collect();
+    let tables: Vec<_> = keys
+        .iter()
+        .map(|key| proc_data.futex_table_for(key))
+        .collect();
+    let guards: Vec<_> = tables
+        .iter()
+        .zip(&keys)
+        .map(|(table, key)| table.get_or_insert(key))
+        .collect();
+    let queues: Vec<_> = guards.iter().map(|futex| &futex.wq).collect();
+
+    let woken = WaitQueue::wait_any(&queues, time_left(deadline), || {
+        futexes
+            .iter()
+            .all(|(uaddr, value)| uaddr.vm_read() == Ok(*value))
+    })?;
+    woken.map(|index| index as isize).ok_or(AxError::WouldBlock)
+}
+
 pub fn sys_get_robust_list(
     tid: u32,
     head: *mut *const robust_list_head,
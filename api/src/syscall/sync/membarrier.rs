@@ -1,33 +1,184 @@
-use core::sync::atomic::{Ordering, compiler_fence};
+use core::sync::atomic::{Ordering, fence};
 
 use axerrno::{AxError, AxResult};
+use axtask::current;
+use starry_core::task::{AsThread, MembarrierState, cpus_running_aspace, cpus_running_registered};
 
 /// Memory barrier commands
 const MEMBARRIER_CMD_QUERY: i32 = 0;
-const MEMBARRIER_CMD_GLOBAL: i32 = 1;
-const MEMBARRIER_CMD_GLOBAL_EXPEDITED: i32 = 2;
-const MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED: i32 = 3;
-const MEMBARRIER_CMD_PRIVATE_EXPEDITED: i32 = 4;
-const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: i32 = 5;
+const MEMBARRIER_CMD_GLOBAL: i32 = 1 << 0;
+const MEMBARRIER_CMD_GLOBAL_EXPEDITED: i32 = 1 << 1;
+const MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED: i32 = 1 << 2;
+const MEMBARRIER_CMD_PRIVATE_EXPEDITED: i32 = 1 << 3;
+const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: i32 = 1 << 4;
+const MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE: i32 = 1 << 5;
+const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE: i32 = 1 << 6;
+const MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ: i32 = 1 << 7;
+const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_RSEQ: i32 = 1 << 8;
+
+/// Restricts `MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ` to the CPU given
+const MEMBARRIER_CMD_FLAG_CPU: u32 = 1 << 0;
 
 /// Supported command flags for query
-const SUPPORTED_COMMANDS: i32 = (1 << MEMBARRIER_CMD_GLOBAL)
-    | (1 << MEMBARRIER_CMD_GLOBAL_EXPEDITED)
-    | (1 << MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED)
-    | (1 << MEMBARRIER_CMD_PRIVATE_EXPEDITED)
-    | (1 << MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED);
-
-pub fn sys_membarrier(cmd: i32, flags: u32, _cpu_id: i32) -> AxResult<isize> {
-    // 检查 flags 参数，目前应该为 0
-    if flags != 0 {
+const SUPPORTED_COMMANDS: i32 = MEMBARRIER_CMD_GLOBAL
+    | MEMBARRIER_CMD_GLOBAL_EXPEDITED
+    | MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED
+    | MEMBARRIER_CMD_PRIVATE_EXPEDITED
+    | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED
+    | MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE
+    | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE
+    | MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ
+    | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_RSEQ;
+
+/// Makes the instructions the process wrote visible to the instruction
+/// fetch of the current CPU once it returns to user space.
+fn sync_core() {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+            unsafe { core::arch::asm!("fence.i") };
+        } else if #[cfg(target_arch = "loongarch64")] {
+            unsafe { core::arch::asm!("ibar 0") };
+        } else {
+            // Returning to user space serializes the instruction stream.
+        }
+    }
+}
+
+/// Runs `f` on each of `cpus`, returning once all of them did.
+#[cfg(feature = "smp")]
+fn run_on_cpus(cpus: &[usize], f: impl Fn() + Clone + Send + 'static) {
+    use alloc::sync::Arc;
+    use core::sync::atomic::AtomicUsize;
+
+    let pending = Arc::new(AtomicUsize::new(cpus.len()));
+    for &cpu in cpus {
+        let pending = pending.clone();
+        let f = f.clone();
+        axipi::run_on_cpu(cpu, move || {
+            f();
+            pending.fetch_sub(1, Ordering::Release);
+        });
+    }
+    while pending.load(Ordering::Acquire) != 0 {
+        axtask::yield_now();
+    }
+}
+
+/// Runs a full memory barrier on each of `cpus`, and `sync_core` as well if
+/// asked to, returning once all of them did.
+///
+/// Taking the interrupt orders the user memory accesses of the thread a CPU
+/// runs against those before the system call, since the barrier sits
+/// between them.
+#[cfg(feature = "smp")]
+fn barrier_on(cpus: &[usize], sync: bool) {
+    run_on_cpus(cpus, move || {
+        fence(Ordering::SeqCst);
+        if sync {
+            sync_core();
+        }
+    });
+}
+
+/// There are no other CPUs to order against.
+#[cfg(not(feature = "smp"))]
+fn barrier_on(_cpus: &[usize], _sync: bool) {}
+
+/// Makes the CPUs that run a thread of the current process see a
+/// registration made on this one, returning once all of them did.
+#[cfg(feature = "smp")]
+fn sync_registration() {
+    use starry_core::task::refresh_membarrier_state;
+
+    let proc_data = &current().as_thread().proc_data;
+    run_on_cpus(&cpus_running_aspace(Some(proc_data)), || {
+        if let Some(thr) = current().try_as_thread() {
+            refresh_membarrier_state(&thr.proc_data);
+        }
+    });
+}
+
+/// There are no other CPUs to tell.
+#[cfg(not(feature = "smp"))]
+fn sync_registration() {}
+
+pub fn sys_membarrier(cmd: i32, flags: u32, cpu_id: i32) -> AxResult<isize> {
+    debug!("sys_membarrier <= cmd: {cmd}, flags: {flags}, cpu_id: {cpu_id}");
+
+    let valid_flags = if cmd == MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ {
+        MEMBARRIER_CMD_FLAG_CPU
+    } else {
+        0
+    };
+    if flags & !valid_flags != 0 {
         return Err(AxError::InvalidInput);
     }
 
-    match cmd {
-        MEMBARRIER_CMD_QUERY => Ok(SUPPORTED_COMMANDS as isize),
-        _ => {
-            compiler_fence(Ordering::SeqCst);
-            Ok(0)
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+
+    let (required, sync) = match cmd {
+        MEMBARRIER_CMD_QUERY => return Ok(SUPPORTED_COMMANDS as isize),
+        MEMBARRIER_CMD_GLOBAL => {
+            fence(Ordering::SeqCst);
+            barrier_on(&cpus_running_aspace(None), false);
+            fence(Ordering::SeqCst);
+            return Ok(0);
+        }
+        MEMBARRIER_CMD_GLOBAL_EXPEDITED => {
+            fence(Ordering::SeqCst);
+            barrier_on(
+                &cpus_running_registered(MembarrierState::GLOBAL_EXPEDITED),
+                false,
+            );
+            fence(Ordering::SeqCst);
+            return Ok(0);
+        }
+        MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED => {
+            proc_data.register_membarrier(MembarrierState::GLOBAL_EXPEDITED);
+            sync_registration();
+            return Ok(0);
+        }
+        MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED => {
+            proc_data.register_membarrier(MembarrierState::PRIVATE_EXPEDITED);
+            return Ok(0);
+        }
+        MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE => {
+            proc_data.register_membarrier(MembarrierState::PRIVATE_EXPEDITED_SYNC_CORE);
+            return Ok(0);
         }
+        MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_RSEQ => {
+            proc_data.register_membarrier(MembarrierState::PRIVATE_EXPEDITED_RSEQ);
+            return Ok(0);
+        }
+        MEMBARRIER_CMD_PRIVATE_EXPEDITED => (MembarrierState::PRIVATE_EXPEDITED, false),
+        MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE => {
+            (MembarrierState::PRIVATE_EXPEDITED_SYNC_CORE, true)
+        }
+        MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ => (MembarrierState::PRIVATE_EXPEDITED_RSEQ, false),
+        _ => return Err(AxError::InvalidInput),
+    };
+
+    if !proc_data.membarrier_state().contains(required) {
+        return Err(AxError::OperationNotPermitted);
+    }
+
+    let mut cpus = cpus_running_aspace(Some(proc_data));
+    if flags & MEMBARRIER_CMD_FLAG_CPU != 0 {
+        let cpu = usize::try_from(cpu_id).map_err(|_| AxError::InvalidInput)?;
+        if cpu >= axconfig::plat::CPU_NUM {
+            return Err(AxError::InvalidInput);
+        }
+        // There are no `rseq` critical sections to restart, so targeting a
+        // CPU only narrows the barrier.
+        cpus.retain(|it| *it == cpu);
+    }
+
+    fence(Ordering::SeqCst);
+    barrier_on(&cpus, sync);
+    if sync {
+        sync_core();
     }
+    fence(Ordering::SeqCst);
+    Ok(0)
 }
@@ -4,11 +4,12 @@ use core::ffi::c_char;
 use axconfig::ARCH;
 use axerrno::{AxError, AxResult};
 use axfs_ng::FS_CONTEXT;
+use axhal::time::monotonic_time;
 use linux_raw_sys::{
     general::{GRND_INSECURE, GRND_NONBLOCK, GRND_RANDOM},
     system::{new_utsname, sysinfo},
 };
-use starry_core::task::processes;
+use starry_core::{mm::MemInfo, task::processes, time::load_average};
 use starry_vm::{VmMutPtr, vm_write_slice};
 
 pub fn sys_getuid() -> AxResult<isize> {
@@ -77,6 +78,14 @@ pub fn sys_uname(name: *mut new_utsname) -> AxResult<isize> {
 pub fn sys_sysinfo(info: *mut sysinfo) -> AxResult<isize> {
     // FIXME: Zeroable
     let mut kinfo: sysinfo = unsafe { core::mem::zeroed() };
+    let mem = MemInfo::collect();
+    kinfo.uptime = monotonic_time().as_secs() as _;
+    kinfo.loads = load_average().map(|load| load as _);
+    kinfo.totalram = mem.total as _;
+    kinfo.freeram = mem.free as _;
+    kinfo.sharedram = mem.shmem as _;
+    kinfo.totalswap = mem.swap_total as _;
+    kinfo.freeswap = mem.swap_free as _;
     kinfo.procs = processes().len() as _;
     kinfo.mem_unit = 1;
     info.vm_write(kinfo)?;
@@ -8,7 +8,7 @@ use bitflags::bitflags;
 use kspin::SpinNoIrq;
 use linux_raw_sys::general::*;
 use starry_core::{
-    mm::copy_from_kernel,
+    mm::{INIT_PKRU, copy_from_kernel, read_pkru},
     task::{AsThread, ProcessData, Thread, add_task_to_table},
 };
 use starry_process::Pid;
@@ -17,6 +17,7 @@ use starry_signal::Signo;
 use crate::{
     file::{FD_TABLE, FileLike, PidFd},
     mm::UserPtr,
+    syscall::add_inherited_mappings,
     task::new_user_task,
 };
 
@@ -162,6 +163,10 @@ pub fn sys_clone(
             let mut aspace = old_proc_data.aspace.lock();
             let aspace = aspace.try_clone()?;
             copy_from_kernel(&mut aspace.lock())?;
+            old_proc_data
+                .uffd
+                .lock()
+                .restore_protection(&mut aspace.lock())?;
             aspace
         };
         new_task
@@ -182,6 +187,26 @@ pub fn sys_clone(
             exit_signal,
         );
         proc_data.set_umask(old_proc_data.umask());
+        proc_data.set_oom_score_adj(old_proc_data.oom_score_adj());
+        proc_data.set_dumpable(old_proc_data.is_dumpable());
+        *proc_data.rlim.write() = old_proc_data.rlim.read().clone();
+        *proc_data.auxv.write() = old_proc_data.auxv.read().clone();
+        *proc_data.thp.lock() = old_proc_data.thp.lock().clone();
+        *proc_data.mapped_files.lock() = old_proc_data.mapped_files.lock().clone();
+        *proc_data.soft_dirty.lock() = old_proc_data.soft_dirty.lock().clone();
+        *proc_data.pkeys.lock() = old_proc_data.pkeys.lock().clone();
+        proc_data.sync_rss(&proc_data.aspace.lock());
+        if !flags.contains(CloneFlags::VM) {
+            *proc_data.hugetlb.lock() = old_proc_data.hugetlb.lock().try_clone()?;
+            *proc_data.swap.lock() = old_proc_data.swap.lock().clone();
+            *proc_data.secret.lock() = old_proc_data.secret.lock().clone();
+            // The copied page table entries lost their protection keys.
+            proc_data
+                .pkeys
+                .lock()
+                .apply_all(&proc_data, &mut proc_data.aspace.lock());
+            add_inherited_mappings(&proc_data)?;
+        }
 
         {
             let mut scope = proc_data.scope.write();
@@ -215,6 +240,7 @@ pub fn sys_clone(
     }
 
     let thr = Thread::new(tid, new_proc_data);
+    thr.set_pkru(read_pkru().unwrap_or(INIT_PKRU));
     if flags.contains(CloneFlags::CHILD_CLEARTID) {
         thr.set_clear_child_tid(child_tid);
     }
@@ -4,6 +4,7 @@ use axerrno::{AxError, AxResult};
 use axtask::current;
 use linux_raw_sys::general::{__user_cap_data_struct, __user_cap_header_struct};
 use starry_core::task::{AsThread, get_process_data};
+use starry_signal::Signo;
 use starry_vm::{VmMutPtr, VmPtr, vm_write_slice};
 
 use crate::mm::vm_load_string;
@@ -97,6 +98,42 @@ pub fn sys_prctl(
             buf[..len].copy_from_slice(&name.as_bytes()[..len]);
             vm_write_slice(arg2 as _, &buf)?;
         }
+        PR_SET_DUMPABLE => {
+            // SUID_DUMP_ROOT (2) may only be set by the kernel.
+            if arg2 > 1 {
+                return Err(AxError::InvalidInput);
+            }
+            current().as_thread().proc_data.set_dumpable(arg2 == 1);
+        }
+        PR_GET_DUMPABLE => {
+            return Ok(current().as_thread().proc_data.is_dumpable() as isize);
+        }
+        PR_SET_PDEATHSIG => {
+            let signo = match arg2 {
+                0 => None,
+                _ => Some(
+                    u8::try_from(arg2)
+                        .ok()
+                        .and_then(Signo::from_repr)
+                        .ok_or(AxError::InvalidInput)?,
+                ),
+            };
+            current().as_thread().proc_data.set_pdeath_signal(signo);
+        }
+        PR_GET_PDEATHSIG => {
+            let signo = current().as_thread().proc_data.pdeath_signal();
+            (arg2 as *mut i32).vm_write(signo.map_or(0, |signo| signo as i32))?;
+        }
+        PR_SET_CHILD_SUBREAPER => {
+            current()
+                .as_thread()
+                .proc_data
+                .set_child_subreaper(arg2 != 0);
+        }
+        PR_GET_CHILD_SUBREAPER => {
+            let subreaper = current().as_thread().proc_data.is_child_subreaper();
+            (arg2 as *mut i32).vm_write(subreaper as i32)?;
+        }
         PR_SET_SECCOMP => {}
         PR_MCE_KILL => {}
         PR_SET_MM_START_CODE
@@ -5,7 +5,10 @@ use axerrno::{AxError, AxResult};
 use axfs_ng::FS_CONTEXT;
 use axhal::uspace::UserContext;
 use axtask::current;
-use starry_core::{mm::load_user_app, task::AsThread};
+use starry_core::{
+    mm::{INIT_PKRU, MappedFiles, load_user_app, read_auxv, write_pkru},
+    task::AsThread,
+};
 use starry_vm::vm_load_until_nul;
 
 use crate::{file::FD_TABLE, mm::vm_load_string};
@@ -49,9 +52,25 @@ pub fn sys_execve(
         return Err(AxError::WouldBlock);
     }
 
+    // The old image is about to be discarded, so sample its peak RSS first.
+    proc_data.update_maxrss();
+
     let mut aspace = proc_data.aspace.lock();
-    let (entry_point, user_stack_base) =
-        load_user_app(&mut aspace, Some(path.as_str()), &args, &envs)?;
+    let mut mapped_files = MappedFiles::default();
+    let (entry_point, user_stack_base) = load_user_app(
+        &mut aspace,
+        &mut mapped_files,
+        Some(path.as_str()),
+        &args,
+        &envs,
+    )?;
+    proc_data.clear_mm();
+    *proc_data.mapped_files.lock() = mapped_files;
+    proc_data.sync_rss(&aspace);
+    proc_data.clear_membarrier();
+    write_pkru(INIT_PKRU);
+    proc_data.reset_vm_peak();
+    *proc_data.auxv.write() = Arc::new(read_auxv(&aspace, user_stack_base)?);
     drop(aspace);
 
     let loc = FS_CONTEXT.lock().resolve(&path)?;
@@ -61,6 +80,7 @@ pub fn sys_execve(
     *proc_data.cmdline.write() = Arc::new(args);
 
     *proc_data.signal.actions.lock() = Default::default();
+    proc_data.set_dumpable(true);
 
     // Close CLOEXEC file descriptors
     let mut fd_table = FD_TABLE.write();
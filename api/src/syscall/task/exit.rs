@@ -1,13 +1,14 @@
 use axerrno::AxResult;
+use starry_core::task::exited_status;
 
 use crate::task::do_exit;
 
 pub fn sys_exit(exit_code: i32) -> AxResult<isize> {
-    do_exit(exit_code << 8, false);
+    do_exit(exited_status(exit_code), false);
     Ok(0)
 }
 
 pub fn sys_exit_group(exit_code: i32) -> AxResult<isize> {
-    do_exit(exit_code << 8, true);
+    do_exit(exited_status(exit_code), true);
     Ok(0)
 }
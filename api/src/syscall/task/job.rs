@@ -3,6 +3,8 @@ use axtask::current;
 use starry_core::task::{AsThread, get_process_data, get_process_group};
 use starry_process::Pid;
 
+use crate::vfs::dev::tty;
+
 pub fn sys_getsid(pid: Pid) -> AxResult<isize> {
     Ok(get_process_data(pid)?.proc.group().session().sid() as _)
 }
@@ -37,4 +39,9 @@ pub fn sys_setpgid(pid: Pid, pgid: Pid) -> AxResult<isize> {
     Ok(0)
 }
 
+pub fn sys_vhangup() -> AxResult<isize> {
+    tty::hangup_current();
+    Ok(0)
+}
+
 // TODO: job control
@@ -8,19 +8,33 @@ use axtask::{
 };
 use bitflags::bitflags;
 use linux_raw_sys::general::{
-    __WALL, __WCLONE, __WNOTHREAD, WCONTINUED, WEXITED, WNOHANG, WNOWAIT, WUNTRACED,
+    __WALL, __WCLONE, __WNOTHREAD, P_ALL, P_PGID, P_PID, P_PIDFD, WCONTINUED, WEXITED, WNOHANG,
+    WNOWAIT, WUNTRACED, rusage, siginfo,
+};
+use starry_core::{
+    resources::Rusage,
+    task::{
+        AsThread, ProcessData, StopEvent, child_signal_info, exit_status_info, get_process_data,
+    },
 };
-use starry_core::task::AsThread;
 use starry_process::{Pid, Process};
+use starry_signal::Signo;
 use starry_vm::{VmMutPtr, VmPtr};
 
+use crate::{
+    file::{FileLike, PidFd},
+    syscall::resources::rusage_to_user,
+};
+
 bitflags! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy)]
     struct WaitOptions: u32 {
         /// Do not block when there are no processes wishing to report status.
         const WNOHANG = WNOHANG;
         /// Report the status of selected processes which are stopped due to a
         /// `SIGTTIN`, `SIGTTOU`, `SIGTSTP`, or `SIGSTOP` signal.
+        ///
+        /// Also known as `WSTOPPED` for `waitid`.
         const WUNTRACED = WUNTRACED;
         /// Report the status of selected processes which have terminated.
         const WEXITED = WEXITED;
@@ -39,6 +53,25 @@ bitflags! {
     }
 }
 
+impl WaitOptions {
+    /// Returns whether `child` is of the type these options wait for.
+    ///
+    /// "Clone" children, which don't send `SIGCHLD` when they exit, are only
+    /// waited for with `__WCLONE` or `__WALL`; the others only without
+    /// `__WCLONE`.
+    fn wants_child(&self, parent: &ProcessData, child: &Process) -> bool {
+        if self.contains(Self::WALL) {
+            return true;
+        }
+        // The child's data may already be gone if it is a zombie.
+        let clone_child = get_process_data(child.pid()).map_or_else(
+            |_| parent.is_clone_zombie(child.pid()),
+            |data| data.is_clone_child(),
+        );
+        clone_child == self.contains(Self::WCLONE)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 enum WaitPid {
     /// Wait for any child process
@@ -59,58 +92,158 @@ impl WaitPid {
     }
 }
 
-pub fn sys_waitpid(pid: i32, exit_code: *mut i32, options: u32) -> AxResult<isize> {
-    let options = WaitOptions::from_bits_truncate(options);
-    info!("sys_waitpid <= pid: {pid:?}, options: {options:?}");
+/// A change of state of a child, collected by `wait4` or `waitid`.
+struct WaitEvent {
+    pid: Pid,
+    /// The wait status
+    status: i32,
+    /// The `si_code` describing the change
+    code: u32,
+    /// The `si_status` describing the change
+    si_status: i32,
+    rusage: Rusage,
+}
 
+/// Waits for a child selected by `pid` to change state as `options` asks.
+///
+/// Returns `None` if `WNOHANG` is given and no child has changed state yet.
+fn do_wait(pid: WaitPid, options: WaitOptions) -> AxResult<Option<WaitEvent>> {
     let curr = current();
     let proc_data = &curr.as_thread().proc_data;
-    let proc = &proc_data.proc;
-
-    let pid = if pid == -1 {
-        WaitPid::Any
-    } else if pid == 0 {
-        WaitPid::Pgid(proc.group().pgid())
-    } else if pid > 0 {
-        WaitPid::Pid(pid as _)
-    } else {
-        WaitPid::Pgid(-pid as _)
-    };
 
-    // FIXME: add back support for WALL & WCLONE, since ProcessData may drop before
-    // Process now.
-    let children = proc
+    let children = proc_data
+        .proc
         .children()
         .into_iter()
-        .filter(|child| pid.apply(child))
+        .filter(|child| pid.apply(child) && options.wants_child(proc_data, child))
         .collect::<Vec<_>>();
     if children.is_empty() {
         return Err(AxError::from(LinuxError::ECHILD));
     }
 
     let check_children = || {
-        if let Some(child) = children.iter().find(|child| child.is_zombie()) {
-            if !options.contains(WaitOptions::WNOWAIT) {
+        if options.contains(WaitOptions::WEXITED)
+            && let Some(child) = children.iter().find(|child| child.is_zombie())
+        {
+            let reap = !options.contains(WaitOptions::WNOWAIT);
+            let status = child.exit_code();
+            if reap {
                 child.free();
             }
-            if let Some(exit_code) = exit_code.nullable() {
-                exit_code.vm_write(child.exit_code())?;
-            }
-            Ok(Some(child.pid() as _))
-        } else if options.contains(WaitOptions::WNOHANG) {
-            Ok(Some(0))
-        } else {
-            Ok(None)
+            let rusage = proc_data.take_zombie_rusage(child.pid(), reap);
+            let (code, si_status) = exit_status_info(status);
+            return Some(WaitEvent {
+                pid: child.pid(),
+                status,
+                code,
+                si_status,
+                rusage,
+            });
         }
+        children.iter().find_map(|child| {
+            let data = get_process_data(child.pid()).ok()?;
+            let event = data.stop.take_event(
+                |event| match event {
+                    StopEvent::Stopped(_) => options.contains(WaitOptions::WUNTRACED),
+                    StopEvent::Continued => options.contains(WaitOptions::WCONTINUED),
+                },
+                !options.contains(WaitOptions::WNOWAIT),
+            )?;
+            let (code, si_status) = event.child_info();
+            Some(WaitEvent {
+                pid: child.pid(),
+                status: event.wait_status(),
+                code,
+                si_status,
+                rusage: data.self_rusage(),
+            })
+        })
     };
 
-    block_on(interruptible(poll_fn(|cx| {
-        match check_children().transpose() {
-            Some(res) => Poll::Ready(res),
-            None => {
-                proc_data.child_exit_event.register(cx.waker());
-                Poll::Pending
-            }
+    block_on(interruptible(poll_fn(|cx| match check_children() {
+        Some(event) => Poll::Ready(Ok(Some(event))),
+        None if options.contains(WaitOptions::WNOHANG) => Poll::Ready(Ok(None)),
+        None => {
+            proc_data.child_exit_event.register(cx.waker());
+            Poll::Pending
         }
     })))?
 }
+
+pub fn sys_waitpid(
+    pid: i32,
+    exit_code: *mut i32,
+    options: u32,
+    rusage: *mut rusage,
+) -> AxResult<isize> {
+    let options = WaitOptions::from_bits(options)
+        .filter(|options| !options.intersects(WaitOptions::WEXITED | WaitOptions::WNOWAIT))
+        .ok_or(AxError::InvalidInput)?;
+    info!("sys_waitpid <= pid: {pid:?}, options: {options:?}");
+
+    let pid = if pid == -1 {
+        WaitPid::Any
+    } else if pid == 0 {
+        WaitPid::Pgid(current().as_thread().proc_data.proc.group().pgid())
+    } else if pid > 0 {
+        WaitPid::Pid(pid as _)
+    } else if pid == i32::MIN {
+        return Err(AxError::NoSuchProcess);
+    } else {
+        WaitPid::Pgid(-pid as _)
+    };
+
+    let Some(event) = do_wait(pid, options | WaitOptions::WEXITED)? else {
+        return Ok(0);
+    };
+    if let Some(exit_code) = exit_code.nullable() {
+        exit_code.vm_write(event.status)?;
+    }
+    if let Some(rusage) = rusage.nullable() {
+        rusage.vm_write(rusage_to_user(event.rusage))?;
+    }
+    Ok(event.pid as _)
+}
+
+pub fn sys_waitid(
+    which: u32,
+    id: u32,
+    info: *mut siginfo,
+    options: u32,
+    rusage: *mut rusage,
+) -> AxResult<isize> {
+    let options = WaitOptions::from_bits(options)
+        .filter(|options| {
+            options
+                .intersects(WaitOptions::WEXITED | WaitOptions::WUNTRACED | WaitOptions::WCONTINUED)
+        })
+        .ok_or(AxError::InvalidInput)?;
+    info!("sys_waitid <= which: {which}, id: {id}, options: {options:?}");
+
+    let pid = match which {
+        P_ALL => WaitPid::Any,
+        P_PID if id as i32 > 0 => WaitPid::Pid(id as _),
+        P_PGID if id == 0 => WaitPid::Pgid(current().as_thread().proc_data.proc.group().pgid()),
+        P_PGID if id as i32 > 0 => WaitPid::Pgid(id as _),
+        P_PIDFD => WaitPid::Pid(PidFd::from_fd(id as _)?.pid()),
+        _ => return Err(AxError::InvalidInput),
+    };
+
+    let event = do_wait(pid, options)?;
+    if let Some(info) = info.nullable() {
+        let sig = match &event {
+            Some(event) => {
+                child_signal_info(Signo::SIGCHLD, event.code, event.pid, event.si_status).0
+            }
+            // Nothing to report with WNOHANG
+            // FIXME: Zeroable
+            None => unsafe { core::mem::zeroed() },
+        };
+        info.vm_write(sig)?;
+    }
+    if let Some(rusage) = rusage.nullable() {
+        let usage = event.as_ref().map(|event| event.rusage).unwrap_or_default();
+        rusage.vm_write(rusage_to_user(usage))?;
+    }
+    Ok(0)
+}
@@ -1,29 +1,59 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+};
 use core::{ffi::c_long, sync::atomic::Ordering};
 
-use axerrno::{AxError, AxResult};
-use axhal::uspace::{ExceptionKind, ReturnReason, UserContext};
-use axtask::{TaskInner, current};
+use axerrno::{AxError, AxResult, LinuxError};
+use axfs_ng::FS_CONTEXT;
+#[cfg(target_arch = "loongarch64")]
+use axhal::uspace::ExceptionKind;
+use axhal::uspace::{ReturnReason, UserContext};
+use axsync::Mutex;
+use axtask::{AxTaskRef, TaskExtProxy, TaskInner, current, spawn_task};
 use bytemuck::AnyBitPattern;
-use linux_raw_sys::general::ROBUST_LIST_LIMIT;
+use linux_raw_sys::general::{FUTEX_OWNER_DIED, FUTEX_TID_MASK, FUTEX_WAITERS, ROBUST_LIST_LIMIT};
 use starry_core::{
     futex::FutexKey,
-    mm::access_user_memory,
+    mm::{
+        MappedFiles, access_user_memory, copy_from_kernel, load_user_app, new_user_aspace_empty,
+        read_auxv,
+    },
     shm::SHM_MANAGER,
     task::{
-        AsThread, get_process_data, get_task, send_signal_to_process, send_signal_to_thread,
-        set_timer_state,
+        AsThread, ProcessData, Thread, add_task_to_table, child_signal_info, exit_status_info,
+        get_process_data, get_task, reparent_orphans, send_signal_to_process,
+        send_signal_to_thread, set_timer_state, signaled_status,
     },
     time::TimerState,
 };
-use starry_process::Pid;
-use starry_signal::{SignalInfo, Signo};
+use starry_process::{Pid, Process};
+use starry_signal::{SignalInfo, SignalOSAction, Signo};
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
-    signal::{check_signals, unblock_next_signal},
-    syscall::handle_syscall,
+    fault::{bus_info, exception_info, segv_info},
+    mm::{UserPtr, handle_user_page_fault},
+    signal::{check_signals, unblock_next_signal, wait_while_stopped},
+    syscall::{handle_syscall, is_restartable},
+    vfs::dev::tty::disassociate_ctty,
 };
 
+/// The length of the instruction that makes a system call
+#[cfg(target_arch = "x86_64")]
+const SYSCALL_INSN_LEN: usize = 2;
+#[cfg(not(target_arch = "x86_64"))]
+const SYSCALL_INSN_LEN: usize = 4;
+
+/// Rewinds `uctx` so that the system call it returned from is made again.
+///
+/// The return value overwrote the system call number on x86_64 and the first
+/// argument elsewhere, so that register is restored to `saved`.
+fn restart_syscall(uctx: &mut UserContext, saved: usize) {
+    uctx.set_ip(uctx.ip() - SYSCALL_INSN_LEN);
+    uctx.set_retval(saved);
+}
+
 /// Create a new user task.
 pub fn new_user_task(
     name: &str,
@@ -44,39 +74,57 @@ pub fn new_user_task(
             let thr = curr.as_thread();
             while !thr.pending_exit() {
                 let reason = uctx.run();
+                thr.save_user_context(&uctx);
+                let mut interrupted = None;
 
                 set_timer_state(&curr, TimerState::Kernel);
 
                 match reason {
-                    ReturnReason::Syscall => handle_syscall(&mut uctx),
+                    ReturnReason::Syscall => {
+                        // The register the return value goes into
+                        #[cfg(target_arch = "x86_64")]
+                        let saved = uctx.sysno();
+                        #[cfg(not(target_arch = "x86_64"))]
+                        let saved = uctx.arg0();
+                        let restartable = is_restartable(&uctx);
+                        handle_syscall(&mut uctx);
+                        if restartable
+                            && uctx.retval() as isize == -(LinuxError::EINTR.code() as isize)
+                        {
+                            interrupted = Some(saved);
+                        }
+                    }
                     ReturnReason::PageFault(addr, flags) => {
-                        if !thr.proc_data.aspace.lock().handle_page_fault(addr, flags) {
-                            info!(
-                                "{:?}: segmentation fault at {:#x} {:?}",
-                                thr.proc_data.proc, addr, flags
-                            );
-                            raise_signal_fatal(SignalInfo::new_kernel(Signo::SIGSEGV))
-                                .expect("Failed to send SIGSEGV");
+                        match handle_user_page_fault(thr, addr, flags) {
+                            Ok(()) => {}
+                            Err(AxError::OutOfRange) => {
+                                info!(
+                                    "{:?}: bus error at {:#x} {:?}",
+                                    thr.proc_data.proc, addr, flags
+                                );
+                                raise_signal_fatal(bus_info(addr)).expect("Failed to send SIGBUS");
+                            }
+                            Err(_) => {
+                                info!(
+                                    "{:?}: segmentation fault at {:#x} {:?}",
+                                    thr.proc_data.proc, addr, flags
+                                );
+                                raise_signal_fatal(segv_info(thr, addr, flags))
+                                    .expect("Failed to send SIGSEGV");
+                            }
                         }
                     }
                     ReturnReason::Interrupt => {}
                     #[allow(unused_labels)]
                     ReturnReason::Exception(exc_info) => 'exc: {
-                        // TODO: detailed handling
-                        let signo = match exc_info.kind() {
-                            ExceptionKind::Misaligned => {
-                                #[cfg(target_arch = "loongarch64")]
-                                if unsafe { uctx.emulate_unaligned() }.is_ok() {
-                                    break 'exc;
-                                }
-                                Signo::SIGBUS
-                            }
-                            ExceptionKind::Breakpoint => Signo::SIGTRAP,
-                            ExceptionKind::IllegalInstruction => Signo::SIGILL,
-                            _ => Signo::SIGTRAP,
-                        };
-                        raise_signal_fatal(SignalInfo::new_kernel(signo))
-                            .expect("Failed to send SIGTRAP");
+                        #[cfg(target_arch = "loongarch64")]
+                        if matches!(exc_info.kind(), ExceptionKind::Misaligned)
+                            && unsafe { uctx.emulate_unaligned() }.is_ok()
+                        {
+                            break 'exc;
+                        }
+                        raise_signal_fatal(exception_info(&uctx, &exc_info))
+                            .expect("Failed to send exception signal");
                     }
                     r => {
                         warn!("Unexpected return reason: {r:?}");
@@ -86,9 +134,25 @@ pub fn new_user_task(
                 }
 
                 if !unblock_next_signal() {
-                    while check_signals(thr, &mut uctx, None) {}
+                    let (mut stopped, mut handled) = (false, false);
+                    while let Some(action) = check_signals(thr, &mut uctx, None) {
+                        match action {
+                            SignalOSAction::Stop | SignalOSAction::Continue => stopped = true,
+                            _ => handled = true,
+                        }
+                    }
+                    stopped |= wait_while_stopped(thr);
+                    // A restartable system call interrupted only to stop the
+                    // process is made again once it is continued.
+                    if let Some(saved) = interrupted
+                        && stopped
+                        && !handled
+                    {
+                        restart_syscall(&mut uctx, saved);
+                    }
                 }
 
+                thr.update_priority();
                 set_timer_state(&curr, TimerState::User);
                 // Clear interrupt state
                 let _ = curr.interrupted();
@@ -99,6 +163,57 @@ pub fn new_user_task(
     )
 }
 
+/// Loads the program `args` names into a new address space and runs it as the
+/// only thread of a new process, which `new_proc` creates from the ID of that
+/// thread.
+///
+/// `setup` prepares the process, such as its file descriptors, before it
+/// starts running.
+pub fn spawn_user_process(
+    args: &[String],
+    envs: &[String],
+    new_proc: impl FnOnce(Pid) -> Arc<Process>,
+    exit_signal: Option<Signo>,
+    setup: impl FnOnce(&ProcessData) -> AxResult,
+) -> AxResult<AxTaskRef> {
+    let loc = FS_CONTEXT
+        .lock()
+        .resolve(args.first().ok_or(AxError::InvalidInput)?)?;
+    let path = loc.absolute_path()?.to_string();
+
+    let mut uspace = new_user_aspace_empty()?;
+    copy_from_kernel(&mut uspace)?;
+    let mut mapped_files = MappedFiles::default();
+    let (entry, ustack_top) = load_user_app(&mut uspace, &mut mapped_files, None, args, envs)?;
+    let auxv = read_auxv(&uspace, ustack_top)?;
+
+    let uctx = UserContext::new(entry.into(), ustack_top, 0);
+    let mut task = new_user_task(loc.name(), uctx, None);
+    task.ctx_mut().set_page_table_root(uspace.page_table_root());
+    let pid = task.id().as_u64() as Pid;
+
+    let proc = new_proc(pid);
+    proc.add_thread(pid);
+
+    let proc_data = ProcessData::new(
+        proc,
+        path,
+        Arc::new(args.to_vec()),
+        Arc::new(Mutex::new(uspace)),
+        Arc::default(),
+        exit_signal,
+    );
+    *proc_data.mapped_files.lock() = mapped_files;
+    *proc_data.auxv.write() = Arc::new(auxv);
+    setup(&proc_data)?;
+
+    let thr = Thread::new(pid, proc_data);
+    *task.task_ext_mut() = Some(unsafe { TaskExtProxy::from_impl(thr) });
+    let task = spawn_task(task);
+    add_task_to_table(&task);
+    Ok(task)
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, AnyBitPattern)]
 pub struct RobustList {
@@ -113,11 +228,31 @@ pub struct RobustListHead {
     pub list_op_pending: *mut RobustList,
 }
 
-fn handle_futex_death(entry: *mut RobustList, offset: i64) -> AxResult<()> {
+/// Strips the flag in the lowest bit of a robust list pointer, which marks
+/// priority-inheritance futexes.
+fn robust_entry(entry: *mut RobustList) -> *mut RobustList {
+    entry.map_addr(|addr| addr & !1)
+}
+
+fn handle_futex_death(entry: *mut RobustList, offset: i64, tid: u32) -> AxResult<()> {
     let address = (entry as u64)
         .checked_add_signed(offset)
         .ok_or(AxError::InvalidInput)?;
     let address: usize = address.try_into().map_err(|_| AxError::InvalidInput)?;
+    release_dead_futex(address, tid)
+}
+
+/// Releases the futex at `address` if the exiting thread `tid` holds it,
+/// telling the next owner that it has to recover the state it protects.
+fn release_dead_futex(address: usize, tid: u32) -> AxResult<()> {
+    let word = UserPtr::<u32>::from(address).get_as_atomic()?;
+    let released = word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |val| {
+        (val & FUTEX_TID_MASK == tid).then_some((val & FUTEX_WAITERS) | FUTEX_OWNER_DIED)
+    });
+    if released.is_err() {
+        return Ok(());
+    }
+
     let key = FutexKey::new_current(address);
 
     let curr = current();
@@ -135,17 +270,18 @@ pub fn exit_robust_list(head: *const RobustListHead) -> AxResult<()> {
     // Reference: https://elixir.bootlin.com/linux/v6.13.6/source/kernel/futex/core.c#L777
 
     let mut limit = ROBUST_LIST_LIMIT;
+    let tid = current().id().as_u64() as u32;
 
     let end_ptr = unsafe { &raw const (*head).list };
     let head = head.vm_read()?;
-    let mut entry = head.list.next;
+    let mut entry = robust_entry(head.list.next);
     let offset = head.futex_offset;
-    let pending = head.list_op_pending;
+    let pending = robust_entry(head.list_op_pending);
 
     while !core::ptr::eq(entry, end_ptr) {
-        let next_entry = entry.vm_read()?.next;
+        let next_entry = robust_entry(entry.vm_read()?.next);
         if entry != pending {
-            handle_futex_death(entry, offset)?;
+            handle_futex_death(entry, offset, tid)?;
         }
         entry = next_entry;
 
@@ -181,15 +317,54 @@ pub fn do_exit(exit_code: i32, group_exit: bool) {
     {
         warn!("exit robust list failed: {err:?}");
     }
+    // Priority-inheritance futexes the thread is known to hold are released
+    // as well, whether or not they are on its robust list.
+    let tid = curr.id().as_u64() as u32;
+    for address in thr.take_pi_futexes() {
+        if let Err(err) = release_dead_futex(address, tid) {
+            warn!("release PI futex at {address:#x} failed: {err:?}");
+        }
+    }
 
+    let exit_code = if group_exit {
+        thr.proc_data.group_exit_code(exit_code)
+    } else {
+        exit_code
+    };
     let process = &thr.proc_data.proc;
-    if process.exit_thread(curr.id().as_u64() as Pid, exit_code) {
+    let last_thread = process.exit_thread(curr.id().as_u64() as Pid, exit_code);
+    // Only now is the thread no longer counted among the live ones.
+    thr.proc_data.add_exited_thread(thr);
+    if last_thread {
+        disassociate_ctty(process);
+        let orphans = process.children();
         process.exit();
-        if let Some(parent) = process.parent() {
+        if !process.is_init() {
+            reparent_orphans(&thr.proc_data, &orphans);
+        }
+        if thr.proc_data.is_autoreap() {
+            // Nobody waits for helpers the kernel started; a parent blocked
+            // in wait4 only needs to look at its children again.
+            let parent = process.parent();
+            process.free();
+            if let Some(data) = parent.and_then(|parent| get_process_data(parent.pid()).ok()) {
+                data.child_exit_event.wake();
+            }
+        } else if let Some(parent) = process.parent() {
+            let parent_data = get_process_data(parent.pid()).ok();
+            if let Some(data) = &parent_data {
+                let usage = thr
+                    .proc_data
+                    .self_rusage()
+                    .collate(thr.proc_data.children_rusage());
+                data.add_zombie(process.pid(), usage, thr.proc_data.is_clone_child());
+            }
             if let Some(signo) = thr.proc_data.exit_signal {
-                let _ = send_signal_to_process(parent.pid(), Some(SignalInfo::new_kernel(signo)));
+                let (code, status) = exit_status_info(process.exit_code());
+                let sig = child_signal_info(signo, code, process.pid(), status);
+                let _ = send_signal_to_process(parent.pid(), Some(sig));
             }
-            if let Ok(data) = get_process_data(parent.pid()) {
+            if let Some(data) = parent_data {
                 data.child_exit_event.wake();
             }
         }
@@ -220,7 +395,7 @@ pub fn raise_signal_fatal(sig: SignalInfo) -> AxResult<()> {
         task.interrupt();
     } else {
         // No task wants to handle the signal, abort the task
-        do_exit(signo as i32, true);
+        do_exit(signaled_status(signo, false), true);
     }
 
     Ok(())
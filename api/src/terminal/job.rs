@@ -1,12 +1,13 @@
 use alloc::sync::{Arc, Weak};
 use core::task::Context;
 
-use axerrno::{AxResult, ax_bail};
+use axerrno::{AxError, AxResult, ax_bail};
 use axpoll::{IoEvents, PollSet, Pollable};
 use axtask::current;
 use kspin::SpinNoIrq;
-use starry_core::task::AsThread;
+use starry_core::task::{AsThread, send_signal_to_process_group};
 use starry_process::{ProcessGroup, Session};
+use starry_signal::{SignalDisposition, SignalInfo, Signo};
 
 pub struct JobControl {
     foreground: SpinNoIrq<Weak<ProcessGroup>>,
@@ -36,6 +37,41 @@ impl JobControl {
             .is_none_or(|pg| Arc::ptr_eq(&current().as_thread().proc_data.proc.group(), &pg))
     }
 
+    /// Stops the process group of the current process with `signo` if it
+    /// uses the terminal from the background.
+    ///
+    /// If `signo` is blocked or ignored, background reads fail with `EIO` and
+    /// writes go ahead. Otherwise the call fails as interrupted once the
+    /// signal is sent, and is made again when the group is continued.
+    pub fn check_background(&self, signo: Signo) -> AxResult<()> {
+        let curr = current();
+        let thr = curr.as_thread();
+        let pg = thr.proc_data.proc.group();
+        let controlling = self
+            .session
+            .lock()
+            .upgrade()
+            .is_some_and(|session| Arc::ptr_eq(&session, &pg.session()));
+        if !controlling || self.current_in_foreground() {
+            return Ok(());
+        }
+
+        let ignored = thr.signal.blocked().has(signo)
+            || matches!(
+                thr.proc_data.signal.actions.lock()[signo].disposition,
+                SignalDisposition::Ignore
+            );
+        if ignored {
+            return if signo == Signo::SIGTTIN {
+                Err(AxError::Io)
+            } else {
+                Ok(())
+            };
+        }
+        send_signal_to_process_group(pg.pgid(), Some(SignalInfo::new_kernel(signo)))?;
+        Err(AxError::Interrupted)
+    }
+
     pub fn foreground(&self) -> Option<Arc<ProcessGroup>> {
         self.foreground.lock().upgrade()
     }
@@ -71,6 +107,19 @@ impl JobControl {
         assert!(guard.upgrade().is_none());
         *guard = Arc::downgrade(session);
     }
+
+    /// Returns the session the terminal controls.
+    pub fn session(&self) -> Option<Arc<Session>> {
+        self.session.lock().upgrade()
+    }
+
+    /// Forgets the session and the foreground process group once the terminal
+    /// no longer controls the session.
+    pub fn clear_session(&self) {
+        *self.session.lock() = Weak::new();
+        *self.foreground.lock() = Weak::new();
+        self.poll_fg.wake();
+    }
 }
 
 impl Pollable for JobControl {
@@ -215,13 +215,15 @@ pub struct LineDiscipline<R, W> {
     processor: Processor<R, W>,
 }
 
-struct WaitPollable<'a>(Option<&'a Arc<PollSet>>);
+/// Waits for input or for the terminal to be hung up.
+struct WaitPollable<'a>(Option<&'a Arc<PollSet>>, &'a PollSet);
 impl Pollable for WaitPollable<'_> {
     fn poll(&self) -> IoEvents {
         unreachable!()
     }
 
     fn register(&self, context: &mut Context<'_>, _events: IoEvents) {
+        self.1.register(context.waker());
         if let Some(set) = self.0 {
             set.register(context.waker());
         } else {
@@ -359,10 +361,14 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
             Processor::External(set) => Some(set),
             _ => unreachable!(),
         };
-        let pollable = WaitPollable(set);
+        let pollable = WaitPollable(set, &self.terminal.hangup_event);
         Poller::new(&pollable, IoEvents::IN).poll(|| {
             total_read += self.buf_rx.pop_slice(&mut buf[total_read..]);
             self.poll_tx.wake();
+            // What was read before the hangup is returned, then end-of-file.
+            if self.terminal.is_hung_up() {
+                return Ok(total_read);
+            }
             (total_read >= vmin)
                 .then_some(total_read)
                 .ok_or(AxError::WouldBlock)
@@ -1,10 +1,13 @@
 //! Terminal module.
 
 use alloc::sync::Arc;
-use core::sync::atomic::AtomicU32;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
+use axpoll::PollSet;
 use bytemuck::AnyBitPattern;
 use kspin::SpinNoPreempt;
+use starry_core::task::{send_signal_to_process, send_signal_to_process_group};
+use starry_signal::{SignalInfo, Signo};
 
 pub mod job;
 pub mod ldisc;
@@ -24,6 +27,10 @@ pub struct Terminal {
     pub window_size: SpinNoPreempt<WindowSize>,
     pub termios: SpinNoPreempt<Arc<termios::Termios2>>,
     pub pty_number: AtomicU32,
+    /// Whether the terminal was hung up
+    hung_up: AtomicBool,
+    /// Woken when the terminal is hung up
+    pub hangup_event: PollSet,
 }
 impl Default for Terminal {
     fn default() -> Self {
@@ -37,6 +44,8 @@ impl Default for Terminal {
             }),
             termios: SpinNoPreempt::new(Arc::new(termios::Termios2::default())),
             pty_number: AtomicU32::new(0),
+            hung_up: AtomicBool::new(false),
+            hangup_event: PollSet::new(),
         }
     }
 }
@@ -44,4 +53,50 @@ impl Terminal {
     pub fn load_termios(&self) -> Arc<termios::Termios2> {
         self.termios.lock().clone()
     }
+
+    /// Detaches the terminal from the session it controls.
+    ///
+    /// The foreground process group, and the session leader too if
+    /// `signal_leader` is set, get `SIGHUP` followed by `SIGCONT`.
+    pub fn disassociate(&self, signal_leader: bool) {
+        let Some(session) = self.job_control.session() else {
+            return;
+        };
+        let foreground = self.job_control.foreground();
+        if let Some(term) = session.terminal() {
+            session.unset_terminal(&term);
+        }
+        self.job_control.clear_session();
+
+        for signo in [Signo::SIGHUP, Signo::SIGCONT] {
+            if signal_leader {
+                let _ = send_signal_to_process(session.sid(), Some(SignalInfo::new_kernel(signo)));
+            }
+            if let Some(pg) = &foreground {
+                let _ =
+                    send_signal_to_process_group(pg.pgid(), Some(SignalInfo::new_kernel(signo)));
+            }
+        }
+    }
+
+    /// Hangs up the terminal, as when the pty master is closed or `vhangup`
+    /// is called.
+    ///
+    /// Besides losing its session, the terminal reads as end-of-file and
+    /// fails writes with `EIO` until another session acquires it.
+    pub fn hangup(&self) {
+        self.hung_up.store(true, Ordering::Release);
+        self.disassociate(true);
+        self.hangup_event.wake();
+    }
+
+    /// Returns whether the terminal was hung up.
+    pub fn is_hung_up(&self) -> bool {
+        self.hung_up.load(Ordering::Acquire)
+    }
+
+    /// Makes a hung up terminal usable again.
+    pub fn clear_hangup(&self) {
+        self.hung_up.store(false, Ordering::Release);
+    }
 }
@@ -6,8 +6,10 @@ use axfs_ng_vfs::NodeFlags;
 use axpoll::{IoEvents, Pollable};
 use axsync::Mutex;
 use axtask::{current, future::Poller};
+use linux_raw_sys::general::TOSTOP;
 use starry_core::{task::AsThread, vfs::SimpleFs};
 use starry_process::Process;
+use starry_signal::Signo;
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
@@ -61,19 +63,66 @@ impl<R: TtyRead, W: TtyWrite + Clone> Tty<R, W> {
 
 impl<R: TtyRead, W: TtyWrite> Tty<R, W> {
     pub fn bind_to(self: &Arc<Self>, proc: &Process) -> AxResult<()> {
+        self.set_controlling(proc, false)
+    }
+
+    /// Makes this the controlling terminal of the session `proc` leads.
+    ///
+    /// A terminal that controls another session is only taken away from it if
+    /// `steal` is set.
+    fn set_controlling(self: &Arc<Self>, proc: &Process, steal: bool) -> AxResult<()> {
         let pg = proc.group();
-        if pg.session().sid() != proc.pid() {
+        let session = pg.session();
+        if session.sid() != proc.pid() {
             return Err(AxError::OperationNotPermitted);
         }
-        assert!(pg.session().set_terminal_with(|| {
-            self.terminal.job_control.set_session(&pg.session());
+        if let Some(term) = session.terminal() {
+            return if term
+                .downcast_ref::<Self>()
+                .is_some_and(|term| core::ptr::eq(term, &**self))
+            {
+                Ok(())
+            } else {
+                Err(AxError::OperationNotPermitted)
+            };
+        }
+        if let Some(other) = self.terminal.job_control.session() {
+            if !steal {
+                return Err(AxError::OperationNotPermitted);
+            }
+            if let Some(term) = other.terminal() {
+                other.unset_terminal(&term);
+            }
+            self.terminal.job_control.clear_session();
+        }
+        if !session.set_terminal_with(|| {
+            self.terminal.job_control.set_session(&session);
             self.clone()
-        }));
+        }) {
+            return Err(AxError::OperationNotPermitted);
+        }
 
-        self.terminal.job_control.set_foreground(&pg).unwrap();
+        self.terminal.job_control.set_foreground(&pg)?;
+        self.terminal.clear_hangup();
         Ok(())
     }
 
+    /// Returns whether this is the controlling terminal of the current
+    /// process.
+    fn is_current_controlling(&self) -> bool {
+        current()
+            .as_thread()
+            .proc_data
+            .proc
+            .group()
+            .session()
+            .terminal()
+            .is_some_and(|term| {
+                term.downcast_ref::<Self>()
+                    .is_some_and(|term| core::ptr::eq(term, self))
+            })
+    }
+
     pub fn pty_number(&self) -> u32 {
         self.terminal.pty_number.load(Ordering::Acquire)
     }
@@ -81,6 +130,12 @@ impl<R: TtyRead, W: TtyWrite> Tty<R, W> {
 
 impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
     fn read_at(&self, buf: &mut [u8], _offset: u64) -> AxResult<usize> {
+        if !self.is_ptm {
+            if self.terminal.is_hung_up() {
+                return Ok(0);
+            }
+            self.terminal.job_control.check_background(Signo::SIGTTIN)?;
+        }
         Poller::new(&self.terminal.job_control, IoEvents::IN).poll(|| {
             if self.is_ptm || self.terminal.job_control.current_in_foreground() {
                 self.ldisc.lock().read(buf)
@@ -91,12 +146,32 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
     }
 
     fn write_at(&self, buf: &[u8], _offset: u64) -> AxResult<usize> {
+        if !self.is_ptm && self.terminal.is_hung_up() {
+            return Err(AxError::Io);
+        }
+        if !self.is_ptm && self.terminal.termios.lock().has_lflag(TOSTOP) {
+            self.terminal.job_control.check_background(Signo::SIGTTOU)?;
+        }
         self.writer.write(buf);
         Ok(buf.len())
     }
 
     fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
         use linux_raw_sys::ioctl::*;
+        // A hung up terminal can only be acquired again.
+        if !self.is_ptm && self.terminal.is_hung_up() && cmd != TIOCSCTTY {
+            return Err(AxError::Io);
+        }
+        // Changing the terminal from the background is subject to job control
+        // like writing to it.
+        if !self.is_ptm
+            && matches!(
+                cmd,
+                TCSETS | TCSETSF | TCSETSW | TCSETS2 | TCSETSF2 | TCSETSW2 | TIOCSPGRP
+            )
+        {
+            self.terminal.job_control.check_background(Signo::SIGTTOU)?;
+        }
         match cmd {
             TCGETS => {
                 (arg as *mut Termios).vm_write(*self.terminal.termios.lock().as_ref().deref())?;
@@ -147,25 +222,31 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
                 self.this
                     .upgrade()
                     .unwrap()
-                    .bind_to(&current().as_thread().proc_data.proc)?;
+                    .set_controlling(&current().as_thread().proc_data.proc, arg == 1)?;
             }
             TIOCNOTTY => {
-                if current()
-                    .as_thread()
-                    .proc_data
-                    .proc
-                    .group()
-                    .session()
-                    .unset_terminal(&(self.this.upgrade().unwrap() as _))
-                {
-                    // TODO: If the process was session leader, send SIGHUP and
-                    // SIGCONT to the foreground process group and all processes
-                    // in the current session lose their
-                    // controlling terminal.
-                } else {
-                    warn!("Failed to unset terminal");
+                if !self.is_current_controlling() {
+                    return Err(AxError::NotATty);
+                }
+                // The controlling terminal belongs to the whole session, so
+                // only its leader can give it up.
+                let curr = current();
+                let proc = &curr.as_thread().proc_data.proc;
+                if proc.group().session().sid() == proc.pid() {
+                    self.terminal.disassociate(false);
                 }
             }
+            TIOCGSID => {
+                if !self.is_ptm && !self.is_current_controlling() {
+                    return Err(AxError::NotATty);
+                }
+                let session = self
+                    .terminal
+                    .job_control
+                    .session()
+                    .ok_or(AxError::NotATty)?;
+                (arg as *mut u32).vm_write(session.sid())?;
+            }
             _ => return Err(AxError::NotATty),
         }
         Ok(0)
@@ -185,8 +266,20 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
     }
 }
 
+impl<R, W> Drop for Tty<R, W> {
+    fn drop(&mut self) {
+        // Closing the pty master hangs up the slave.
+        if self.is_ptm {
+            self.terminal.hangup();
+        }
+    }
+}
+
 impl<R: TtyRead, W: TtyWrite> Pollable for Tty<R, W> {
     fn poll(&self) -> IoEvents {
+        if !self.is_ptm && self.terminal.is_hung_up() {
+            return IoEvents::IN | IoEvents::OUT | IoEvents::HUP;
+        }
         let mut events = IoEvents::OUT | self.terminal.job_control.poll();
         if self.is_ptm || events.contains(IoEvents::IN) {
             events.set(IoEvents::IN, self.ldisc.lock().poll_read());
@@ -197,6 +290,7 @@ impl<R: TtyRead, W: TtyWrite> Pollable for Tty<R, W> {
     fn register(&self, context: &mut Context<'_>, events: IoEvents) {
         if !self.is_ptm {
             self.terminal.job_control.register(context, events);
+            self.terminal.hangup_event.register(context.waker());
         }
         if events.contains(IoEvents::IN) {
             self.ldisc.lock().register_rx_waker(context.waker());
@@ -204,6 +298,40 @@ impl<R: TtyRead, W: TtyWrite> Pollable for Tty<R, W> {
     }
 }
 
+/// Returns the [`Terminal`] of the controlling terminal `term` of a session.
+fn session_terminal(term: &Arc<dyn Any + Send + Sync>) -> Option<&Arc<Terminal>> {
+    if let Some(tty) = term.downcast_ref::<NTtyDriver>() {
+        Some(&tty.terminal)
+    } else {
+        term.downcast_ref::<PtyDriver>().map(|pty| &pty.terminal)
+    }
+}
+
+/// Releases the controlling terminal of the session `proc` leads as it exits.
+///
+/// The foreground process group gets `SIGHUP` and `SIGCONT`.
+pub fn disassociate_ctty(proc: &Process) {
+    let session = proc.group().session();
+    if session.sid() != proc.pid() {
+        return;
+    }
+    if let Some(term) = session.terminal()
+        && let Some(terminal) = session_terminal(&term)
+    {
+        terminal.disassociate(false);
+    }
+}
+
+/// Hangs up the controlling terminal of the current process, if any.
+pub fn hangup_current() {
+    let session = current().as_thread().proc_data.proc.group().session();
+    if let Some(term) = session.terminal()
+        && let Some(terminal) = session_terminal(&term)
+    {
+        terminal.hangup();
+    }
+}
+
 pub struct CurrentTty;
 impl DeviceOps for CurrentTty {
     fn read_at(&self, _buf: &mut [u8], _offset: u64) -> AxResult<usize> {
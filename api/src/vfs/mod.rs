@@ -10,8 +10,12 @@ use axfs_ng_vfs::{
     Filesystem, NodePermission,
     path::{Path, PathBuf},
 };
+use axhal::paging::PageSize;
 pub use starry_core::vfs::{Device, DeviceOps, DirMapping, SimpleFs};
-pub use tmp::MemoryFs;
+pub use tmp::{
+    MemoryFs, add_file_seals, add_shared_mapping, file_seals, hugetlbfs_page_size, hugetlbfs_pages,
+    init_file_seals,
+};
 
 const DIR_PERMISSION: NodePermission = NodePermission::from_bits_truncate(0o755);
 
@@ -29,6 +33,11 @@ pub fn mount_all() -> LinuxResult<()> {
     let fs = FS_CONTEXT.lock();
     mount_at(&fs, "/dev", dev::new_devfs())?;
     mount_at(&fs, "/dev/shm", tmp::MemoryFs::new())?;
+    mount_at(
+        &fs,
+        "/dev/hugepages",
+        tmp::MemoryFs::new_hugetlbfs(PageSize::Size2M),
+    )?;
     mount_at(&fs, "/tmp", tmp::MemoryFs::new())?;
     mount_at(&fs, "/proc", proc::new_procfs())?;
 
@@ -7,81 +7,114 @@ use alloc::{
     vec,
     vec::Vec,
 };
-use core::{ffi::CStr, iter};
+use core::{any::Any, ffi::CStr, fmt::Write, iter};
 
-use axfs_ng_vfs::{Filesystem, NodeType, VfsError, VfsResult};
-use axtask::{AxTaskRef, WeakAxTaskRef, current};
-use indoc::indoc;
+use axfs_ng_vfs::{DeviceId, Filesystem, NodeFlags, NodeType, VfsError, VfsResult};
+use axhal::paging::MappingFlags;
+use axtask::{AxTaskRef, TaskState, WeakAxTaskRef, current};
+use linux_raw_sys::general::RLIMIT_SIGPENDING;
+use memory_addr::{PAGE_SIZE_4K, VirtAddr};
 use starry_core::{
-    task::{AsThread, TaskStat, get_task, tasks},
+    mm::{
+        HPAGE_SIZE, MemInfo, OOM_SCORE_ADJ_MAX, OOM_SCORE_ADJ_MIN, PageMapCounts, ProcessMemory,
+        Vma, VmaUsage, hugepages_total, kpageflags, max_pfn, may_access_memory, may_read_pfns,
+        oom_score, pagemap_entries, read_process_memory, set_hugepages_total, swap_areas,
+        vm_events, vmas, write_process_memory,
+    },
+    task::{AsThread, ProcessData, TaskStat, get_task, queued_signal_count, tasks},
     vfs::{
         DirMaker, DirMapping, NodeOpsMux, RwFile, SimpleDir, SimpleDirOps, SimpleFile,
         SimpleFileOperation, SimpleFs,
     },
 };
 use starry_process::Process;
+use starry_signal::{SignalDisposition, SignalSet, Signo};
+
+use crate::{
+    coredump::{core_pattern, set_core_pattern},
+    file::FD_TABLE,
+    vfs::{Device, DeviceOps},
+};
+
+/// Generates `/proc/meminfo`.
+fn meminfo() -> String {
+    let info = MemInfo::collect();
+    let kb = |bytes: usize| bytes / 1024;
+    let fields = [
+        ("MemTotal", kb(info.total)),
+        ("MemFree", kb(info.free)),
+        ("MemAvailable", kb(info.available)),
+        ("Buffers", 0),
+        ("Cached", kb(info.cached)),
+        ("SwapCached", 0),
+        ("Active", kb(info.anon + info.cached)),
+        ("Inactive", 0),
+        ("Active(anon)", kb(info.anon)),
+        ("Inactive(anon)", 0),
+        ("Active(file)", kb(info.cached)),
+        ("Inactive(file)", 0),
+        ("Unevictable", 0),
+        ("Mlocked", 0),
+        ("SwapTotal", kb(info.swap_total)),
+        ("SwapFree", kb(info.swap_free)),
+        ("Dirty", 0),
+        ("Writeback", 0),
+        ("AnonPages", kb(info.anon)),
+        ("Mapped", kb(info.mapped)),
+        ("Shmem", kb(info.shmem)),
+        ("KReclaimable", 0),
+        ("Slab", kb(info.slab)),
+        ("SReclaimable", 0),
+        ("SUnreclaim", kb(info.slab)),
+        ("KernelStack", kb(info.kernel_stack)),
+        ("PageTables", kb(info.page_tables)),
+        ("CommitLimit", kb(info.total / 2 + info.swap_total)),
+        ("Committed_AS", kb(info.committed)),
+        ("AnonHugePages", kb(info.anon_huge)),
+        ("ShmemHugePages", 0),
+        ("FileHugePages", 0),
+    ];
+
+    let mut result = String::new();
+    for (name, value) in fields {
+        writeln!(result, "{:<16}{value:>8} kB", format!("{name}:")).unwrap();
+    }
+    for (name, value) in [
+        ("HugePages_Total", info.hugetlb_total / HPAGE_SIZE),
+        ("HugePages_Free", info.hugetlb_free / HPAGE_SIZE),
+        ("HugePages_Rsvd", 0),
+        ("HugePages_Surp", 0),
+    ] {
+        writeln!(result, "{:<16}{value:>8}", format!("{name}:")).unwrap();
+    }
+    writeln!(result, "{:<16}{:>8} kB", "Hugepagesize:", kb(HPAGE_SIZE)).unwrap();
+    writeln!(result, "{:<16}{:>8} kB", "Hugetlb:", kb(info.hugetlb_total)).unwrap();
+    result
+}
 
-use crate::file::FD_TABLE;
-
-const DUMMY_MEMINFO: &str = indoc! {"
-    MemTotal:       32536204 kB
-    MemFree:         5506524 kB
-    MemAvailable:   18768344 kB
-    Buffers:            3264 kB
-    Cached:         14454588 kB
-    SwapCached:            0 kB
-    Active:         18229700 kB
-    Inactive:        6540624 kB
-    Active(anon):   11380224 kB
-    Inactive(anon):        0 kB
-    Active(file):    6849476 kB
-    Inactive(file):  6540624 kB
-    Unevictable:      930088 kB
-    Mlocked:            1136 kB
-    SwapTotal:       4194300 kB
-    SwapFree:        4194300 kB
-    Zswap:                 0 kB
-    Zswapped:              0 kB
-    Dirty:             47952 kB
-    Writeback:             0 kB
-    AnonPages:      10992512 kB
-    Mapped:          1361184 kB
-    Shmem:           1068056 kB
-    KReclaimable:     341440 kB
-    Slab:             628996 kB
-    SReclaimable:     341440 kB
-    SUnreclaim:       287556 kB
-    KernelStack:       28704 kB
-    PageTables:        85308 kB
-    SecPageTables:      2084 kB
-    NFS_Unstable:          0 kB
-    Bounce:                0 kB
-    WritebackTmp:          0 kB
-    CommitLimit:    20462400 kB
-    Committed_AS:   45105316 kB
-    VmallocTotal:   34359738367 kB
-    VmallocUsed:      205924 kB
-    VmallocChunk:          0 kB
-    Percpu:            23840 kB
-    HardwareCorrupted:     0 kB
-    AnonHugePages:   1417216 kB
-    ShmemHugePages:        0 kB
-    ShmemPmdMapped:        0 kB
-    FileHugePages:    477184 kB
-    FilePmdMapped:    288768 kB
-    CmaTotal:              0 kB
-    CmaFree:               0 kB
-    Unaccepted:            0 kB
-    HugePages_Total:       0
-    HugePages_Free:        0
-    HugePages_Rsvd:        0
-    HugePages_Surp:        0
-    Hugepagesize:       2048 kB
-    Hugetlb:               0 kB
-    DirectMap4k:     1739900 kB
-    DirectMap2M:    31492096 kB
-    DirectMap1G:     1048576 kB
-"};
+/// Generates `/proc/vmstat`.
+fn vmstat() -> String {
+    let info = MemInfo::collect();
+    let pages = |bytes: usize| bytes / PAGE_SIZE_4K;
+    let counters = [
+        ("nr_free_pages", pages(info.free)),
+        ("nr_anon_pages", pages(info.anon)),
+        ("nr_mapped", pages(info.mapped)),
+        ("nr_file_pages", pages(info.cached)),
+        ("nr_shmem", pages(info.shmem)),
+        ("nr_slab_unreclaimable", pages(info.slab)),
+        ("nr_page_table_pages", pages(info.page_tables)),
+        ("nr_kernel_stack", info.kernel_stack / 1024),
+        ("nr_anon_transparent_hugepages", info.anon_huge / HPAGE_SIZE),
+        ("nr_swap_pages", pages(info.swap_free)),
+    ];
+
+    let mut result = String::new();
+    for (name, value) in counters.into_iter().chain(vm_events()) {
+        writeln!(result, "{name} {value}").unwrap();
+    }
+    result
+}
 
 pub fn new_procfs() -> Filesystem {
     SimpleFs::new_with("proc".into(), 0x9fa0, builder)
@@ -127,22 +160,397 @@ impl SimpleDirOps for ProcessTaskDir {
     }
 }
 
-#[rustfmt::skip]
+/// Packs `set` into the mask of signal bits /proc/[pid]/status shows.
+fn sigset_mask(set: &SignalSet) -> u64 {
+    (1..=64)
+        .filter_map(Signo::from_repr)
+        .filter(|signo| set.has(*signo))
+        .fold(0, |mask, signo| mask | (1 << (signo as u32 - 1)))
+}
+
 fn task_status(task: &AxTaskRef) -> String {
+    let thr = task.as_thread();
+    let proc_data = &thr.proc_data;
+    let proc = &proc_data.proc;
+    let mem = ProcessMemory::measure(proc_data);
+    let state = match task.state() {
+        TaskState::Running | TaskState::Ready | TaskState::Blocked
+            if proc_data.stop.is_stopped() =>
+        {
+            "T (stopped)"
+        }
+        TaskState::Running | TaskState::Ready => "R (running)",
+        TaskState::Blocked => "S (sleeping)",
+        TaskState::Exited => "Z (zombie)",
+    };
+    let (ignored, caught) = {
+        let actions = proc_data.signal.actions.lock();
+        (1..=64)
+            .filter_map(Signo::from_repr)
+            .fold((0u64, 0u64), |(ignored, caught), signo| {
+                let bit = 1 << (signo as u32 - 1);
+                match actions[signo].disposition {
+                    SignalDisposition::Default => (ignored, caught),
+                    SignalDisposition::Ignore => (ignored | bit, caught),
+                    SignalDisposition::Handler(_) => (ignored, caught | bit),
+                }
+            })
+    };
+
+    let mut out = String::new();
+    writeln!(out, "Name:\t{}", task.name()).unwrap();
+    writeln!(out, "Umask:\t{:04o}", proc_data.umask()).unwrap();
+    writeln!(out, "State:\t{state}").unwrap();
+    writeln!(out, "Tgid:\t{}", proc.pid()).unwrap();
+    writeln!(out, "Pid:\t{}", task.id().as_u64()).unwrap();
+    writeln!(
+        out,
+        "PPid:\t{}",
+        proc.parent().map_or(0, |parent| parent.pid())
+    )
+    .unwrap();
+    writeln!(out, "TracerPid:\t0").unwrap();
+    // Every task runs as root.
+    writeln!(out, "Uid:\t0\t0\t0\t0").unwrap();
+    writeln!(out, "Gid:\t0\t0\t0\t0").unwrap();
+    for (name, value) in [
+        ("VmPeak", proc_data.update_vm_peak(mem.size) / 1024),
+        ("VmSize", mem.size / 1024),
+        ("VmLck", 0),
+        ("VmPin", 0),
+        ("VmHWM", proc_data.self_rusage().maxrss),
+        ("VmRSS", mem.rss() / 1024),
+        ("RssAnon", mem.rss_anon / 1024),
+        ("RssFile", mem.rss_file / 1024),
+        ("RssShmem", mem.rss_shmem / 1024),
+        ("VmData", mem.data / 1024),
+        ("VmStk", mem.stack / 1024),
+        ("VmExe", mem.exe / 1024),
+        ("VmLib", mem.lib / 1024),
+        ("VmPTE", mem.pte / 1024),
+        ("VmSwap", mem.swap / 1024),
+    ] {
+        writeln!(out, "{name}:\t{value:>8} kB").unwrap();
+    }
+    writeln!(out, "Threads:\t{}", proc.threads().len()).unwrap();
+    writeln!(
+        out,
+        "SigQ:\t{}/{}",
+        queued_signal_count(),
+        proc_data.rlim.read()[RLIMIT_SIGPENDING].current
+    )
+    .unwrap();
+    writeln!(out, "SigPnd:\t{:016x}", sigset_mask(&thr.signal.pending())).unwrap();
+    writeln!(
+        out,
+        "ShdPnd:\t{:016x}",
+        sigset_mask(&proc_data.signal.pending())
+    )
+    .unwrap();
+    writeln!(out, "SigBlk:\t{:016x}", sigset_mask(&thr.signal.blocked())).unwrap();
+    writeln!(out, "SigIgn:\t{ignored:016x}").unwrap();
+    writeln!(out, "SigCgt:\t{caught:016x}").unwrap();
+    writeln!(out, "Cpus_allowed:\t1").unwrap();
+    writeln!(out, "Cpus_allowed_list:\t0").unwrap();
+    writeln!(out, "Mems_allowed:\t1").unwrap();
+    writeln!(out, "Mems_allowed_list:\t0").unwrap();
+    out
+}
+
+/// Generates /proc/[pid]/statm, in pages.
+fn task_statm(task: &AxTaskRef) -> String {
+    let mem = ProcessMemory::measure(&task.as_thread().proc_data);
     format!(
-        "Tgid:\t{}\n\
-        Pid:\t{}\n\
-        Uid:\t0 0 0 0\n\
-        Gid:\t0 0 0 0\n\
-        Cpus_allowed:\t1\n\
-        Cpus_allowed_list:\t0\n\
-        Mems_allowed:\t1\n\
-        Mems_allowed_list:\t0",
-        task.as_thread().proc_data.proc.pid(),
-        task.id().as_u64()
+        "{} {} {} {} 0 {} 0\n",
+        mem.size / PAGE_SIZE_4K,
+        mem.rss() / PAGE_SIZE_4K,
+        (mem.rss_file + mem.rss_shmem) / PAGE_SIZE_4K,
+        mem.exe / PAGE_SIZE_4K,
+        (mem.data + mem.stack) / PAGE_SIZE_4K,
     )
 }
 
+/// Writes the line of /proc/[pid]/maps for `vma`, which also heads its entry
+/// in /proc/[pid]/smaps.
+fn write_vma_header(out: &mut String, vma: &Vma) {
+    /// The column the name starts at
+    const NAME_COLUMN: usize = 73;
+
+    let line_start = out.len();
+    let dev = vma.device;
+    let major = ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0xfff);
+    let minor = ((dev >> 12) & 0xffff_ff00) | (dev & 0xff);
+    let perm = |flag, c| if vma.flags.contains(flag) { c } else { '-' };
+    let _ = write!(
+        out,
+        "{:08x}-{:08x} {}{}{}{} {:08x} {:02x}:{:02x} {} ",
+        vma.start.as_usize(),
+        vma.end.as_usize(),
+        perm(MappingFlags::READ, 'r'),
+        perm(MappingFlags::WRITE, 'w'),
+        perm(MappingFlags::EXECUTE, 'x'),
+        if vma.shared { 's' } else { 'p' },
+        vma.offset,
+        major,
+        minor,
+        vma.inode,
+    );
+    if !vma.name.is_empty() {
+        let width = out.len() - line_start;
+        out.extend(iter::repeat_n(' ', (NAME_COLUMN - 1).saturating_sub(width)));
+        out.push(' ');
+        out.push_str(&vma.name);
+    }
+    out.push('\n');
+}
+
+/// Writes the memory usage fields shared by /proc/[pid]/smaps and
+/// /proc/[pid]/smaps_rollup.
+fn write_vma_usage(out: &mut String, usage: &VmaUsage) {
+    for (name, bytes) in [
+        ("Rss:", usage.rss),
+        ("Pss:", usage.pss()),
+        ("Pss_Dirty:", usage.pss_dirty()),
+        ("Shared_Clean:", usage.shared_clean),
+        ("Shared_Dirty:", usage.shared_dirty),
+        ("Private_Clean:", usage.private_clean),
+        ("Private_Dirty:", usage.private_dirty),
+        ("Referenced:", usage.rss),
+        ("Anonymous:", usage.anonymous),
+        ("KSM:", 0),
+        ("LazyFree:", 0),
+        ("AnonHugePages:", usage.anon_huge),
+        ("ShmemPmdMapped:", 0),
+        ("FilePmdMapped:", 0),
+        ("Shared_Hugetlb:", 0),
+        ("Private_Hugetlb:", 0),
+        ("Swap:", usage.swap),
+        ("SwapPss:", usage.swap_pss()),
+        ("Locked:", 0),
+    ] {
+        let _ = writeln!(out, "{name:<16}{:>8} kB", bytes / 1024);
+    }
+}
+
+fn task_maps(task: &AxTaskRef) -> String {
+    let proc_data = &task.as_thread().proc_data;
+    let aspace = proc_data.aspace.lock();
+    let mut out = String::new();
+    for vma in vmas(proc_data, &aspace) {
+        write_vma_header(&mut out, &vma);
+    }
+    out
+}
+
+fn task_smaps(task: &AxTaskRef) -> String {
+    let proc_data = &task.as_thread().proc_data;
+    let counts = PageMapCounts::collect_mapped(proc_data, 0..usize::MAX);
+    let aspace = proc_data.aspace.lock();
+    let mut out = String::new();
+    for vma in vmas(proc_data, &aspace) {
+        let usage = VmaUsage::measure(proc_data, &aspace, &vma, &counts);
+        write_vma_header(&mut out, &vma);
+        for (name, bytes) in [
+            ("Size:", vma.end - vma.start),
+            ("KernelPageSize:", PAGE_SIZE_4K),
+            ("MMUPageSize:", PAGE_SIZE_4K),
+        ] {
+            let _ = writeln!(out, "{name:<16}{:>8} kB", bytes / 1024);
+        }
+        write_vma_usage(&mut out, &usage);
+        let thp_eligible = proc_data.thp.lock().is_eligible(vma.start);
+        let _ = writeln!(out, "{:<16}{:>8}", "THPeligible:", thp_eligible as u8);
+        out.push_str("VmFlags:");
+        for (flag, name) in [
+            (MappingFlags::READ, " rd"),
+            (MappingFlags::WRITE, " wr"),
+            (MappingFlags::EXECUTE, " ex"),
+        ] {
+            if vma.flags.contains(flag) {
+                out.push_str(name);
+            }
+        }
+        if vma.shared {
+            out.push_str(" sh");
+        }
+        out.push_str(" mr mw me\n");
+    }
+    out
+}
+
+fn task_smaps_rollup(task: &AxTaskRef) -> String {
+    let proc_data = &task.as_thread().proc_data;
+    let counts = PageMapCounts::collect_mapped(proc_data, 0..usize::MAX);
+    let aspace = proc_data.aspace.lock();
+    let vmas = vmas(proc_data, &aspace);
+    let mut usage = VmaUsage::default();
+    for vma in &vmas {
+        usage.add(&VmaUsage::measure(proc_data, &aspace, vma, &counts));
+    }
+    let mut out = String::new();
+    if let (Some(first), Some(last)) = (vmas.first(), vmas.last()) {
+        let mut rollup = first.clone();
+        rollup.end = last.end;
+        rollup.flags = MappingFlags::empty();
+        rollup.shared = false;
+        rollup.offset = 0;
+        rollup.device = 0;
+        rollup.inode = 0;
+        rollup.name = "[rollup]".to_string();
+        write_vma_header(&mut out, &rollup);
+    }
+    write_vma_usage(&mut out, &usage);
+    out
+}
+
+/// The /proc/[pid]/mem file, whose offsets are virtual addresses of the
+/// process.
+struct ProcessMem(Weak<ProcessData>);
+
+impl ProcessMem {
+    fn process(&self) -> VfsResult<Arc<ProcessData>> {
+        let proc_data = self.0.upgrade().ok_or(VfsError::NoSuchProcess)?;
+        may_access_memory(&proc_data)?;
+        Ok(proc_data)
+    }
+}
+
+impl DeviceOps for ProcessMem {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let proc_data = self.process()?;
+        read_process_memory(&proc_data, VirtAddr::from(offset as usize), buf)
+            .map_err(|_| VfsError::Io)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        let proc_data = self.process()?;
+        // Like ptrace, writes go through to pages that are not writable, so
+        // that debuggers can set breakpoints.
+        write_process_memory(&proc_data, VirtAddr::from(offset as usize), buf, true)
+            .map_err(|_| VfsError::Io)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+/// Checks that a read of a file made of 64-bit entries is aligned to them and
+/// returns the index of the first entry and the number of entries.
+fn entry_range(buf: &[u8], offset: u64) -> VfsResult<(usize, usize)> {
+    if offset % 8 != 0 || buf.len() % 8 != 0 {
+        return Err(VfsError::InvalidInput);
+    }
+    Ok(((offset / 8) as usize, buf.len() / 8))
+}
+
+fn write_entries(buf: &mut [u8], entries: impl IntoIterator<Item = u64>) -> usize {
+    let mut len = 0;
+    for (chunk, entry) in buf.chunks_exact_mut(8).zip(entries) {
+        chunk.copy_from_slice(&entry.to_ne_bytes());
+        len += 8;
+    }
+    len
+}
+
+/// The /proc/[pid]/pagemap file, with a 64-bit entry for each virtual page of
+/// the process.
+struct ProcessPagemap(Weak<ProcessData>);
+
+impl DeviceOps for ProcessPagemap {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let proc_data = self.0.upgrade().ok_or(VfsError::NoSuchProcess)?;
+        may_access_memory(&proc_data)?;
+        let (page, count) = entry_range(buf, offset)?;
+        let end = proc_data.aspace.lock().end().as_usize();
+        let Some(start) = page.checked_mul(PAGE_SIZE_4K).filter(|start| *start < end) else {
+            return Ok(0);
+        };
+        let count = count.min((end - start) / PAGE_SIZE_4K);
+        let counts = PageMapCounts::collect_mapped(&proc_data, start..start + count * PAGE_SIZE_4K);
+        let entries = pagemap_entries(
+            &proc_data,
+            VirtAddr::from(start),
+            count,
+            &counts,
+            may_read_pfns(&proc_data),
+        );
+        Ok(write_entries(buf, entries))
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(VfsError::BadFileDescriptor)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+/// The /proc/kpagecount and /proc/kpageflags files, with a 64-bit entry for
+/// each page frame of physical memory.
+struct KernelPages(fn(&PageMapCounts, usize) -> u64);
+
+impl DeviceOps for KernelPages {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let (pfn, count) = entry_range(buf, offset)?;
+        let count = count.min(max_pfn().saturating_sub(pfn));
+        if count == 0 {
+            return Ok(0);
+        }
+        let counts = PageMapCounts::collect_for(|it| (pfn..pfn + count).contains(&it));
+        Ok(write_entries(
+            buf,
+            (pfn..pfn + count).map(|pfn| (self.0)(&counts, pfn)),
+        ))
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(VfsError::BadFileDescriptor)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+/// Handles a write to /proc/[pid]/clear_refs.
+///
+/// Only resetting the soft-dirty bits and the peak RSS are supported; the
+/// referenced bits that modes 1 to 3 clear aren't tracked, so those are
+/// rejected rather than silently ignored.
+fn clear_refs(proc_data: &ProcessData, data: &[u8]) -> VfsResult<()> {
+    let mode = str::from_utf8(data)
+        .ok()
+        .and_then(|it| it.trim().parse::<u32>().ok())
+        .ok_or(VfsError::InvalidInput)?;
+    match mode {
+        4 => {
+            let mut aspace = proc_data.aspace.lock();
+            proc_data
+                .soft_dirty
+                .lock()
+                .clear_refs(proc_data, &mut aspace)?;
+            proc_data.pkeys.lock().apply_all(proc_data, &mut aspace);
+        }
+        5 => proc_data.reset_maxrss(),
+        _ => return Err(VfsError::InvalidInput),
+    }
+    Ok(())
+}
+
 /// The /proc/[pid]/fd directory
 struct ThreadFdDir {
     fs: Arc<SimpleFs>,
@@ -195,9 +603,16 @@ impl SimpleDirOps for ThreadDir {
             [
                 "stat",
                 "status",
+                "statm",
+                "oom_score",
                 "oom_score_adj",
                 "task",
                 "maps",
+                "smaps",
+                "smaps_rollup",
+                "mem",
+                "pagemap",
+                "clear_refs",
                 "mounts",
                 "cmdline",
                 "comm",
@@ -218,19 +633,29 @@ impl SimpleDirOps for ThreadDir {
             })
             .into(),
             "status" => SimpleFile::new_regular(fs, move || Ok(task_status(&task))).into(),
+            "statm" => SimpleFile::new_regular(fs, move || Ok(task_statm(&task))).into(),
+            "oom_score" => SimpleFile::new_regular(fs, move || {
+                Ok(format!("{}\n", oom_score(&task.as_thread().proc_data)))
+            })
+            .into(),
             "oom_score_adj" => SimpleFile::new_regular(
                 fs,
                 RwFile::new(move |req| match req {
                     SimpleFileOperation::Read => Ok(Some(
-                        task.as_thread().oom_score_adj().to_string().into_bytes(),
+                        task.as_thread()
+                            .proc_data
+                            .oom_score_adj()
+                            .to_string()
+                            .into_bytes(),
                     )),
                     SimpleFileOperation::Write(data) => {
                         if !data.is_empty() {
                             let value = str::from_utf8(data)
                                 .ok()
-                                .and_then(|it| it.parse::<i32>().ok())
+                                .and_then(|it| it.trim().parse::<i32>().ok())
+                                .filter(|it| (OOM_SCORE_ADJ_MIN..=OOM_SCORE_ADJ_MAX).contains(it))
                                 .ok_or(VfsError::InvalidInput)?;
-                            task.as_thread().set_oom_score_adj(value);
+                            task.as_thread().proc_data.set_oom_score_adj(value);
                         }
                         Ok(None)
                     }
@@ -245,14 +670,45 @@ impl SimpleDirOps for ThreadDir {
                 }),
             )
             .into(),
-            "maps" => SimpleFile::new_regular(fs, move || {
-                Ok(indoc! {"
-                    7f000000-7f001000 r--p 00000000 00:00 0          [vdso]
-                    7f001000-7f003000 r-xp 00001000 00:00 0          [vdso]
-                    7f003000-7f005000 r--p 00003000 00:00 0          [vdso]
-                    7f005000-7f007000 rw-p 00005000 00:00 0          [vdso]
-                "})
-            })
+            "maps" => SimpleFile::new_regular(fs, move || Ok(task_maps(&task))).into(),
+            "smaps" => SimpleFile::new_regular(fs, move || Ok(task_smaps(&task))).into(),
+            "smaps_rollup" => {
+                SimpleFile::new_regular(fs, move || Ok(task_smaps_rollup(&task))).into()
+            }
+            "mem" => {
+                let proc_data = &task.as_thread().proc_data;
+                may_access_memory(proc_data)?;
+                Device::new(
+                    fs,
+                    NodeType::RegularFile,
+                    DeviceId::new(0, 0),
+                    Arc::new(ProcessMem(Arc::downgrade(proc_data))),
+                )
+                .into()
+            }
+            "pagemap" => {
+                let proc_data = &task.as_thread().proc_data;
+                may_access_memory(proc_data)?;
+                Device::new(
+                    fs,
+                    NodeType::RegularFile,
+                    DeviceId::new(0, 0),
+                    Arc::new(ProcessPagemap(Arc::downgrade(proc_data))),
+                )
+                .into()
+            }
+            "clear_refs" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(move |req| match req {
+                    SimpleFileOperation::Read => Ok(Some("")),
+                    SimpleFileOperation::Write(data) => {
+                        if !data.is_empty() {
+                            clear_refs(&task.as_thread().proc_data, data)?;
+                        }
+                        Ok(None)
+                    }
+                }),
+            )
             .into(),
             "mounts" => SimpleFile::new_regular(fs, move || {
                 Ok("proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n")
@@ -362,13 +818,47 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     );
     root.add(
         "meminfo",
-        SimpleFile::new_regular(fs.clone(), || Ok(DUMMY_MEMINFO)),
+        SimpleFile::new_regular(fs.clone(), || Ok(meminfo())),
+    );
+    root.add(
+        "kpagecount",
+        Device::new(
+            fs.clone(),
+            NodeType::RegularFile,
+            DeviceId::new(0, 0),
+            Arc::new(KernelPages(|counts, pfn| counts.count(pfn) as u64)),
+        ),
     );
     root.add(
-        "meminfo2",
+        "kpageflags",
+        Device::new(
+            fs.clone(),
+            NodeType::RegularFile,
+            DeviceId::new(0, 0),
+            Arc::new(KernelPages(kpageflags)),
+        ),
+    );
+    root.add(
+        "vmstat",
+        SimpleFile::new_regular(fs.clone(), || Ok(vmstat())),
+    );
+    root.add(
+        "swaps",
         SimpleFile::new_regular(fs.clone(), || {
-            let allocator = axalloc::global_allocator();
-            Ok(format!("{:?}\n", allocator.usage_stats()))
+            let mut result = String::from("Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority\n");
+            for area in swap_areas() {
+                writeln!(
+                    result,
+                    "{:<40}{:<16}{:<16}{:<16}{}",
+                    area.path,
+                    if area.is_device { "partition" } else { "file" },
+                    area.pages * PAGE_SIZE_4K / 1024,
+                    area.used * PAGE_SIZE_4K / 1024,
+                    area.priority
+                )
+                .unwrap();
+            }
+            Ok(result)
         }),
     );
     root.add(
@@ -395,6 +885,25 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         sys.add("kernel", {
             let mut kernel = DirMapping::new();
 
+            kernel.add(
+                "core_pattern",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => {
+                            Ok(Some(format!("{}\n", core_pattern()).into_bytes()))
+                        }
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let pattern =
+                                    str::from_utf8(data).map_err(|_| VfsError::InvalidInput)?;
+                                set_core_pattern(pattern.strip_suffix('\n').unwrap_or(pattern))?;
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
             kernel.add(
                 "pid_max",
                 SimpleFile::new_regular(fs.clone(), || Ok("32768\n")),
@@ -403,6 +912,34 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             SimpleDir::new_maker(fs.clone(), Arc::new(kernel))
         });
 
+        sys.add("vm", {
+            let mut vm = DirMapping::new();
+
+            vm.add(
+                "nr_hugepages",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => {
+                            Ok(Some(format!("{}\n", hugepages_total()).into_bytes()))
+                        }
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let value = str::from_utf8(data)
+                                    .ok()
+                                    .and_then(|it| it.trim().parse::<usize>().ok())
+                                    .ok_or(VfsError::InvalidInput)?;
+                                set_hugepages_total(value);
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+
+            SimpleDir::new_maker(fs.clone(), Arc::new(vm))
+        });
+
         SimpleDir::new_maker(fs.clone(), Arc::new(sys))
     });
 
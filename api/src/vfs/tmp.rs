@@ -1,16 +1,30 @@
-use alloc::{borrow::ToOwned, string::String, sync::Arc};
+use alloc::{
+    borrow::ToOwned,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::{any::Any, borrow::Borrow, cmp::Ordering, task::Context, time::Duration};
 
 use axfs_ng_vfs::{
     DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
-    FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType,
-    Reference, StatFs, VfsError, VfsResult, WeakDirEntry,
+    FilesystemOps, Location, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission,
+    NodeType, Reference, StatFs, VfsError, VfsResult, WeakDirEntry,
+};
+use axhal::paging::{MappingFlags, PageSize};
+use axmm::{
+    AddrSpace,
+    backend::{Backend, SharedPages},
 };
 use axpoll::{IoEvents, Pollable};
 use axsync::Mutex;
 use hashbrown::HashMap;
+use linux_raw_sys::general::{
+    F_SEAL_EXEC, F_SEAL_FUTURE_WRITE, F_SEAL_GROW, F_SEAL_SEAL, F_SEAL_SHRINK, F_SEAL_WRITE,
+};
+use memory_addr::{MemoryAddr, VirtAddr};
 use slab::Slab;
-use starry_core::vfs::dummy_stat_fs;
+use starry_core::{mm::HugePageReservation, vfs::dummy_stat_fs};
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 struct FileName(String);
@@ -53,15 +67,28 @@ impl Borrow<str> for FileName {
 pub struct MemoryFs {
     inodes: Mutex<Slab<Arc<Inode>>>,
     root: Mutex<Option<DirEntry>>,
+    /// The page size files are mapped with, if this is a hugetlbfs
+    huge_page_size: Option<PageSize>,
 }
 
 impl MemoryFs {
     /// Creates a new empty memory filesystem.
     #[allow(clippy::new_ret_no_self)]
     pub fn new() -> Filesystem {
+        Self::new_with(None)
+    }
+
+    /// Creates a new empty hugetlbfs whose files are mapped with huge pages of
+    /// `page_size`.
+    pub fn new_hugetlbfs(page_size: PageSize) -> Filesystem {
+        Self::new_with(Some(page_size))
+    }
+
+    fn new_with(huge_page_size: Option<PageSize>) -> Filesystem {
         let fs = Arc::new(Self {
             inodes: Mutex::new(Slab::new()),
             root: Mutex::default(),
+            huge_page_size,
         });
         let root_ino = Inode::new(
             &fs,
@@ -83,7 +110,11 @@ impl MemoryFs {
 
 impl FilesystemOps for MemoryFs {
     fn name(&self) -> &str {
-        "tmpfs"
+        if self.huge_page_size.is_some() {
+            "hugetlbfs"
+        } else {
+            "tmpfs"
+        }
     }
 
     fn root_dir(&self) -> DirEntry {
@@ -91,7 +122,11 @@ impl FilesystemOps for MemoryFs {
     }
 
     fn stat(&self) -> VfsResult<StatFs> {
-        Ok(dummy_stat_fs(0x01021994))
+        if self.huge_page_size.is_some() {
+            Ok(dummy_stat_fs(0x958458f6))
+        } else {
+            Ok(dummy_stat_fs(0x01021994))
+        }
     }
 }
 
@@ -112,6 +147,18 @@ struct FileContent {
     /// content management to page cache.
     length: Mutex<u64>,
     symlink: Mutex<Option<String>>,
+    /// The huge pages shared by all mappings of a hugetlbfs file.
+    huge_pages: Mutex<Option<HugetlbPages>>,
+    /// The `F_SEAL_*` seals of the file.
+    seals: Mutex<u32>,
+    /// Shared mappings created writable, which have to be gone before the file
+    /// can be sealed against writes.
+    writable_mappings: Mutex<Vec<(Weak<Mutex<AddrSpace>>, VirtAddr)>>,
+}
+
+struct HugetlbPages {
+    pages: Arc<SharedPages>,
+    reservation: HugePageReservation,
 }
 
 #[derive(Default)]
@@ -158,7 +205,11 @@ impl Inode {
         };
         let content = match node_type {
             NodeType::Directory => NodeContent::Dir(DirContent::default()),
-            _ => NodeContent::File(FileContent::default()),
+            // Only memfds created with `MFD_ALLOW_SEALING` can be sealed.
+            _ => NodeContent::File(FileContent {
+                seals: Mutex::new(F_SEAL_SEAL),
+                ..Default::default()
+            }),
         };
         let result = Arc::new(Self {
             ino,
@@ -268,6 +319,12 @@ impl NodeOps for MemoryNode {
     fn update_metadata(&self, update: MetadataUpdate) -> VfsResult<()> {
         let mut metadata = self.inode.metadata.lock();
         if let Some(mode) = update.mode {
+            if let NodeContent::File(file) = &self.inode.content
+                && *file.seals.lock() & F_SEAL_EXEC != 0
+                && (mode.bits() ^ metadata.mode.bits()) & 0o111 != 0
+            {
+                return Err(VfsError::OperationNotPermitted);
+            }
             metadata.mode = mode;
         }
         if let Some((uid, gid)) = update.owner {
@@ -321,7 +378,15 @@ impl FileNodeOps for MemoryNode {
     }
 
     fn set_len(&self, len: u64) -> VfsResult<()> {
-        *self.inode.as_file()?.length.lock() = len;
+        let file = self.inode.as_file()?;
+        let seals = *file.seals.lock();
+        let mut length = file.length.lock();
+        if (len < *length && seals & F_SEAL_SHRINK != 0)
+            || (len > *length && seals & F_SEAL_GROW != 0)
+        {
+            return Err(VfsError::OperationNotPermitted);
+        }
+        *length = len;
         Ok(())
     }
 
@@ -451,6 +516,134 @@ impl DirNodeOps for MemoryNode {
     }
 }
 
+/// Returns the huge page size files at `loc` are mapped with, or `None` if it
+/// is not on a hugetlbfs.
+pub fn hugetlbfs_page_size(loc: &Location) -> Option<PageSize> {
+    loc.entry()
+        .downcast::<MemoryNode>()
+        .ok()
+        .and_then(|node| node.fs.huge_page_size)
+}
+
+/// Returns the huge pages backing the hugetlbfs file at `loc`.
+///
+/// The pages are allocated from the huge page pool on first use, covering at
+/// least `size` bytes and extending the file accordingly.
+pub fn hugetlbfs_pages(loc: &Location, size: usize) -> VfsResult<Arc<SharedPages>> {
+    let node = loc.entry().downcast::<MemoryNode>()?;
+    let page_size = node.fs.huge_page_size.ok_or(VfsError::InvalidInput)?;
+    let file = node.inode.as_file()?;
+
+    let mut huge_pages = file.huge_pages.lock();
+    if huge_pages.is_none() {
+        let mut length = file.length.lock();
+        let size = size.max(*length as usize).align_up(page_size);
+        *huge_pages = Some(HugetlbPages {
+            reservation: HugePageReservation::new(size)?,
+            pages: Arc::new(SharedPages::new(size, page_size)?),
+        });
+        *length = (*length).max(size as u64);
+    }
+    let huge_pages = huge_pages.as_ref().unwrap();
+    if size > huge_pages.reservation.size() {
+        return Err(VfsError::InvalidInput);
+    }
+    Ok(huge_pages.pages.clone())
+}
+
+/// Returns the `F_SEAL_*` seals of the file at `loc`.
+///
+/// Fails with [`VfsError::InvalidInput`] if the file is not on a memory
+/// filesystem and can't be sealed at all.
+pub fn file_seals(loc: &Location) -> VfsResult<u32> {
+    let node = loc
+        .entry()
+        .downcast::<MemoryNode>()
+        .map_err(|_| VfsError::InvalidInput)?;
+    Ok(*node.inode.as_file()?.seals.lock())
+}
+
+/// Replaces the seals of a newly created memfd at `loc`.
+pub fn init_file_seals(loc: &Location, seals: u32) -> VfsResult<()> {
+    let node = loc.entry().downcast::<MemoryNode>()?;
+    *node.inode.as_file()?.seals.lock() = seals;
+    Ok(())
+}
+
+/// Adds `seals` to the file at `loc`.
+///
+/// Sealing against writes fails with [`VfsError::ResourceBusy`] while the
+/// file is still mapped shared and writable.
+pub fn add_file_seals(loc: &Location, seals: u32) -> VfsResult<()> {
+    let node = loc
+        .entry()
+        .downcast::<MemoryNode>()
+        .map_err(|_| VfsError::InvalidInput)?;
+    let file = node.inode.as_file()?;
+    if *file.seals.lock() & F_SEAL_SEAL != 0 {
+        return Err(VfsError::OperationNotPermitted);
+    }
+
+    if seals & F_SEAL_WRITE != 0 {
+        // Address spaces are locked one at a time without holding the seals,
+        // since mmap checks the seals with its address space locked.
+        let mappings = file.writable_mappings.lock().clone();
+        let live: Vec<_> = mappings
+            .into_iter()
+            .filter(|(aspace, start)| {
+                aspace.upgrade().is_some_and(|aspace| {
+                    aspace.lock().find_area(*start).is_some_and(|area| {
+                        area.start() == *start
+                            && area.flags().contains(MappingFlags::WRITE)
+                            && matches!(area.backend(), Backend::File(_) | Backend::Shared(_))
+                    })
+                })
+            })
+            .collect();
+        let busy = !live.is_empty();
+        *file.writable_mappings.lock() = live;
+        if busy {
+            return Err(VfsError::ResourceBusy);
+        }
+    }
+
+    let mut current = file.seals.lock();
+    if *current & F_SEAL_SEAL != 0 {
+        return Err(VfsError::OperationNotPermitted);
+    }
+    *current |= seals;
+    Ok(())
+}
+
+/// Checks a new shared mapping of the file at `loc` at `start` in `aspace`
+/// against the seals of the file, remembering it if it is writable.
+pub fn add_shared_mapping(
+    loc: &Location,
+    aspace: &Arc<Mutex<AddrSpace>>,
+    start: VirtAddr,
+    writable: bool,
+) -> VfsResult<()> {
+    if !writable {
+        return Ok(());
+    }
+    let Ok(node) = loc.entry().downcast::<MemoryNode>() else {
+        return Ok(());
+    };
+    let file = node.inode.as_file()?;
+    if *file.seals.lock() & (F_SEAL_WRITE | F_SEAL_FUTURE_WRITE) != 0 {
+        return Err(VfsError::OperationNotPermitted);
+    }
+    let aspace = Arc::downgrade(aspace);
+    let mut mappings = file.writable_mappings.lock();
+    if !mappings
+        .iter()
+        .any(|(other, other_start)| other.ptr_eq(&aspace) && *other_start == start)
+    {
+        mappings.push((aspace, start));
+    }
+    Ok(())
+}
+
 impl Drop for MemoryNode {
     fn drop(&mut self) {
         if let NodeContent::Dir(dir) = &self.inode.content {
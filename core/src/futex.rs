@@ -1,7 +1,6 @@
 //! Futex implementation.
 //! ot
 //! reboot
-//! 
 
 use alloc::{
     collections::vec_deque::VecDeque,
@@ -52,8 +51,22 @@ impl WaitQueue {
         bitset: u32,
         timeout: Option<Duration>,
         condition: impl FnOnce() -> bool,
+    ) -> AxResult<bool> {
+        self.wait_if_queued(bitset, timeout, condition, |_| {})
+    }
+
+    /// Waits if the given condition is met, like [`WaitQueue::wait_if`], and
+    /// passes the waker the task is queued with to `queued` once it is, so
+    /// that the task can be woken alone through [`WaitQueue::wake_waker`].
+    pub fn wait_if_queued(
+        &self,
+        bitset: u32,
+        timeout: Option<Duration>,
+        condition: impl FnOnce() -> bool,
+        queued: impl FnOnce(&Waker),
     ) -> AxResult<bool> {
         let mut condition = Some(condition);
+        let mut queued = Some(queued);
         block_on(interruptible(future::timeout(
             timeout,
             poll_fn(|cx| {
@@ -63,6 +76,10 @@ impl WaitQueue {
                         Poll::Ready(Ok(false))
                     } else {
                         queue.push_back((cx.waker().clone(), bitset));
+                        drop(queue);
+                        if let Some(queued) = queued.take() {
+                            queued(cx.waker());
+                        }
                         Poll::Pending
                     }
                 } else {
@@ -72,6 +89,63 @@ impl WaitQueue {
         )))??
     }
 
+    /// Waits on several wait queues at once if the given condition is met,
+    /// until any of them wakes the task.
+    ///
+    /// The task is queued before the condition is checked, so a wakeup right
+    /// after the check is not missed. Returns the index of the queue that woke
+    /// the task, or `None` if the condition is not met and no actual waiting
+    /// occurs.
+    pub fn wait_any(
+        queues: &[&WaitQueue],
+        timeout: Option<Duration>,
+        condition: impl FnOnce() -> bool,
+    ) -> AxResult<Option<usize>> {
+        let mut condition = Some(condition);
+        let mut waker = None;
+        let result = block_on(interruptible(future::timeout(
+            timeout,
+            poll_fn(|cx| {
+                if let Some(cond) = condition.take() {
+                    for wq in queues {
+                        wq.queue.lock().push_back((cx.waker().clone(), u32::MAX));
+                    }
+                    waker = Some(cx.waker().clone());
+                    if cond() {
+                        Poll::Pending
+                    } else {
+                        Poll::Ready(false)
+                    }
+                } else {
+                    Poll::Ready(true)
+                }
+            }),
+        )));
+
+        // Whatever ended the wait, the task leaves the queues that didn't
+        // wake it. A wakeup that raced with a timeout or a signal is still
+        // reported, as it was consumed.
+        let mut woken = None;
+        if let Some(waker) = waker {
+            for (i, wq) in queues.iter().enumerate() {
+                let mut queue = wq.queue.lock();
+                let len = queue.len();
+                queue.retain(|(w, _)| !w.will_wake(&waker));
+                if queue.len() == len && woken.is_none() {
+                    woken = Some(i);
+                }
+            }
+        }
+        match result {
+            Ok(Ok(false)) => Ok(None),
+            _ if woken.is_some() => Ok(woken),
+            result => {
+                result??;
+                Ok(None)
+            }
+        }
+    }
+
     /// Wakes up at most `count` tasks whose bitset intersects with the given
     /// bitmask.
     pub fn wake(&self, count: usize, mask: u32) -> usize {
@@ -88,6 +162,21 @@ impl WaitQueue {
         woke
     }
 
+    /// Wakes up the task queued with `waker`.
+    ///
+    /// Returns whether it was in the queue.
+    pub fn wake_waker(&self, waker: &Waker) -> bool {
+        let mut queue = self.queue.lock();
+        let len = queue.len();
+        queue.retain(|(w, _)| !w.will_wake(waker));
+        let queued = queue.len() != len;
+        drop(queue);
+        if queued {
+            waker.wake_by_ref();
+        }
+        queued
+    }
+
     /// Checks if the wait queue is empty.
     pub fn is_empty(&self) -> bool {
         self.queue.lock().is_empty()
@@ -168,6 +257,10 @@ pub struct FutexEntry {
 
     /// Used by robust list, indicates if the owner of this futex is dead.
     pub owner_dead: AtomicBool,
+
+    /// The TIDs of the threads waiting to take this priority-inheritance
+    /// futex, in the order they blocked, and the wakers they wait with.
+    pub pi_waiters: Mutex<VecDeque<(u32, Waker)>>,
 }
 
 impl FutexEntry {
@@ -175,8 +268,22 @@ impl FutexEntry {
         Self {
             wq: WaitQueue::new(),
             owner_dead: AtomicBool::new(false),
+            pi_waiters: Mutex::new(VecDeque::new()),
         }
     }
+
+    /// Removes `tid` from the waiters of this priority-inheritance futex.
+    ///
+    /// Returns `false` if it was no longer among them, because the futex was
+    /// handed over to it.
+    pub fn remove_pi_waiter(&self, tid: u32) -> bool {
+        let mut waiters = self.pi_waiters.lock();
+        let Some(index) = waiters.iter().position(|(waiter, _)| *waiter == tid) else {
+            return false;
+        };
+        waiters.remove(index);
+        true
+    }
 }
 
 /// A table mapping memory addresses to futex wait queues.
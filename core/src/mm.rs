@@ -1,6 +1,24 @@
 //! User address space management.
 
-use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+mod access;
+mod hugetlb;
+mod oom;
+mod pagemap;
+mod pkey;
+mod range_map;
+mod secretmem;
+mod swap;
+mod thp;
+mod userfaultfd;
+mod vma;
+mod vmstat;
+
+use alloc::{
+    borrow::ToOwned,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
 use core::{
     ffi::CStr,
     hint::unlikely,
@@ -27,6 +45,33 @@ use ouroboros::self_referencing;
 use starry_vm::{VmError, VmIo, VmResult};
 use uluru::LRUCache;
 
+pub use self::{
+    access::{may_access_memory, read_process_memory, write_process_memory},
+    hugetlb::{
+        HugePageReservation, HugetlbAreas, hugepages_free, hugepages_total, set_hugepages_total,
+    },
+    oom::{
+        OOM_SCORE_ADJ_MAX, OOM_SCORE_ADJ_MIN, can_alloc_page, oom_score, out_of_memory,
+        wait_oom_victim,
+    },
+    pagemap::{SoftDirtyPages, kpageflags, max_pfn, may_read_pfns, pagemap_entries},
+    pkey::{
+        INIT_PKRU, PKEY_DISABLE_ACCESS, PKEY_DISABLE_WRITE, ProtectionKeys, pkey_fault, read_pkru,
+        restore_pkru, write_pkru,
+    },
+    secretmem::{SecretAreas, SecretRegion},
+    swap::{
+        SWAP_CLUSTER_MAX, SwapAreaStat, SwapEntries, reclaim_pages, swap_areas, swap_in_page,
+        swap_in_range, swapoff, swapon, touch_page,
+    },
+    thp::{HPAGE_SIZE, ThpAreas},
+    userfaultfd::{
+        UFFD_PAGEFAULT_FLAG_WP, UFFD_PAGEFAULT_FLAG_WRITE, UserFault, UserFaultCtx, UserFaultMode,
+        UserFaultRanges,
+    },
+    vma::{MappedFile, MappedFiles, PageMapCounts, ProcessMemory, Vma, VmaUsage, vmas},
+    vmstat::{MemInfo, VmEvent, count_vm_event, count_vm_events, vm_events},
+};
 use crate::config::{USER_SPACE_BASE, USER_SPACE_SIZE};
 
 /// Creates a new empty user address space.
@@ -81,17 +126,21 @@ fn mapping_flags(flags: xmas_elf::program::Flags) -> MappingFlags {
 ///
 /// # Arguments
 /// - `uspace`: The address space of the user app.
+/// - `files`: Where the mapped segments are recorded.
 /// - `elf`: The elf file.
 ///
 /// # Returns
 /// - The entry point of the user app.
 fn map_elf<'a>(
     uspace: &mut AddrSpace,
+    files: &mut MappedFiles,
     base: usize,
     entry: &'a ElfCacheEntry,
 ) -> AxResult<ELFParser<'a>> {
     let elf_parser = ELFParser::new(entry.borrow_elf(), base).map_err(|_| AxError::InvalidData)?;
     let cache = entry.borrow_cache();
+    let path = cache.location().absolute_path()?.to_string();
+    let metadata = cache.location().metadata()?;
 
     for ph in elf_parser
         .headers()
@@ -129,6 +178,17 @@ fn map_elf<'a>(
             false,
             backend,
         )?;
+        files.insert(
+            seg_start.align_down_4k(),
+            seg_start.align_down_4k() + seg_align_size,
+            MappedFile {
+                path: path.clone(),
+                offset: ph.offset - seg_pad as u64,
+                device: metadata.device,
+                inode: metadata.inode,
+                location: None,
+            },
+        );
 
         // TDOO: flush the I-cache
     }
@@ -184,7 +244,12 @@ impl ElfLoader {
         Self(LRUCache::new())
     }
 
-    fn load(&mut self, uspace: &mut AddrSpace, path: &str) -> AxResult<LoadResult> {
+    fn load(
+        &mut self,
+        uspace: &mut AddrSpace,
+        files: &mut MappedFiles,
+        path: &str,
+    ) -> AxResult<LoadResult> {
         let loc = FS_CONTEXT.lock().resolve(path)?;
 
         if !self.0.touch(|e| e.borrow_cache().location().ptr_eq(&loc)) {
@@ -199,6 +264,7 @@ impl ElfLoader {
         }
 
         uspace.clear();
+        files.clear();
         map_trampoline(uspace)?;
 
         let entry = self.0.front().unwrap();
@@ -238,9 +304,9 @@ impl ElfLoader {
             (entry, None)
         };
 
-        let elf = map_elf(uspace, crate::config::USER_SPACE_BASE, elf)?;
+        let elf = map_elf(uspace, files, crate::config::USER_SPACE_BASE, elf)?;
         let ldso = ldso
-            .map(|elf| map_elf(uspace, crate::config::USER_INTERP_BASE, elf))
+            .map(|elf| map_elf(uspace, files, crate::config::USER_INTERP_BASE, elf))
             .transpose()?;
 
         let entry = VirtAddr::from_usize(
@@ -268,6 +334,7 @@ pub fn clear_elf_cache() {
 ///
 /// # Arguments
 /// - `uspace`: The address space of the user app.
+/// - `files`: Where the files mapped into the address space are recorded.
 /// - `args`: The arguments of the user app. The first argument is the path of
 ///   the user app.
 /// - `envs`: The environment variables of the user app.
@@ -277,6 +344,7 @@ pub fn clear_elf_cache() {
 /// - The stack pointer of the user app.
 pub fn load_user_app(
     uspace: &mut AddrSpace,
+    files: &mut MappedFiles,
     path: Option<&str>,
     args: &[String],
     envs: &[String],
@@ -290,10 +358,10 @@ pub fn load_user_app(
         let new_args: Vec<String> = iter::once("/bin/sh".to_owned())
             .chain(args.iter().cloned())
             .collect();
-        return load_user_app(uspace, None, &new_args, envs);
+        return load_user_app(uspace, files, None, &new_args, envs);
     }
 
-    let (entry, auxv) = match { ELF_LOADER.lock().load(uspace, path)? } {
+    let (entry, auxv) = match { ELF_LOADER.lock().load(uspace, files, path)? } {
         Ok((entry, auxv)) => (entry, auxv),
         Err(data) => {
             if data.starts_with(b"#!") {
@@ -308,7 +376,7 @@ pub fn load_user_app(
                     .chain(iter::once(path.to_owned()))
                     .chain(args.iter().skip(1).cloned())
                     .collect();
-                return load_user_app(uspace, None, &new_args, envs);
+                return load_user_app(uspace, files, None, &new_args, envs);
             }
             return Err(AxError::InvalidExecutable);
         }
@@ -350,6 +418,133 @@ pub fn load_user_app(
     Ok((entry, user_sp))
 }
 
+/// Reads the auxiliary vector back from the initial stack at `sp` that
+/// [`load_user_app`] set up.
+pub fn read_auxv(uspace: &AddrSpace, sp: VirtAddr) -> AxResult<Vec<u8>> {
+    const WORD: usize = size_of::<usize>();
+    let read_word = |addr: VirtAddr| -> AxResult<usize> {
+        let mut buf = [0; WORD];
+        uspace.read(addr, &mut buf)?;
+        Ok(usize::from_ne_bytes(buf))
+    };
+
+    // Skip argc, the arguments and the environment with their terminators.
+    let argc = read_word(sp)?;
+    let mut addr = sp + (argc + 2) * WORD;
+    while read_word(addr)? != 0 {
+        addr += WORD;
+    }
+    addr += WORD;
+
+    let mut auxv = Vec::new();
+    loop {
+        let key = read_word(addr)?;
+        auxv.extend_from_slice(&key.to_ne_bytes());
+        auxv.extend_from_slice(&read_word(addr + WORD)?.to_ne_bytes());
+        addr += 2 * WORD;
+        if key == 0 {
+            break;
+        }
+    }
+    Ok(auxv)
+}
+
+/// Counts the pages of the address space that are currently backed by
+/// physical memory.
+///
+/// Linear mappings (the signal trampoline, device memory) are not owned by the
+/// process and are skipped.
+pub fn resident_pages(aspace: &AddrSpace) -> usize {
+    aspace
+        .areas()
+        .filter(|area| !matches!(area.backend(), Backend::Linear { .. }))
+        .map(|area| resident_pages_in(aspace, area.start(), area.end()))
+        .sum()
+}
+
+/// Counts the pages in `[start, end)` that are currently backed by physical
+/// memory.
+///
+/// A huge page counts as the base pages it spans, even those outside the
+/// range.
+pub fn resident_pages_in(aspace: &AddrSpace, start: VirtAddr, end: VirtAddr) -> usize {
+    let pt = aspace.page_table();
+    let mut count = 0;
+    let mut addr = start.align_down_4k();
+    while addr < end {
+        match pt.query(addr) {
+            Ok((_, _, size)) => {
+                count += size as usize / PAGE_SIZE_4K;
+                addr = addr.align_down(size) + size as usize;
+            }
+            Err(_) => addr += PAGE_SIZE_4K,
+        }
+    }
+    count
+}
+
+/// Flushes the TLB entries of the address space of `proc_data` for the page
+/// at `vaddr`, or all of them, on every CPU that runs a thread of it, and
+/// returns once all of them did.
+///
+/// Page table entries that lost permissions need this; stale entries with
+/// fewer permissions only cause a spurious fault.
+pub fn flush_tlb(proc_data: &crate::task::ProcessData, vaddr: Option<VirtAddr>) {
+    axhal::asm::flush_tlb(vaddr);
+    #[cfg(feature = "smp")]
+    {
+        use alloc::sync::Arc;
+        use core::sync::atomic::AtomicUsize;
+
+        let cpus = crate::task::cpus_running_aspace(Some(proc_data));
+        let pending = Arc::new(AtomicUsize::new(cpus.len()));
+        for cpu in cpus {
+            let pending = pending.clone();
+            axipi::run_on_cpu(cpu, move || {
+                axhal::asm::flush_tlb(vaddr);
+                pending.fetch_sub(1, Ordering::Release);
+            });
+        }
+        while pending.load(Ordering::Acquire) != 0 {
+            axtask::yield_now();
+        }
+    }
+    #[cfg(not(feature = "smp"))]
+    let _ = proc_data;
+}
+
+/// Flushes the TLB entries of the kernel pages within `[start, start + size)`
+/// on every CPU, and returns once all of them did.
+pub fn flush_kernel_tlb(start: VirtAddr, size: usize) {
+    let flush = move || {
+        for offset in (0..size).step_by(PAGE_SIZE_4K) {
+            axhal::asm::flush_tlb(Some(start + offset));
+        }
+    };
+    flush();
+    #[cfg(feature = "smp")]
+    {
+        use alloc::sync::Arc;
+        use core::sync::atomic::AtomicUsize;
+
+        use axhal::percpu::this_cpu_id;
+
+        let this_cpu = this_cpu_id();
+        let cpus = (0..axconfig::plat::CPU_NUM).filter(|cpu| *cpu != this_cpu);
+        let pending = Arc::new(AtomicUsize::new(axconfig::plat::CPU_NUM - 1));
+        for cpu in cpus {
+            let pending = pending.clone();
+            axipi::run_on_cpu(cpu, move || {
+                flush();
+                pending.fetch_sub(1, Ordering::Release);
+            });
+        }
+        while pending.load(Ordering::Acquire) != 0 {
+            axtask::yield_now();
+        }
+    }
+}
+
 static ACCESSING_USER_MEM: AtomicBool = AtomicBool::new(false);
 
 /// Enables scoped access into user memory, allowing page faults to occur inside
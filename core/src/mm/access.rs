@@ -0,0 +1,178 @@
+//! Access to the memory of other processes, for `process_vm_readv`,
+//! `process_vm_writev` and `/proc/[pid]/mem`.
+
+use axerrno::{AxError, AxResult};
+use axhal::paging::MappingFlags;
+use axmm::{AddrSpace, backend::Backend};
+use axtask::current;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+
+use super::swap_in_page;
+use crate::task::{AsThread, ProcessData};
+
+/// Checks whether the current process may access the memory of `target`, as
+/// `PTRACE_MODE_ATTACH_REALCREDS` would.
+///
+/// A process may always access its own memory. Other processes are checked by
+/// credentials, and since every task runs as root with all capabilities this
+/// only rules out targets that no longer have an address space.
+pub fn may_access_memory(target: &ProcessData) -> AxResult {
+    let curr = current();
+    if curr.as_thread().proc_data.proc.pid() == target.proc.pid() {
+        return Ok(());
+    }
+    if target.proc.is_zombie() {
+        return Err(AxError::NoSuchProcess);
+    }
+    Ok(())
+}
+
+/// Faults in the page containing `vaddr` for `access_flags` and returns how
+/// many bytes from `vaddr` to the end of the page may be accessed.
+///
+/// Secret memory, and pages user space still has to supply through
+/// userfaultfd, are not accessible.
+///
+/// With `force`, a write to a private mapping that is not writable, such as
+/// a debugger setting a breakpoint in program text, is still allowed, see
+/// [`force_private_page`].
+fn prepare_page(
+    proc_data: &ProcessData,
+    aspace: &mut AddrSpace,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+    force: bool,
+) -> AxResult<usize> {
+    let page = vaddr.align_down_4k();
+    if proc_data.secret.lock().overlaps(page, PAGE_SIZE_4K) {
+        return Err(AxError::BadAddress);
+    }
+    swap_in_page(proc_data, aspace, page)?;
+    if access_flags.contains(MappingFlags::WRITE) {
+        proc_data
+            .soft_dirty
+            .lock()
+            .mark_dirty(aspace, page, page + PAGE_SIZE_4K)?;
+    }
+    if force
+        && access_flags.contains(MappingFlags::WRITE)
+        && aspace.find_area(page).is_some_and(|area| {
+            !area.flags().contains(MappingFlags::WRITE)
+                && matches!(area.backend(), Backend::Cow { .. })
+        })
+    {
+        force_private_page(proc_data, aspace, page)?;
+        return Ok(page + PAGE_SIZE_4K - vaddr);
+    }
+    if !aspace.can_access_range(page, PAGE_SIZE_4K, access_flags) {
+        return Err(AxError::BadAddress);
+    }
+    {
+        let uffd = proc_data.uffd.lock();
+        let missing = aspace.page_table().query(page).is_err();
+        if (missing && uffd.find(page).is_some())
+            || (access_flags.contains(MappingFlags::WRITE) && uffd.is_write_protected(page))
+        {
+            return Err(AxError::BadAddress);
+        }
+    }
+    proc_data.populate_area(aspace, page, PAGE_SIZE_4K, access_flags)?;
+    proc_data
+        .pkeys
+        .lock()
+        .apply(proc_data, aspace, page, page + PAGE_SIZE_4K);
+    Ok(page + PAGE_SIZE_4K - vaddr)
+}
+
+/// Gives the page at `page` of a private mapping that is not writable a copy
+/// of its own, so that writing to it neither shows through to the file nor
+/// to other processes sharing the page after fork.
+///
+/// The page is faulted in for writing with the mapping briefly made writable,
+/// which breaks the sharing, and is then mapped read-only again.
+fn force_private_page(proc_data: &ProcessData, aspace: &mut AddrSpace, page: VirtAddr) -> AxResult {
+    if proc_data.uffd.lock().find(page).is_some() && aspace.page_table().query(page).is_err() {
+        return Err(AxError::BadAddress);
+    }
+    // Only the one page is made writable, not the huge page around it.
+    proc_data
+        .thp
+        .lock()
+        .split_boundaries(aspace, page, page + PAGE_SIZE_4K)?;
+    let flags = aspace.find_area(page).ok_or(AxError::BadAddress)?.flags();
+    let resident = aspace.page_table().query(page).is_ok();
+    aspace.protect(page, PAGE_SIZE_4K, flags | MappingFlags::WRITE)?;
+    let copied = aspace.handle_page_fault(page, MappingFlags::WRITE);
+    aspace.protect(page, PAGE_SIZE_4K, flags)?;
+    if !copied {
+        return Err(AxError::NoMemory);
+    }
+    if !resident {
+        proc_data.add_rss(1);
+    }
+    proc_data
+        .pkeys
+        .lock()
+        .apply(proc_data, aspace, page, page + PAGE_SIZE_4K);
+    Ok(())
+}
+
+/// Reads the memory of `proc_data` at `vaddr` into `buf`.
+///
+/// Returns the number of bytes read, which falls short of `buf` if an
+/// inaccessible page is reached, or [`AxError::BadAddress`] if the first one
+/// already is.
+pub fn read_process_memory(
+    proc_data: &ProcessData,
+    vaddr: VirtAddr,
+    buf: &mut [u8],
+) -> AxResult<usize> {
+    if vaddr.as_usize().checked_add(buf.len()).is_none() {
+        return Err(AxError::BadAddress);
+    }
+    let mut count = 0;
+    while count < buf.len() {
+        let addr = vaddr + count;
+        let mut aspace = proc_data.aspace.lock();
+        let len = match prepare_page(proc_data, &mut aspace, addr, MappingFlags::READ, false) {
+            Ok(len) => len.min(buf.len() - count),
+            Err(err) if count == 0 => return Err(err),
+            Err(_) => break,
+        };
+        aspace.read(addr, &mut buf[count..count + len])?;
+        count += len;
+    }
+    Ok(count)
+}
+
+/// Writes `buf` to the memory of `proc_data` at `vaddr`.
+///
+/// With `force`, as for `/proc/[pid]/mem`, private mappings are written to
+/// even if they are not writable, on a copy of the page.
+///
+/// Returns the number of bytes written, which falls short of `buf` if an
+/// inaccessible page is reached, or [`AxError::BadAddress`] if the first one
+/// already is.
+pub fn write_process_memory(
+    proc_data: &ProcessData,
+    vaddr: VirtAddr,
+    buf: &[u8],
+    force: bool,
+) -> AxResult<usize> {
+    if vaddr.as_usize().checked_add(buf.len()).is_none() {
+        return Err(AxError::BadAddress);
+    }
+    let mut count = 0;
+    while count < buf.len() {
+        let addr = vaddr + count;
+        let mut aspace = proc_data.aspace.lock();
+        let len = match prepare_page(proc_data, &mut aspace, addr, MappingFlags::WRITE, force) {
+            Ok(len) => len.min(buf.len() - count),
+            Err(err) if count == 0 => return Err(err),
+            Err(_) => break,
+        };
+        aspace.write(addr, &buf[count..count + len])?;
+        count += len;
+    }
+    Ok(count)
+}
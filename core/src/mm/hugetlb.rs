@@ -0,0 +1,158 @@
+//! The huge page pool behind hugetlbfs, `MAP_HUGETLB` and `SHM_HUGETLB`.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use axerrno::{AxError, AxResult};
+use axhal::paging::PageSize;
+use kspin::SpinNoIrq;
+use memory_addr::VirtAddr;
+
+use super::{
+    HPAGE_SIZE,
+    range_map::{RangeMap, RangeValue},
+};
+
+struct HugePagePool {
+    /// Number of huge pages in the pool
+    total: usize,
+    /// Number of huge pages handed out
+    used: usize,
+}
+
+static POOL: SpinNoIrq<HugePagePool> = SpinNoIrq::new(HugePagePool { total: 0, used: 0 });
+
+/// Returns the number of huge pages in the pool.
+pub fn hugepages_total() -> usize {
+    POOL.lock().total
+}
+
+/// Returns the number of huge pages in the pool that are not in use.
+pub fn hugepages_free() -> usize {
+    let pool = POOL.lock();
+    pool.total.saturating_sub(pool.used)
+}
+
+/// Resizes the huge page pool.
+///
+/// Shrinking the pool below the number of pages in use only takes effect as
+/// those pages are released.
+pub fn set_hugepages_total(total: usize) {
+    POOL.lock().total = total;
+}
+
+/// A number of huge pages taken from the pool, returned when dropped.
+///
+/// The pool counts in units of [`HPAGE_SIZE`]; a 1G page takes as many units
+/// as it covers.
+pub struct HugePageReservation {
+    pages: usize,
+}
+
+impl HugePageReservation {
+    /// Takes enough huge pages from the pool to back `size` bytes.
+    pub fn new(size: usize) -> AxResult<Self> {
+        let pages = size.div_ceil(HPAGE_SIZE);
+        let mut pool = POOL.lock();
+        if pool.used + pages > pool.total {
+            return Err(AxError::NoMemory);
+        }
+        pool.used += pages;
+        Ok(Self { pages })
+    }
+
+    /// Takes the same number of huge pages from the pool again.
+    pub fn try_clone(&self) -> AxResult<Self> {
+        Self::new(self.pages * HPAGE_SIZE)
+    }
+
+    /// Returns the size in bytes covered by this reservation.
+    pub fn size(&self) -> usize {
+        self.pages * HPAGE_SIZE
+    }
+}
+
+impl Drop for HugePageReservation {
+    fn drop(&mut self) {
+        POOL.lock().used -= self.pages;
+    }
+}
+
+/// A huge page reserved for an anonymous `MAP_HUGETLB` mapping.
+#[derive(Clone)]
+struct HugetlbPage {
+    /// Shared by the address spaces a shared mapping was inherited by
+    reservation: Arc<HugePageReservation>,
+    shared: bool,
+}
+
+impl RangeValue for HugetlbPage {}
+
+/// Huge pages reserved for the anonymous `MAP_HUGETLB` mappings of an address
+/// space, one reservation per huge page.
+#[derive(Default)]
+pub struct HugetlbAreas(RangeMap<HugetlbPage>);
+
+impl HugetlbAreas {
+    /// Reserves huge pages of `page_size` for `[start, end)`, which is mapped
+    /// shared if `shared` is set.
+    pub fn reserve(
+        &mut self,
+        start: VirtAddr,
+        end: VirtAddr,
+        page_size: PageSize,
+        shared: bool,
+    ) -> AxResult {
+        let page_size = page_size as usize;
+        let reserved = (start.as_usize()..end.as_usize())
+            .step_by(page_size)
+            .map(|addr| {
+                Ok((
+                    VirtAddr::from(addr),
+                    HugetlbPage {
+                        reservation: Arc::new(HugePageReservation::new(page_size)?),
+                        shared,
+                    },
+                ))
+            })
+            .collect::<AxResult<Vec<_>>>()?;
+        self.remove(start, end);
+        for (addr, page) in reserved {
+            self.0.insert(addr, addr + page_size, page);
+        }
+        Ok(())
+    }
+
+    /// Returns the huge pages within `[start, end)` to the pool.
+    pub fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.0.remove(start, end);
+    }
+
+    /// Returns all huge pages to the pool.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Reserves the huge pages again for a copy of the address space.
+    ///
+    /// The copy shares the pages of shared mappings, and with them their
+    /// reservation; only the pages of private mappings are reserved again.
+    pub fn try_clone(&self) -> AxResult<Self> {
+        let mut pages = RangeMap::default();
+        for (range, page) in self.0.iter() {
+            let reservation = if page.shared {
+                page.reservation.clone()
+            } else {
+                Arc::new(page.reservation.try_clone()?)
+            };
+            pages.insert(
+                range.start,
+                range.end,
+                HugetlbPage {
+                    reservation,
+                    shared: page.shared,
+                },
+            );
+        }
+        Ok(Self(pages))
+    }
+}
@@ -0,0 +1,114 @@
+//! The out-of-memory killer.
+
+use alloc::sync::Arc;
+
+use axhal::paging::PageSize;
+use memory_addr::PAGE_SIZE_4K;
+use starry_process::Pid;
+use starry_signal::{SignalInfo, Signo};
+
+use super::{
+    resident_pages,
+    vmstat::{VmEvent, count_vm_event},
+};
+use crate::task::{ProcessData, get_process_data, processes, send_signal_to_process};
+
+/// The `oom_score_adj` value that exempts a process from the OOM killer.
+pub const OOM_SCORE_ADJ_MIN: i32 = -1000;
+/// The largest `oom_score_adj` value.
+pub const OOM_SCORE_ADJ_MAX: i32 = 1000;
+
+fn total_pages() -> usize {
+    let allocator = axalloc::global_allocator();
+    (allocator.used_pages() + allocator.available_pages()).max(1)
+}
+
+/// Returns how many pages killing the process would be worth: its resident
+/// pages, shifted by `oom_score_adj` thousandths of all memory.
+///
+/// Returns `None` if the process must not be killed.
+fn oom_badness(proc_data: &ProcessData, total_pages: usize) -> Option<isize> {
+    let adj = proc_data.oom_score_adj();
+    if adj == OOM_SCORE_ADJ_MIN || proc_data.proc.is_init() || proc_data.proc.is_zombie() {
+        return None;
+    }
+    let rss = resident_pages(&proc_data.aspace.lock()) as isize;
+    Some(rss + adj as isize * total_pages as isize / 1000)
+}
+
+/// Returns whether a page could be allocated right now.
+///
+/// A page fault the area permits fails either for lack of memory or for
+/// another reason, such as an I/O error; only the former calls for
+/// reclaiming memory.
+pub fn can_alloc_page() -> bool {
+    let allocator = axalloc::global_allocator();
+    match allocator.alloc_pages(1, PAGE_SIZE_4K) {
+        Ok(vaddr) => {
+            allocator.dealloc_pages(vaddr, 1);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Returns the score shown in `/proc/<pid>/oom_score`, from 0 to 2000.
+pub fn oom_score(proc_data: &ProcessData) -> usize {
+    let total_pages = total_pages();
+    oom_badness(proc_data, total_pages).map_or(0, |badness| {
+        (1000 + badness * 1000 / total_pages as isize).clamp(0, 2000) as usize
+    })
+}
+
+/// Kills the process with the highest badness to free memory.
+///
+/// While an earlier victim is still exiting, no other process is killed and
+/// that victim is returned instead. Returns `None` if there is nothing left to
+/// kill.
+pub fn out_of_memory() -> Option<Pid> {
+    let processes = processes();
+    if let Some(victim) = processes
+        .iter()
+        .find(|proc_data| proc_data.is_oom_victim() && !proc_data.proc.is_zombie())
+    {
+        return Some(victim.proc.pid());
+    }
+
+    let total_pages = total_pages();
+    let (badness, victim) = processes
+        .into_iter()
+        .filter_map(|proc_data| Some((oom_badness(&proc_data, total_pages)?, proc_data)))
+        .max_by_key(|(badness, _)| *badness)?;
+
+    let pid = victim.proc.pid();
+    let rss = resident_pages(&victim.aspace.lock());
+    warn!(
+        "Out of memory: Killed process {pid} ({}) rss:{}kB badness:{badness} oom_score_adj:{}",
+        victim.exe_path.read(),
+        rss * PageSize::Size4K as usize / 1024,
+        victim.oom_score_adj(),
+    );
+    victim.set_oom_victim();
+    count_vm_event(VmEvent::OomKill);
+    let _ = send_signal_to_process(pid, Some(SignalInfo::new_kernel(Signo::SIGKILL)));
+    Some(pid)
+}
+
+/// Waits for an OOM victim to exit and releases its address space.
+pub fn wait_oom_victim(pid: Pid) {
+    loop {
+        let Ok(victim) = get_process_data(pid) else {
+            return;
+        };
+        if victim.proc.is_zombie() {
+            // Nobody else can touch the memory of an exited process unless the
+            // address space is shared.
+            if Arc::strong_count(&victim.aspace) == 1 {
+                victim.aspace.lock().clear();
+            }
+            return;
+        }
+        drop(victim);
+        axtask::yield_now();
+    }
+}
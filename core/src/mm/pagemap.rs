@@ -0,0 +1,314 @@
+//! Page-level views of memory, for `/proc/[pid]/pagemap`, `/proc/kpagecount`
+//! and `/proc/kpageflags`, and the soft-dirty bits `/proc/[pid]/clear_refs`
+//! resets.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use axerrno::{AxError, AxResult};
+use axhal::{
+    mem::{MemRegionFlags, memory_regions},
+    paging::MappingFlags,
+};
+use axmm::{AddrSpace, backend::Backend};
+use axtask::current;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+
+use super::{
+    flush_tlb,
+    range_map::RangeMap,
+    vma::{PageMapCounts, for_each_page, vmas},
+};
+use crate::task::{AsThread, ProcessData};
+
+/// The page frame number, or the swap type and offset of a swapped-out page
+const PM_PFRAME_MASK: u64 = (1 << 55) - 1;
+/// The number of bits of the swap type below the swap offset
+const PM_SWAP_TYPE_BITS: u32 = 5;
+/// The page was written since the soft-dirty bits were last cleared.
+const PM_SOFT_DIRTY: u64 = 1 << 55;
+/// The page is mapped only once.
+const PM_MMAP_EXCLUSIVE: u64 = 1 << 56;
+/// The page is write-protected through userfaultfd.
+const PM_UFFD_WP: u64 = 1 << 57;
+/// The page is a file page or shared memory.
+const PM_FILE: u64 = 1 << 61;
+/// The page is swapped out.
+const PM_SWAP: u64 = 1 << 62;
+/// The page is present.
+const PM_PRESENT: u64 = 1 << 63;
+
+/// The page contents are valid.
+pub(crate) const KPF_UPTODATE: u64 = 1 << 3;
+/// The page has been written to.
+pub(crate) const KPF_DIRTY: u64 = 1 << 4;
+/// The page is on a reclaim list.
+pub(crate) const KPF_LRU: u64 = 1 << 5;
+/// The page is mapped into a user address space.
+pub(crate) const KPF_MMAP: u64 = 1 << 11;
+/// The page is anonymous memory.
+pub(crate) const KPF_ANON: u64 = 1 << 12;
+/// The page is backed by swap.
+pub(crate) const KPF_SWAPBACKED: u64 = 1 << 14;
+/// The page is the first of a huge page.
+pub(crate) const KPF_COMPOUND_HEAD: u64 = 1 << 15;
+/// The page is part of a huge page, but not the first.
+pub(crate) const KPF_COMPOUND_TAIL: u64 = 1 << 16;
+/// The page is part of a hugetlb page.
+pub(crate) const KPF_HUGE: u64 = 1 << 17;
+/// There is no memory at the frame.
+const KPF_NOPAGE: u64 = 1 << 20;
+/// The page is part of a transparent huge page.
+pub(crate) const KPF_THP: u64 = 1 << 22;
+
+/// The pages of an address space whose soft-dirty bit has been cleared
+/// through `/proc/[pid]/clear_refs` and that haven't been written since.
+///
+/// There is no dirty bit to look at, so writable pages are write-protected
+/// when cleared and the first write fault marks them dirty again. Only the
+/// page table entries are write-protected; the areas keep their permissions.
+/// Pages that aren't tracked here are soft-dirty. Each page is kept with
+/// whether its page table entry was write-protected to notice the first write.
+#[derive(Default, Clone)]
+pub struct SoftDirtyPages(RangeMap<bool>);
+
+/// Changes the permissions of the page table entry mapping `vaddr` to
+/// `flags`, leaving its area alone.
+///
+/// The TLB is not flushed.
+pub(super) fn protect_entry(
+    aspace: &mut AddrSpace,
+    vaddr: VirtAddr,
+    flags: MappingFlags,
+) -> AxResult {
+    let (_, flush) = aspace
+        .page_table_mut()
+        .protect(vaddr, flags)
+        .map_err(|_| AxError::BadAddress)?;
+    flush.ignore();
+    Ok(())
+}
+
+impl SoftDirtyPages {
+    /// Returns the tracked pages overlapping `[start, end)`.
+    fn overlapping(&self, start: VirtAddr, end: VirtAddr) -> Vec<(Range<VirtAddr>, bool)> {
+        self.0
+            .overlapping(start, end)
+            .map(|(page, &protected)| (page, protected))
+            .collect()
+    }
+
+    /// Clears the soft-dirty bit of every present page of `aspace`, the
+    /// address space of `proc_data`.
+    pub fn clear_refs(&mut self, proc_data: &ProcessData, aspace: &mut AddrSpace) -> AxResult {
+        let mut pages = Vec::new();
+        for area in aspace.areas() {
+            if matches!(area.backend(), Backend::Linear { .. }) {
+                continue;
+            }
+            for_each_page(aspace, area.start(), area.end(), |vaddr, _, flags, size| {
+                pages.push((vaddr, size as usize, flags));
+            });
+        }
+        let mut protected_any = false;
+        for (vaddr, size, flags) in pages {
+            // A page still clean from the last time is already protected.
+            if self.0.contains(vaddr) {
+                continue;
+            }
+            let protected = flags.contains(MappingFlags::WRITE);
+            if protected {
+                protect_entry(aspace, vaddr, flags - MappingFlags::WRITE)?;
+                protected_any = true;
+            }
+            self.0.insert(vaddr, vaddr + size, protected);
+        }
+        if protected_any {
+            flush_tlb(proc_data, None);
+        }
+        Ok(())
+    }
+
+    /// Marks the pages within `[start, end)` soft-dirty as they are about to
+    /// be written.
+    ///
+    /// Private and file pages stay write-protected, so that the write fault
+    /// goes through their backend, which breaks copy-on-write sharing and
+    /// tracks dirty file pages. Shared memory is made writable again.
+    ///
+    /// Returns whether any of the pages was write-protected.
+    pub fn mark_dirty(
+        &mut self,
+        aspace: &mut AddrSpace,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) -> AxResult<bool> {
+        let mut protected = false;
+        for (page, was_protected) in self.overlapping(start, end) {
+            self.0.remove(page.start, page.end);
+            if !was_protected {
+                continue;
+            }
+            protected = true;
+            let vaddr = page.start;
+            if let Some(area) = aspace.find_area(vaddr)
+                && matches!(area.backend(), Backend::Shared(_))
+            {
+                let flags = area.flags();
+                protect_entry(aspace, vaddr, flags)?;
+            }
+        }
+        Ok(protected)
+    }
+
+    /// Returns whether the page containing `vaddr` is soft-dirty.
+    pub fn is_soft_dirty(&self, vaddr: VirtAddr) -> bool {
+        !self.0.contains(vaddr)
+    }
+
+    /// Keeps the clean pages within `[start, end)` write-protected after
+    /// their area of `aspace`, the address space of `proc_data`, is given new
+    /// permissions.
+    ///
+    /// Swapped-out pages and pages straddling the range are marked dirty
+    /// instead.
+    pub fn protect(
+        &mut self,
+        proc_data: &ProcessData,
+        aspace: &mut AddrSpace,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) -> AxResult {
+        let mut protected_any = false;
+        for (page, _) in self.overlapping(start, end) {
+            if page.start < start || page.end > end {
+                self.0.remove(page.start, page.end);
+                continue;
+            }
+            match aspace.page_table().query(page.start) {
+                Ok((_, pte_flags, _)) if pte_flags.contains(MappingFlags::WRITE) => {
+                    protect_entry(aspace, page.start, pte_flags - MappingFlags::WRITE)?;
+                    protected_any = true;
+                    self.0.insert(page.start, page.end, true);
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    self.0.remove(page.start, page.end);
+                }
+            }
+        }
+        if protected_any {
+            flush_tlb(proc_data, None);
+        }
+        Ok(())
+    }
+
+    /// Forgets the pages within `[start, end)`, for memory that is unmapped.
+    pub fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        for (page, _) in self.overlapping(start, end) {
+            self.0.remove(page.start, page.end);
+        }
+    }
+
+    /// Forgets all pages.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Returns whether the current process may see the page frame numbers of
+/// `target` in `/proc/[pid]/pagemap`, which takes `CAP_SYS_ADMIN`.
+///
+/// Tasks carry no credentials to check the capability against, so only a
+/// process's own frame numbers are shown; those of other processes read as
+/// zero, as they would for an unprivileged reader.
+pub fn may_read_pfns(target: &ProcessData) -> bool {
+    current().as_thread().proc_data.proc.pid() == target.proc.pid()
+}
+
+/// Returns the `/proc/[pid]/pagemap` entries of the `count` pages starting at
+/// `start`.
+///
+/// `counts` tells which pages are mapped only once. Page frame numbers are
+/// left as zero unless `show_pfn`.
+pub fn pagemap_entries(
+    proc_data: &ProcessData,
+    start: VirtAddr,
+    count: usize,
+    counts: &PageMapCounts,
+    show_pfn: bool,
+) -> Vec<u64> {
+    let aspace = proc_data.aspace.lock();
+    let vmas = vmas(proc_data, &aspace);
+    let swap = proc_data.swap.lock();
+    let uffd = proc_data.uffd.lock();
+    let soft_dirty = proc_data.soft_dirty.lock();
+
+    let mut entries = Vec::with_capacity(count);
+    let mut vaddr = start.align_down_4k();
+    for _ in 0..count {
+        let vma = vmas
+            .get(vmas.partition_point(|vma| vma.end <= vaddr))
+            .filter(|vma| vma.start <= vaddr);
+        let mut entry = 0;
+        if let Some(vma) = vma
+            && let Ok((paddr, flags, _)) = aspace.page_table().query(vaddr)
+        {
+            let pfn = paddr.as_usize() / PAGE_SIZE_4K;
+            entry |= PM_PRESENT;
+            if show_pfn {
+                entry |= pfn as u64 & PM_PFRAME_MASK;
+            }
+            if !vma.is_linear() {
+                if counts.count(pfn) == 1 {
+                    entry |= PM_MMAP_EXCLUSIVE;
+                }
+                if !vma.is_anonymous_page(flags) {
+                    entry |= PM_FILE;
+                }
+            }
+        } else if let Some((swap_type, offset)) = swap.location(vaddr) {
+            entry |=
+                PM_SWAP | (((offset << PM_SWAP_TYPE_BITS) | swap_type as u64) & PM_PFRAME_MASK);
+        }
+        if entry != 0 {
+            if soft_dirty.is_soft_dirty(vaddr) {
+                entry |= PM_SOFT_DIRTY;
+            }
+            if uffd.is_write_protected(vaddr) {
+                entry |= PM_UFFD_WP;
+            }
+        }
+        entries.push(entry);
+        vaddr += PAGE_SIZE_4K;
+    }
+    entries
+}
+
+/// Returns whether there is RAM at the page frame `pfn`.
+fn is_ram(pfn: usize) -> bool {
+    let paddr = pfn * PAGE_SIZE_4K;
+    memory_regions().any(|region| {
+        !region.flags.contains(MemRegionFlags::DEVICE)
+            && (region.paddr.as_usize()..region.paddr.as_usize() + region.size).contains(&paddr)
+    })
+}
+
+/// Returns the number of page frames `/proc/kpagecount` and
+/// `/proc/kpageflags` describe, up to the end of RAM.
+pub fn max_pfn() -> usize {
+    memory_regions()
+        .filter(|region| !region.flags.contains(MemRegionFlags::DEVICE))
+        .map(|region| (region.paddr.as_usize() + region.size).div_ceil(PAGE_SIZE_4K))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Returns the `/proc/kpageflags` entry of the page frame `pfn`.
+pub fn kpageflags(counts: &PageMapCounts, pfn: usize) -> u64 {
+    if is_ram(pfn) {
+        counts.flags(pfn)
+    } else {
+        KPF_NOPAGE
+    }
+}
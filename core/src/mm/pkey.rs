@@ -0,0 +1,354 @@
+//! Memory protection keys, for `pkey_alloc`, `pkey_free` and `pkey_mprotect`.
+//!
+//! On x86_64 with PKU, every user page carries a 4-bit key in its page table
+//! entry, and the PKRU register of the running thread decides which keys may
+//! be read or written. The page table code knows nothing about keys and drops
+//! them whenever it rewrites an entry, so the keys of an address space are
+//! recorded here and written back after pages are populated or their
+//! permissions change. Entries whose key changed are flushed from the TLB of
+//! every CPU running the address space.
+
+use alloc::vec::Vec;
+
+use axerrno::{AxError, AxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use memory_addr::{MemoryAddr, VirtAddr};
+
+use super::{flush_tlb, range_map::RangeMap};
+use crate::task::ProcessData;
+
+/// Disallow all access to the pages of a key.
+pub const PKEY_DISABLE_ACCESS: u32 = 1;
+/// Disallow writes to the pages of a key.
+pub const PKEY_DISABLE_WRITE: u32 = 2;
+
+/// The number of keys, including the default key 0.
+const NR_PKEYS: u32 = 16;
+
+/// The PKRU value of new programs: every key but 0 is inaccessible.
+pub const INIT_PKRU: u32 = 0x5555_5554;
+
+/// The protection keys of an address space.
+#[derive(Clone)]
+pub struct ProtectionKeys {
+    /// The allocated keys, one bit each; key 0 is always allocated
+    allocated: u16,
+    /// The ranges given a key other than 0
+    ranges: RangeMap<u8>,
+}
+
+impl Default for ProtectionKeys {
+    fn default() -> Self {
+        Self {
+            allocated: 1,
+            ranges: RangeMap::default(),
+        }
+    }
+}
+
+impl ProtectionKeys {
+    /// Allocates the lowest free key.
+    ///
+    /// Fails with `ENOSPC` if all keys are in use or there are no hardware
+    /// keys at all.
+    pub fn alloc(&mut self) -> AxResult<u32> {
+        if !arch::supported() {
+            return Err(AxError::StorageFull);
+        }
+        let pkey = (1..NR_PKEYS)
+            .find(|pkey| self.allocated & (1 << pkey) == 0)
+            .ok_or(AxError::StorageFull)?;
+        self.allocated |= 1 << pkey;
+        arch::enable();
+        Ok(pkey)
+    }
+
+    /// Frees `pkey`.
+    ///
+    /// Pages still using the key keep it, as on Linux.
+    pub fn free(&mut self, pkey: i32) -> AxResult {
+        if pkey == 0 || !self.is_allocated(pkey) {
+            return Err(AxError::InvalidInput);
+        }
+        self.allocated &= !(1 << pkey);
+        Ok(())
+    }
+
+    /// Returns whether `pkey` may be passed to `pkey_mprotect`.
+    pub fn is_allocated(&self, pkey: i32) -> bool {
+        (0..NR_PKEYS as i32).contains(&pkey) && self.allocated & (1 << pkey) != 0
+    }
+
+    /// Returns the key of the page containing `vaddr`.
+    pub fn key(&self, vaddr: VirtAddr) -> u8 {
+        self.ranges.get(vaddr).copied().unwrap_or(0)
+    }
+
+    /// Gives the pages within `[start, end)` of `aspace`, the address space of
+    /// `proc_data`, the key `pkey`.
+    pub fn set(
+        &mut self,
+        proc_data: &ProcessData,
+        aspace: &mut AddrSpace,
+        start: VirtAddr,
+        end: VirtAddr,
+        pkey: u8,
+    ) {
+        if pkey != 0 {
+            self.ranges.insert(start, end, pkey);
+        } else {
+            self.ranges.remove(start, end);
+        }
+        if self.write_keys(aspace, start, end) {
+            flush_tlb(proc_data, None);
+        }
+    }
+
+    /// Writes the keys of the present pages within `[start, end)` of
+    /// `aspace`, the address space of `proc_data`, back into the page table,
+    /// after the page table code rewrote their entries.
+    pub fn apply(
+        &self,
+        proc_data: &ProcessData,
+        aspace: &mut AddrSpace,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) {
+        // Rewritten entries carry key 0, so only ranges with another key need
+        // to be looked at.
+        let (start, end) = (start.align_down_4k(), end.align_up_4k());
+        let ranges: Vec<_> = self
+            .ranges
+            .overlapping(start, end)
+            .map(|(range, _)| range)
+            .collect();
+        let mut changed = false;
+        for range in ranges {
+            changed |= self.write_keys(aspace, start.max(range.start), end.min(range.end));
+        }
+        if changed {
+            flush_tlb(proc_data, None);
+        }
+    }
+
+    /// Writes the keys of all pages of `aspace`, the address space of
+    /// `proc_data`, back into the page table, for a copy of the address space.
+    pub fn apply_all(&self, proc_data: &ProcessData, aspace: &mut AddrSpace) {
+        let mut changed = false;
+        for (range, _) in self.ranges.iter() {
+            changed |= self.write_keys(aspace, range.start, range.end);
+        }
+        if changed {
+            flush_tlb(proc_data, None);
+        }
+    }
+
+    /// Writes the keys of the present pages within `[start, end)` into their
+    /// page table entries, without flushing the TLB.
+    ///
+    /// Returns whether any entry changed.
+    fn write_keys(&self, aspace: &mut AddrSpace, start: VirtAddr, end: VirtAddr) -> bool {
+        let mut changed = false;
+        let mut vaddr = start.align_down_4k();
+        while vaddr < end {
+            let (size, set) = arch::set_page_key(aspace, vaddr, self.key(vaddr));
+            changed |= set;
+            vaddr = (vaddr + 1).align_up(size);
+        }
+        changed
+    }
+
+    /// Forgets the keys of the pages within `[start, end)`, splitting ranges
+    /// that straddle its ends.
+    pub fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.ranges.remove(start, end);
+    }
+
+    /// Frees all keys and forgets all ranges, for a new program.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Returns the key whose access rights in the current PKRU deny the access at
+/// `vaddr`, if the fault was a protection key violation rather than a missing
+/// or write-protected page.
+pub fn pkey_fault(aspace: &AddrSpace, vaddr: VirtAddr, access_flags: MappingFlags) -> Option<u32> {
+    // Instruction fetches aren't checked against keys.
+    if access_flags.contains(MappingFlags::EXECUTE) {
+        return None;
+    }
+    let pkru = arch::read_pkru()?;
+    let (_, flags, _) = aspace.page_table().query(vaddr).ok()?;
+    if !flags.contains(access_flags) {
+        return None;
+    }
+    let pkey = arch::page_key(aspace, vaddr)? as u32;
+    let rights = pkru >> (2 * pkey);
+    let denied = rights & PKEY_DISABLE_ACCESS != 0
+        || (access_flags.contains(MappingFlags::WRITE) && rights & PKEY_DISABLE_WRITE != 0);
+    denied.then_some(pkey)
+}
+
+/// Returns the PKRU of the current thread, or `None` if keys aren't in use on
+/// this CPU.
+pub fn read_pkru() -> Option<u32> {
+    arch::read_pkru()
+}
+
+/// Sets the PKRU of the current thread, if keys are in use on this CPU.
+pub fn write_pkru(pkru: u32) {
+    arch::write_pkru(pkru);
+}
+
+/// Loads the PKRU of a thread being switched to.
+///
+/// Keys are turned on lazily on each CPU, the first time a thread runs there
+/// after some process allocated one.
+pub fn restore_pkru(pkru: u32) {
+    if arch::in_use() {
+        arch::enable();
+        arch::write_pkru(pkru);
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        mod arch {
+            use core::{
+                arch::{asm, x86_64::__cpuid_count},
+                sync::atomic::{AtomicBool, Ordering},
+            };
+
+            use axmm::AddrSpace;
+            use lazy_static::lazy_static;
+            use memory_addr::{PAGE_SIZE_4K, VirtAddr};
+            use page_table_entry::{GenericPTE, x86_64::X64PTE};
+
+            /// CR4 bit enabling protection keys for user pages
+            const CR4_PKE: usize = 1 << 22;
+            const PTE_PKEY_SHIFT: u32 = 59;
+            const PTE_PKEY_MASK: u64 = 0xf << PTE_PKEY_SHIFT;
+
+            lazy_static! {
+                /// Whether the CPU implements protection keys for user pages
+                static ref PKU: bool = unsafe { __cpuid_count(7, 0) }.ecx & (1 << 3) != 0;
+            }
+
+            /// Whether any process has allocated a key
+            static IN_USE: AtomicBool = AtomicBool::new(false);
+
+            pub fn supported() -> bool {
+                *PKU
+            }
+
+            pub fn in_use() -> bool {
+                IN_USE.load(Ordering::Acquire)
+            }
+
+            fn read_cr4() -> usize {
+                let cr4;
+                unsafe { asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack)) };
+                cr4
+            }
+
+            /// Turns keys on for the current CPU.
+            pub fn enable() {
+                IN_USE.store(true, Ordering::Release);
+                let cr4 = read_cr4();
+                if cr4 & CR4_PKE == 0 {
+                    unsafe { asm!("mov cr4, {}", in(reg) cr4 | CR4_PKE, options(nostack)) };
+                    write_pkru(super::INIT_PKRU);
+                }
+            }
+
+            pub fn read_pkru() -> Option<u32> {
+                if read_cr4() & CR4_PKE == 0 {
+                    return None;
+                }
+                let pkru: u32;
+                unsafe {
+                    asm!("rdpkru", in("ecx") 0, out("eax") pkru, out("edx") _, options(nomem, nostack));
+                }
+                Some(pkru)
+            }
+
+            pub fn write_pkru(pkru: u32) {
+                if read_cr4() & CR4_PKE == 0 {
+                    return;
+                }
+                unsafe {
+                    asm!("wrpkru", in("eax") pkru, in("ecx") 0, in("edx") 0, options(nostack));
+                }
+            }
+
+            /// Returns the raw bits of the page table entry `pte`.
+            ///
+            /// The key bits have no `MappingFlags` counterpart, so they are
+            /// edited in place; `X64PTE` is a transparent wrapper of them.
+            fn raw_entry(pte: &mut X64PTE) -> &mut u64 {
+                unsafe { &mut *(pte as *mut X64PTE).cast::<u64>() }
+            }
+
+            pub fn page_key(aspace: &AddrSpace, vaddr: VirtAddr) -> Option<u8> {
+                let (pte, _) = aspace.page_table().get_entry(vaddr).ok()?;
+                if !pte.is_present() {
+                    return None;
+                }
+                Some(((pte.bits() as u64 & PTE_PKEY_MASK) >> PTE_PKEY_SHIFT) as u8)
+            }
+
+            /// Gives the page containing `vaddr` the key `pkey`, without
+            /// flushing the TLB.
+            ///
+            /// Returns the size of the page, and whether its entry changed.
+            pub fn set_page_key(aspace: &mut AddrSpace, vaddr: VirtAddr, pkey: u8) -> (usize, bool) {
+                let Ok((pte, size)) = aspace.page_table_mut().get_entry_mut(vaddr) else {
+                    return (PAGE_SIZE_4K, false);
+                };
+                if !pte.is_present() {
+                    return (size as usize, false);
+                }
+                let bits = raw_entry(pte);
+                let new = (*bits & !PTE_PKEY_MASK) | ((pkey as u64) << PTE_PKEY_SHIFT);
+                let changed = new != *bits;
+                *bits = new;
+                (size as usize, changed)
+            }
+        }
+    } else {
+        mod arch {
+            use axmm::AddrSpace;
+            use memory_addr::{PAGE_SIZE_4K, VirtAddr};
+
+            pub fn supported() -> bool {
+                false
+            }
+
+            pub fn in_use() -> bool {
+                false
+            }
+
+            pub fn enable() {}
+
+            pub fn read_pkru() -> Option<u32> {
+                None
+            }
+
+            pub fn write_pkru(_pkru: u32) {}
+
+            pub fn page_key(_aspace: &AddrSpace, _vaddr: VirtAddr) -> Option<u8> {
+                None
+            }
+
+            pub fn set_page_key(
+                _aspace: &mut AddrSpace,
+                _vaddr: VirtAddr,
+                _pkey: u8,
+            ) -> (usize, bool) {
+                (PAGE_SIZE_4K, false)
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! A map from disjoint address ranges to values, which the side tables of an
+//! address space are built on.
+//!
+//! The address space only knows its areas and page table, so whatever else is
+//! tracked about a range of it, like its huge pages, protection keys or the
+//! file it was mapped from, is kept in a [`RangeMap`] next to it.
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use core::ops::Range;
+
+use memory_addr::VirtAddr;
+
+/// A value of a [`RangeMap`], which is split along with its range.
+pub trait RangeValue: Clone {
+    /// Returns the value of the part of a range that starts `offset` bytes
+    /// into it.
+    fn advance(&self, _offset: usize) -> Self {
+        self.clone()
+    }
+}
+
+impl RangeValue for () {}
+
+impl RangeValue for bool {}
+
+impl RangeValue for u8 {}
+
+/// Disjoint address ranges, each with a value.
+#[derive(Clone)]
+pub struct RangeMap<V>(BTreeMap<usize, (usize, V)>);
+
+impl<V> Default for RangeMap<V> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<V: RangeValue> RangeMap<V> {
+    /// Gives `[start, end)` the value `value`, replacing whatever it had.
+    pub fn insert(&mut self, start: VirtAddr, end: VirtAddr, value: V) {
+        self.remove(start, end);
+        self.0.insert(start.as_usize(), (end.as_usize(), value));
+    }
+
+    /// Forgets `[start, end)`, splitting ranges that straddle its ends.
+    pub fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        let (start, end) = (start.as_usize(), end.as_usize());
+        let overlapping: Vec<usize> = self
+            .0
+            .range(..end)
+            .rev()
+            .take_while(|(_, (range_end, _))| *range_end > start)
+            .map(|(&range_start, _)| range_start)
+            .collect();
+        for range_start in overlapping {
+            let (range_end, value) = self.0.remove(&range_start).unwrap();
+            if range_end > end {
+                self.0
+                    .insert(end, (range_end, value.advance(end - range_start)));
+            }
+            if range_start < start {
+                self.0.insert(range_start, (start, value));
+            }
+        }
+    }
+
+    /// Returns the range containing `vaddr` and its value.
+    pub fn find(&self, vaddr: VirtAddr) -> Option<(Range<VirtAddr>, &V)> {
+        let (&start, (end, value)) = self.0.range(..=vaddr.as_usize()).next_back()?;
+        (*end > vaddr.as_usize()).then(|| (VirtAddr::from(start)..VirtAddr::from(*end), value))
+    }
+
+    /// Returns the value of the range containing `vaddr`.
+    pub fn get(&self, vaddr: VirtAddr) -> Option<&V> {
+        self.find(vaddr).map(|(_, value)| value)
+    }
+
+    /// Returns whether `vaddr` lies in a range.
+    pub fn contains(&self, vaddr: VirtAddr) -> bool {
+        self.find(vaddr).is_some()
+    }
+
+    /// Returns whether a single range covers all of `[start, end)`.
+    pub fn contains_range(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        self.find(start).is_some_and(|(range, _)| end <= range.end)
+    }
+
+    /// Returns the ranges overlapping `[start, end)` and their values, in
+    /// address order.
+    pub fn overlapping(
+        &self,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) -> impl Iterator<Item = (Range<VirtAddr>, &V)> + '_ {
+        let first = self
+            .find(start)
+            .map_or(start.as_usize(), |(range, _)| range.start.as_usize());
+        self.0
+            .range(first..end.as_usize())
+            .filter(move |(_, (range_end, _))| *range_end > start.as_usize())
+            .map(|(&range_start, (range_end, value))| {
+                (
+                    VirtAddr::from(range_start)..VirtAddr::from(*range_end),
+                    value,
+                )
+            })
+    }
+
+    /// Returns the values of the ranges overlapping `[start, end)`, for them to
+    /// be changed in place.
+    pub fn overlapping_mut(
+        &mut self,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) -> impl Iterator<Item = &mut V> + '_ {
+        let first = self
+            .find(start)
+            .map_or(start.as_usize(), |(range, _)| range.start.as_usize());
+        self.0
+            .range_mut(first..end.as_usize())
+            .filter(move |(_, (range_end, _))| *range_end > start.as_usize())
+            .map(|(_, (_, value))| value)
+    }
+
+    /// Returns all ranges and their values, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (Range<VirtAddr>, &V)> + '_ {
+        self.0
+            .iter()
+            .map(|(&start, (end, value))| (VirtAddr::from(start)..VirtAddr::from(*end), value))
+    }
+
+    /// Returns the number of ranges.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether there are no ranges.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Forgets all ranges.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
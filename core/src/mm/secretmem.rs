@@ -0,0 +1,108 @@
+//! Secret memory: pages that are mapped only into the address spaces that use
+//! them, and not into the kernel direct map.
+
+use alloc::sync::Arc;
+
+use axerrno::{AxError, AxResult};
+use axhal::{mem::virt_to_phys, paging::MappingFlags};
+use axmm::backend::Backend;
+use memory_addr::{PAGE_SIZE_4K, PhysAddr, VirtAddr, align_up_4k};
+
+use super::{
+    flush_kernel_tlb,
+    range_map::{RangeMap, RangeValue},
+};
+
+/// Physically contiguous pages removed from the kernel direct map.
+///
+/// The pages are put back into the direct map, zeroed and freed once the last
+/// file and mapping referring to them is gone.
+pub struct SecretRegion {
+    /// Direct map address of the first page
+    vaddr: VirtAddr,
+    size: usize,
+}
+
+impl SecretRegion {
+    /// Allocates a zeroed region of at least `size` bytes and removes it from
+    /// the direct map.
+    pub fn new(size: usize) -> AxResult<Arc<Self>> {
+        let size = align_up_4k(size);
+        if size == 0 {
+            return Err(AxError::InvalidInput);
+        }
+        let vaddr = axalloc::global_allocator()
+            .alloc_pages(size / PAGE_SIZE_4K, PAGE_SIZE_4K)
+            .map_err(|_| AxError::NoMemory)?;
+        let vaddr = VirtAddr::from(vaddr);
+        unsafe { vaddr.as_mut_ptr().write_bytes(0, size) };
+        if let Err(err) = axmm::kernel_aspace().lock().unmap(vaddr, size) {
+            axalloc::global_allocator().dealloc_pages(vaddr.as_usize(), size / PAGE_SIZE_4K);
+            return Err(err);
+        }
+        // Other CPUs may still reach the pages through the direct map.
+        flush_kernel_tlb(vaddr, size);
+        Ok(Arc::new(Self { vaddr, size }))
+    }
+
+    /// Returns the physical address of the first page.
+    pub fn paddr(&self) -> PhysAddr {
+        virt_to_phys(self.vaddr)
+    }
+
+    /// Returns the size of the region in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for SecretRegion {
+    fn drop(&mut self) {
+        let offset = self.vaddr.as_usize() as isize - self.paddr().as_usize() as isize;
+        axmm::kernel_aspace()
+            .lock()
+            .map(
+                self.vaddr,
+                self.size,
+                MappingFlags::READ | MappingFlags::WRITE,
+                true,
+                Backend::new_linear(offset),
+            )
+            .expect("failed to restore the direct map");
+        flush_kernel_tlb(self.vaddr, self.size);
+        unsafe { self.vaddr.as_mut_ptr().write_bytes(0, self.size) };
+        axalloc::global_allocator().dealloc_pages(self.vaddr.as_usize(), self.size / PAGE_SIZE_4K);
+    }
+}
+
+/// The secret memory mapped into an address space.
+///
+/// The ranges keep their regions alive, since the mappings themselves refer to
+/// the pages only by physical address.
+#[derive(Default, Clone)]
+pub struct SecretAreas(RangeMap<Arc<SecretRegion>>);
+
+impl RangeValue for Arc<SecretRegion> {}
+
+impl SecretAreas {
+    /// Records that `region` is mapped at `[start, end)`.
+    pub fn insert(&mut self, start: VirtAddr, end: VirtAddr, region: Arc<SecretRegion>) {
+        self.0.insert(start, end, region);
+    }
+
+    /// Forgets the secret memory within `[start, end)`, splitting ranges that
+    /// straddle its ends.
+    pub fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.0.remove(start, end);
+    }
+
+    /// Returns whether any of `[start, start + len)` is secret memory.
+    pub fn overlaps(&self, start: VirtAddr, len: usize) -> bool {
+        self.0.overlapping(start, start + len).next().is_some()
+    }
+
+    /// Forgets all secret memory.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
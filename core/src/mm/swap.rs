@@ -0,0 +1,462 @@
+//! Swapping private anonymous pages out to swap files and block devices.
+
+use alloc::{collections::btree_map::BTreeMap, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    cmp::Reverse,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng::FileBackend;
+use axhal::{
+    mem::phys_to_virt,
+    paging::{MappingFlags, PageSize},
+};
+use axmm::{AddrSpace, backend::Backend};
+use axsync::Mutex;
+use kspin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+use starry_process::Pid;
+
+use super::{
+    flush_tlb,
+    range_map::{RangeMap, RangeValue},
+    vmstat::{VmEvent, count_vm_event},
+};
+use crate::task::{ProcessData, get_process_data, processes};
+
+/// The signature `mkswap` writes at the end of the first page.
+const SWAP_MAGIC: &[u8] = b"SWAPSPACE2";
+/// Offset of the swap header within the first page, after the boot block.
+const SWAP_HEADER_OFFSET: usize = 1024;
+/// Offset of the bad page list within the first page.
+const SWAP_BADPAGES_OFFSET: usize = SWAP_HEADER_OFFSET + 512;
+/// Maximum number of bad pages the header can list.
+const SWAP_BADPAGES_MAX: usize = (PAGE_SIZE_4K - SWAP_BADPAGES_OFFSET - SWAP_MAGIC.len()) / 4;
+
+/// Number of pages to swap out at a time when memory runs out.
+pub const SWAP_CLUSTER_MAX: usize = 32;
+
+/// Maximum number of pages remembered for eviction.
+const LRU_CAPACITY: usize = 1 << 18;
+
+struct SwapArea {
+    /// Absolute path of the swap file or device
+    path: String,
+    file: FileBackend,
+    priority: i32,
+    /// Number of usable page slots
+    pages: usize,
+    /// Unused page slots
+    free: SpinNoIrq<Vec<u32>>,
+}
+
+static SWAP_AREAS: Mutex<Vec<Arc<SwapArea>>> = Mutex::new(Vec::new());
+
+/// Priority given to the next swap area added without one.
+static NEXT_PRIORITY: AtomicI32 = AtomicI32::new(-1);
+
+/// Recently faulted private anonymous pages, each remembered once.
+struct PageLru {
+    /// The pages by the time they were last used, least recently used first
+    order: BTreeMap<u64, (Pid, usize)>,
+    /// When each page was last used
+    pages: BTreeMap<(Pid, usize), u64>,
+    clock: u64,
+}
+
+impl PageLru {
+    const fn new() -> Self {
+        Self {
+            order: BTreeMap::new(),
+            pages: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Makes `page` the most recently used page.
+    fn touch(&mut self, page: (Pid, usize)) {
+        if let Some(last) = self.pages.insert(page, self.clock) {
+            self.order.remove(&last);
+        } else if self.pages.len() > LRU_CAPACITY {
+            self.pop();
+        }
+        self.order.insert(self.clock, page);
+        self.clock += 1;
+    }
+
+    /// Forgets the least recently used page and returns it.
+    fn pop(&mut self) -> Option<(Pid, usize)> {
+        let (_, page) = self.order.pop_first()?;
+        self.pages.remove(&page);
+        Some(page)
+    }
+}
+
+static LRU: SpinNoIrq<PageLru> = SpinNoIrq::new(PageLru::new());
+
+/// Bounce buffer for page I/O, so that swapping does not need to allocate
+/// while memory is short.
+static PAGE_BUFFER: Mutex<[u8; PAGE_SIZE_4K]> = Mutex::new([0; PAGE_SIZE_4K]);
+
+fn read_u32(page: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(page[offset..offset + 4].try_into().unwrap())
+}
+
+/// A page slot in a swap area, freed when dropped.
+struct SwapSlot {
+    area: Arc<SwapArea>,
+    index: u32,
+}
+
+impl SwapSlot {
+    fn alloc() -> Option<Self> {
+        SWAP_AREAS.lock().iter().find_map(|area| {
+            let index = area.free.lock().pop()?;
+            Some(Self {
+                area: area.clone(),
+                index,
+            })
+        })
+    }
+
+    fn offset(&self) -> u64 {
+        self.index as u64 * PAGE_SIZE_4K as u64
+    }
+
+    fn read(&self, mut buf: &mut [u8]) -> AxResult {
+        if self.area.file.read_at(&mut buf, self.offset())? != PAGE_SIZE_4K {
+            return Err(AxError::Io);
+        }
+        Ok(())
+    }
+
+    fn write(&self, mut buf: &[u8]) -> AxResult {
+        if self.area.file.write_at(&mut buf, self.offset())? != PAGE_SIZE_4K {
+            return Err(AxError::Io);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        self.area.free.lock().push(self.index);
+    }
+}
+
+#[derive(Clone)]
+struct SwapEntry {
+    /// Shared by the copies of the page made by fork
+    slot: Arc<SwapSlot>,
+    flags: MappingFlags,
+}
+
+impl RangeValue for SwapEntry {}
+
+/// The swapped-out pages of an address space.
+///
+/// A swapped-out page stays within its mapping but is no longer present in
+/// the page table, so faults on it have to look here before populating it
+/// afresh.
+#[derive(Default, Clone)]
+pub struct SwapEntries(RangeMap<SwapEntry>);
+
+impl SwapEntries {
+    /// Forgets the swapped-out pages within `[start, end)`.
+    pub fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.0.remove(start, end);
+    }
+
+    /// Changes the mapping flags the pages within `[start, end)` will be
+    /// swapped in with.
+    pub fn protect(&mut self, start: VirtAddr, end: VirtAddr, flags: MappingFlags) {
+        for entry in self.0.overlapping_mut(start, end) {
+            entry.flags = flags;
+        }
+    }
+
+    /// Returns whether the page containing `vaddr` is swapped out.
+    pub fn contains(&self, vaddr: VirtAddr) -> bool {
+        self.0.contains(vaddr)
+    }
+
+    /// Returns where the page containing `vaddr` is swapped out to: the swap
+    /// type, which is the index of the area in priority order, and the page
+    /// offset within the area.
+    pub fn location(&self, vaddr: VirtAddr) -> Option<(usize, u64)> {
+        let entry = self.0.get(vaddr)?;
+        let area = SWAP_AREAS
+            .lock()
+            .iter()
+            .position(|area| Arc::ptr_eq(area, &entry.slot.area))?;
+        Some((area, entry.slot.index as u64))
+    }
+
+    /// Returns, for each swapped-out page within `[start, end)`, the number
+    /// of address spaces sharing its swap slot.
+    pub fn sharers(&self, start: VirtAddr, end: VirtAddr) -> impl Iterator<Item = usize> + '_ {
+        self.0
+            .overlapping(start, end)
+            .map(|(_, entry)| Arc::strong_count(&entry.slot))
+    }
+
+    /// Forgets all swapped-out pages.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Returns the number of swapped-out pages.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether no page is swapped out.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn swap_in(&mut self, aspace: &mut AddrSpace, page: VirtAddr) -> AxResult {
+        let Some(entry) = self.0.get(page) else {
+            return Ok(());
+        };
+        let mut buf = PAGE_BUFFER.lock();
+        entry.slot.read(buf.as_mut_slice())?;
+        aspace.populate_area(page, PAGE_SIZE_4K, entry.flags)?;
+        aspace.write(page, buf.as_slice())?;
+        self.0.remove(page, page + PAGE_SIZE_4K);
+        count_vm_event(VmEvent::SwapIn);
+        count_vm_event(VmEvent::PageAlloc);
+        Ok(())
+    }
+}
+
+/// Swaps the page containing `vaddr` back in if it was swapped out.
+///
+/// Returns whether the page was swapped in.
+pub fn swap_in_page(
+    proc_data: &ProcessData,
+    aspace: &mut AddrSpace,
+    vaddr: VirtAddr,
+) -> AxResult<bool> {
+    let mut entries = proc_data.swap.lock();
+    let page = vaddr.align_down_4k();
+    if !entries.contains(page) {
+        return Ok(false);
+    }
+    entries.swap_in(aspace, page)?;
+    proc_data.add_rss(1);
+    Ok(true)
+}
+
+/// Swaps all swapped-out pages within `[start, start + len)` back in.
+pub fn swap_in_range(
+    proc_data: &ProcessData,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    len: usize,
+) -> AxResult {
+    let mut entries = proc_data.swap.lock();
+    let pages = entries
+        .0
+        .overlapping(start.align_down_4k(), start + len)
+        .map(|(page, _)| page.start)
+        .collect::<Vec<_>>();
+    for page in pages {
+        entries.swap_in(aspace, page)?;
+        proc_data.add_rss(1);
+    }
+    Ok(())
+}
+
+fn swap_out(proc_data: &ProcessData, vaddr: VirtAddr) -> AxResult<bool> {
+    // The swapped-out pages are tracked per process, so an address space
+    // shared with another process must stay resident.
+    if Arc::strong_count(&proc_data.aspace) > 1 {
+        return Ok(false);
+    }
+    let mut aspace = proc_data.aspace.lock();
+    let Some(area) = aspace.find_area(vaddr) else {
+        return Ok(false);
+    };
+    if !matches!(area.backend(), Backend::Cow { .. }) {
+        return Ok(false);
+    }
+    let flags = area.flags();
+    if !matches!(
+        aspace.page_table().query(vaddr),
+        Ok((_, _, PageSize::Size4K))
+    ) {
+        return Ok(false);
+    }
+
+    let slot = SwapSlot::alloc().ok_or(AxError::NoMemory)?;
+    let mut buf = PAGE_BUFFER.lock();
+    aspace.read(vaddr, buf.as_mut_slice())?;
+    slot.write(buf.as_slice())?;
+    drop(buf);
+    // Only the page table entry goes. The area stays as it is, and faults on
+    // the page find it among the swapped-out pages.
+    let (paddr, _, flush) = aspace
+        .page_table_mut()
+        .unmap(vaddr)
+        .map_err(|_| AxError::BadAddress)?;
+    flush.ignore();
+    // Threads of the process on other CPUs may still have the page cached.
+    flush_tlb(proc_data, Some(vaddr));
+    axalloc::global_allocator().dealloc_pages(phys_to_virt(paddr).as_usize(), 1);
+    proc_data.sub_rss(1);
+    proc_data.swap.lock().0.insert(
+        vaddr,
+        vaddr + PAGE_SIZE_4K,
+        SwapEntry {
+            slot: Arc::new(slot),
+            flags,
+        },
+    );
+    count_vm_event(VmEvent::SwapOut);
+    Ok(true)
+}
+
+/// Records a fault on a private anonymous page, making it the most recently
+/// used one.
+pub fn touch_page(pid: Pid, vaddr: VirtAddr) {
+    if SWAP_AREAS.lock().is_empty() {
+        return;
+    }
+    LRU.lock().touch((pid, vaddr.align_down_4k().as_usize()));
+}
+
+/// Swaps out up to `count` of the least recently used private anonymous
+/// pages, returning how many were swapped out.
+pub fn reclaim_pages(count: usize) -> usize {
+    let mut reclaimed = 0;
+    while reclaimed < count {
+        let Some((pid, page)) = LRU.lock().pop() else {
+            break;
+        };
+        let Ok(proc_data) = get_process_data(pid) else {
+            continue;
+        };
+        match swap_out(&proc_data, VirtAddr::from(page)) {
+            Ok(true) => reclaimed += 1,
+            Ok(false) => {}
+            Err(err) => {
+                warn!("Failed to swap out page {page:#x} of process {pid}: {err:?}");
+                break;
+            }
+        }
+    }
+    reclaimed
+}
+
+/// Adds a swap area on `file`, which must start with a `mkswap` header.
+///
+/// Areas with higher `priority` are used first. Without a priority, areas are
+/// used in the order they were added.
+pub fn swapon(file: FileBackend, path: String, priority: Option<i32>) -> AxResult {
+    let mut areas = SWAP_AREAS.lock();
+    if areas.iter().any(|area| area.path == path) {
+        return Err(AxError::ResourceBusy);
+    }
+
+    let mut header = vec![0; PAGE_SIZE_4K];
+    let mut buf = header.as_mut_slice();
+    if file.read_at(&mut buf, 0)? != PAGE_SIZE_4K
+        || &header[PAGE_SIZE_4K - SWAP_MAGIC.len()..] != SWAP_MAGIC
+        || read_u32(&header, SWAP_HEADER_OFFSET) != 1
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let file_pages = (file.location().len()? / PAGE_SIZE_4K as u64) as u32;
+    let last_page = read_u32(&header, SWAP_HEADER_OFFSET + 4).min(file_pages.saturating_sub(1));
+    let nr_badpages = (read_u32(&header, SWAP_HEADER_OFFSET + 8) as usize).min(SWAP_BADPAGES_MAX);
+    let badpages = (0..nr_badpages)
+        .map(|i| read_u32(&header, SWAP_BADPAGES_OFFSET + i * 4))
+        .collect::<Vec<_>>();
+    let free = (1..=last_page)
+        .rev()
+        .filter(|page| !badpages.contains(page))
+        .collect::<Vec<_>>();
+    if free.is_empty() {
+        return Err(AxError::InvalidInput);
+    }
+
+    let priority = priority.unwrap_or_else(|| NEXT_PRIORITY.fetch_sub(1, Ordering::Relaxed));
+    info!(
+        "Adding {}k swap on {path}, priority {priority}",
+        free.len() * PAGE_SIZE_4K / 1024
+    );
+    areas.push(Arc::new(SwapArea {
+        path,
+        file,
+        priority,
+        pages: free.len(),
+        free: SpinNoIrq::new(free),
+    }));
+    areas.sort_by_key(|area| Reverse(area.priority));
+    Ok(())
+}
+
+/// Removes the swap area at `path`, swapping all of its pages back in.
+pub fn swapoff(path: &str) -> AxResult {
+    let area = {
+        let mut areas = SWAP_AREAS.lock();
+        let index = areas
+            .iter()
+            .position(|area| area.path == path)
+            .ok_or(AxError::InvalidInput)?;
+        areas.remove(index)
+    };
+
+    for proc_data in processes() {
+        let mut aspace = proc_data.aspace.lock();
+        let mut entries = proc_data.swap.lock();
+        let pages = entries
+            .0
+            .iter()
+            .filter(|(_, entry)| Arc::ptr_eq(&entry.slot.area, &area))
+            .map(|(page, _)| page.start)
+            .collect::<Vec<_>>();
+        for page in pages {
+            if let Err(err) = entries.swap_in(&mut aspace, page) {
+                let mut areas = SWAP_AREAS.lock();
+                areas.push(area);
+                areas.sort_by_key(|area| Reverse(area.priority));
+                return Err(err);
+            }
+            proc_data.add_rss(1);
+        }
+    }
+    info!("Removed swap on {path}");
+    Ok(())
+}
+
+/// Usage of a swap area, as shown in `/proc/swaps`.
+pub struct SwapAreaStat {
+    /// Absolute path of the swap file or device
+    pub path: String,
+    /// Whether the area is a block device rather than a file
+    pub is_device: bool,
+    /// Number of usable pages
+    pub pages: usize,
+    /// Number of pages in use
+    pub used: usize,
+    /// Priority of the area
+    pub priority: i32,
+}
+
+/// Returns the usage of all swap areas.
+pub fn swap_areas() -> Vec<SwapAreaStat> {
+    SWAP_AREAS
+        .lock()
+        .iter()
+        .map(|area| SwapAreaStat {
+            path: area.path.clone(),
+            is_device: matches!(area.file, FileBackend::Direct(_)),
+            pages: area.pages,
+            used: area.pages - area.free.lock().len(),
+            priority: area.priority,
+        })
+        .collect()
+}
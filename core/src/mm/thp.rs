@@ -0,0 +1,200 @@
+//! Transparent huge pages for anonymous memory.
+
+use alloc::{vec, vec::Vec};
+
+use axerrno::{AxError, AxResult};
+use axhal::paging::{MappingFlags, PageSize};
+use axmm::{AddrSpace, backend::Backend};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+
+use super::{
+    range_map::RangeMap,
+    vmstat::{VmEvent, count_vm_event},
+};
+
+/// The size of a transparent huge page.
+pub const HPAGE_SIZE: usize = PageSize::Size2M as usize;
+
+/// Transparent huge page bookkeeping of a user address space.
+///
+/// The address space itself does not remember which page size an area was
+/// mapped with, so the ranges eligible for and currently backed by huge pages
+/// are tracked here.
+#[derive(Default, Clone)]
+pub struct ThpAreas {
+    /// Private anonymous ranges that may be backed by huge pages
+    anon: RangeMap<()>,
+    /// Ranges currently backed by huge pages
+    huge: RangeMap<()>,
+}
+
+impl ThpAreas {
+    /// Records a private anonymous mapping.
+    pub fn add_anon(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.anon.insert(start, end, ());
+    }
+
+    /// Records a range mapped with huge pages.
+    pub fn add_huge(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.huge.insert(start, end, ());
+    }
+
+    /// Forgets everything about the given range, e.g. after it is unmapped.
+    pub fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.anon.remove(start, end);
+        self.huge.remove(start, end);
+    }
+
+    /// Forgets all ranges.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Checks if the given address is in a range that may be backed by huge
+    /// pages.
+    pub fn is_eligible(&self, addr: VirtAddr) -> bool {
+        self.anon.contains(addr)
+    }
+
+    /// Checks if the given address is backed by a huge page.
+    pub fn is_huge(&self, addr: VirtAddr) -> bool {
+        let chunk = addr.align_down(HPAGE_SIZE);
+        self.huge.contains_range(chunk, chunk + HPAGE_SIZE)
+    }
+
+    /// Splits the huge page containing `addr` into base pages.
+    pub fn split(&mut self, aspace: &mut AddrSpace, addr: VirtAddr) -> AxResult {
+        if !self.is_huge(addr) {
+            return Ok(());
+        }
+        let chunk = addr.align_down(HPAGE_SIZE);
+        remap(aspace, chunk, PageSize::Size4K)?;
+        self.huge.remove(chunk, chunk + HPAGE_SIZE);
+        count_vm_event(VmEvent::ThpSplitPage);
+        Ok(())
+    }
+
+    /// Splits the huge pages that `[start, end)` only partially covers, so that
+    /// the range can be unmapped or protected with base page granularity.
+    pub fn split_boundaries(
+        &mut self,
+        aspace: &mut AddrSpace,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) -> AxResult {
+        for addr in [start, end] {
+            if !addr.is_aligned(HPAGE_SIZE) {
+                self.split(aspace, addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits every huge page overlapping `[start, end)`.
+    ///
+    /// Used by `MADV_NOHUGEPAGE`.
+    pub fn split_range(
+        &mut self,
+        aspace: &mut AddrSpace,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) -> AxResult {
+        let start = start.align_down(HPAGE_SIZE);
+        let end = end.align_up(HPAGE_SIZE);
+        let chunks = self
+            .huge
+            .overlapping(start, end)
+            .flat_map(|(range, _)| {
+                (range.start.max(start).as_usize()..range.end.min(end).as_usize())
+                    .step_by(HPAGE_SIZE)
+            })
+            .collect::<Vec<_>>();
+        for chunk in chunks {
+            self.split(aspace, chunk.into())?;
+        }
+        Ok(())
+    }
+
+    /// Backs every huge-page-sized chunk of private anonymous memory inside
+    /// `[start, end)` with a huge page, preserving its contents.
+    ///
+    /// Used by `MADV_HUGEPAGE`.
+    pub fn collapse_range(
+        &mut self,
+        aspace: &mut AddrSpace,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) -> AxResult {
+        let mut chunk = start.align_up(HPAGE_SIZE);
+        while chunk + HPAGE_SIZE <= end {
+            let chunk_end = chunk + HPAGE_SIZE;
+            if self.anon.contains_range(chunk, chunk_end)
+                && !self.is_huge(chunk)
+                && aspace
+                    .find_area(chunk)
+                    .is_some_and(|area| area.end() >= chunk_end)
+            {
+                remap(aspace, chunk, PageSize::Size2M)?;
+                self.add_huge(chunk, chunk_end);
+            }
+            chunk = chunk_end;
+        }
+        Ok(())
+    }
+
+    /// Counts the huge pages that are currently populated.
+    pub fn anon_huge_pages(&self, aspace: &AddrSpace) -> usize {
+        let pt = aspace.page_table();
+        self.huge
+            .iter()
+            .flat_map(|(range, _)| {
+                (range.start.as_usize()..range.end.as_usize()).step_by(HPAGE_SIZE)
+            })
+            .filter(|chunk| {
+                pt.query(VirtAddr::from_usize(*chunk))
+                    .is_ok_and(|(_, _, size)| size == PageSize::Size2M)
+            })
+            .count()
+    }
+}
+
+/// Re-maps the huge-page-sized chunk at `chunk` with pages of `page_size`,
+/// copying over the contents of the pages that are present.
+fn remap(aspace: &mut AddrSpace, chunk: VirtAddr, page_size: PageSize) -> AxResult {
+    let flags = aspace.find_area(chunk).ok_or(AxError::BadAddress)?.flags();
+
+    // Pages that are not present read as zero either way.
+    let mut saved = Vec::new();
+    let mut addr = chunk;
+    while addr < chunk + HPAGE_SIZE {
+        let size = match aspace.page_table().query(addr) {
+            Ok((_, _, size)) => size as usize,
+            Err(_) => {
+                addr += PAGE_SIZE_4K;
+                continue;
+            }
+        };
+        let mut data = vec![0; size];
+        aspace.read(addr, &mut data)?;
+        saved.push((addr, data));
+        addr += size;
+    }
+
+    aspace.unmap(chunk, HPAGE_SIZE)?;
+    aspace.map(
+        chunk,
+        HPAGE_SIZE,
+        flags,
+        false,
+        Backend::new_alloc(chunk, page_size),
+    )?;
+    for (addr, data) in saved {
+        aspace.populate_area(
+            addr.align_down(page_size),
+            data.len().max(page_size as usize),
+            MappingFlags::READ,
+        )?;
+        aspace.write(addr, &data)?;
+    }
+    Ok(())
+}
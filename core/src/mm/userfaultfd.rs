@@ -0,0 +1,330 @@
+//! Userfaultfd: page faults in registered ranges handed to user space.
+
+use alloc::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use axerrno::{AxError, AxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axpoll::PollSet;
+use axtask::future::{block_on, interruptible};
+use bitflags::bitflags;
+use kspin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+use starry_process::Pid;
+
+use super::{
+    flush_tlb,
+    pagemap::protect_entry,
+    range_map::{RangeMap, RangeValue},
+};
+use crate::task::ProcessData;
+
+/// The fault was caused by a write.
+pub const UFFD_PAGEFAULT_FLAG_WRITE: u64 = 1 << 0;
+/// The fault was caused by a write to a write-protected page.
+pub const UFFD_PAGEFAULT_FLAG_WP: u64 = 1 << 1;
+
+bitflags! {
+    /// The kinds of faults a range is registered for.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct UserFaultMode: u64 {
+        /// Faults on pages that are not present.
+        const MISSING = 1 << 0;
+        /// Writes to pages that are write-protected.
+        const WP = 1 << 1;
+    }
+}
+
+/// A page fault waiting to be resolved by user space.
+#[derive(Debug, Clone, Copy)]
+pub struct UserFault {
+    /// The faulting page
+    pub address: VirtAddr,
+    /// `UFFD_PAGEFAULT_FLAG_*`
+    pub flags: u64,
+    /// The faulting thread
+    pub tid: Pid,
+}
+
+struct FaultWaiter {
+    id: u64,
+    page: VirtAddr,
+    waker: Waker,
+}
+
+/// The state behind a userfaultfd: queued fault messages and the threads
+/// blocked on them.
+#[derive(Default)]
+pub struct UserFaultCtx {
+    events: SpinNoIrq<VecDeque<UserFault>>,
+    waiters: SpinNoIrq<Vec<FaultWaiter>>,
+    next_id: AtomicU64,
+    released: AtomicBool,
+    poll_rx: PollSet,
+}
+
+impl UserFaultCtx {
+    /// Creates a new context.
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    /// Takes the oldest unread fault message.
+    pub fn pop_event(&self) -> Option<UserFault> {
+        self.events.lock().pop_front()
+    }
+
+    /// Returns whether there are unread fault messages.
+    pub fn has_events(&self) -> bool {
+        !self.events.lock().is_empty()
+    }
+
+    /// Registers a waker to be woken when a fault message is queued.
+    pub fn register_reader(&self, context: &mut Context<'_>) {
+        self.poll_rx.register(context.waker());
+    }
+
+    /// Wakes the threads blocked on faults within `[start, start + len)`.
+    pub fn wake(&self, start: VirtAddr, len: usize) {
+        let end = start + len;
+        self.waiters.lock().retain(|waiter| {
+            if (start..end).contains(&waiter.page) {
+                waiter.waker.wake_by_ref();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Detaches the context once its file is closed, letting blocked and
+    /// future faults be handled by the kernel.
+    pub fn release(&self) {
+        self.released.store(true, Ordering::Release);
+        for waiter in self.waiters.lock().drain(..) {
+            waiter.waker.wake();
+        }
+        self.events.lock().clear();
+    }
+
+    /// Returns whether the file of the context has been closed.
+    pub fn is_released(&self) -> bool {
+        self.released.load(Ordering::Acquire)
+    }
+
+    /// Queues `fault` for user space and blocks until the page is woken.
+    ///
+    /// Returns [`AxError::Interrupted`] if a signal arrives first.
+    pub fn wait_fault(&self, fault: UserFault) -> AxResult {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut queued = false;
+        block_on(interruptible(poll_fn(|cx| {
+            let mut waiters = self.waiters.lock();
+            if queued {
+                if waiters.iter().any(|waiter| waiter.id == id) {
+                    return Poll::Pending;
+                }
+                return Poll::Ready(());
+            }
+            if self.is_released() {
+                return Poll::Ready(());
+            }
+            waiters.push(FaultWaiter {
+                id,
+                page: fault.address,
+                waker: cx.waker().clone(),
+            });
+            drop(waiters);
+            queued = true;
+            self.events.lock().push_back(fault);
+            self.poll_rx.wake();
+            Poll::Pending
+        })))
+        .inspect_err(|_| self.waiters.lock().retain(|waiter| waiter.id != id))
+    }
+}
+
+#[derive(Clone)]
+struct UserFaultRange {
+    mode: UserFaultMode,
+    ctx: Weak<UserFaultCtx>,
+}
+
+impl RangeValue for UserFaultRange {}
+
+impl RangeValue for MappingFlags {}
+
+/// The ranges of an address space registered with userfaultfd.
+#[derive(Default)]
+pub struct UserFaultRanges {
+    ranges: RangeMap<UserFaultRange>,
+    /// Write-protected pages and the flags to restore when unprotected
+    wp_pages: RangeMap<MappingFlags>,
+}
+
+impl UserFaultRanges {
+    /// Registers `[start, end)` with `ctx`.
+    ///
+    /// Fails with [`AxError::ResourceBusy`] if part of the range is already
+    /// registered with another userfaultfd.
+    pub fn register(
+        &mut self,
+        start: VirtAddr,
+        end: VirtAddr,
+        mode: UserFaultMode,
+        ctx: &Arc<UserFaultCtx>,
+    ) -> AxResult {
+        let busy = self.ranges.overlapping(start, end).any(|(_, range)| {
+            range
+                .ctx
+                .upgrade()
+                .is_some_and(|other| !other.is_released() && !Arc::ptr_eq(&other, ctx))
+        });
+        if busy {
+            return Err(AxError::ResourceBusy);
+        }
+        self.ranges.insert(
+            start,
+            end,
+            UserFaultRange {
+                mode,
+                ctx: Arc::downgrade(ctx),
+            },
+        );
+        Ok(())
+    }
+
+    /// Unregisters `[start, end)`, splitting ranges that straddle its ends.
+    pub fn unregister(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.ranges.remove(start, end);
+    }
+
+    /// Returns the mode and the live context `vaddr` is registered with.
+    pub fn find(&self, vaddr: VirtAddr) -> Option<(UserFaultMode, Arc<UserFaultCtx>)> {
+        let range = self.ranges.get(vaddr)?;
+        let ctx = range.ctx.upgrade().filter(|ctx| !ctx.is_released())?;
+        Some((range.mode, ctx))
+    }
+
+    /// Returns whether all of `[start, end)` is registered with `ctx`.
+    pub fn is_registered(&self, start: VirtAddr, end: VirtAddr, ctx: &Arc<UserFaultCtx>) -> bool {
+        let mut addr = start;
+        while addr < end {
+            let Some((range, value)) = self.ranges.find(addr) else {
+                return false;
+            };
+            if !value
+                .ctx
+                .upgrade()
+                .is_some_and(|other| Arc::ptr_eq(&other, ctx))
+            {
+                return false;
+            }
+            addr = range.end;
+        }
+        true
+    }
+
+    /// Returns whether the page containing `vaddr` is write-protected.
+    pub fn is_write_protected(&self, vaddr: VirtAddr) -> bool {
+        self.wp_pages.contains(vaddr)
+    }
+
+    /// Write-protects or unprotects the present pages within `[start, end)`
+    /// of `aspace`, the address space of `proc_data`.
+    ///
+    /// Only the page table entries are changed; the areas keep their
+    /// permissions.
+    pub fn write_protect(
+        &mut self,
+        proc_data: &ProcessData,
+        aspace: &mut AddrSpace,
+        start: VirtAddr,
+        end: VirtAddr,
+        protect: bool,
+    ) -> AxResult {
+        let mut protected_any = false;
+        for page in (start.as_usize()..end.as_usize()).step_by(PAGE_SIZE_4K) {
+            let vaddr = VirtAddr::from(page);
+            if protect {
+                if self.wp_pages.contains(vaddr) {
+                    continue;
+                }
+                let Ok((_, pte_flags, _)) = aspace.page_table().query(vaddr) else {
+                    continue;
+                };
+                let Some(flags) = aspace.find_area(vaddr).map(|area| area.flags()) else {
+                    continue;
+                };
+                protect_entry(aspace, vaddr, pte_flags - MappingFlags::WRITE)?;
+                protected_any = true;
+                self.wp_pages.insert(vaddr, vaddr + PAGE_SIZE_4K, flags);
+            } else if let Some(&flags) = self.wp_pages.get(vaddr) {
+                self.wp_pages.remove(vaddr, vaddr + PAGE_SIZE_4K);
+                protect_entry(aspace, vaddr, flags)?;
+            }
+        }
+        if protected_any {
+            flush_tlb(proc_data, None);
+        }
+        Ok(())
+    }
+
+    /// Drops the registrations with `ctx` once its file is closed and
+    /// unprotects their pages in `aspace`, the address space of `proc_data`.
+    pub fn release(
+        &mut self,
+        proc_data: &ProcessData,
+        aspace: &mut AddrSpace,
+        ctx: &Arc<UserFaultCtx>,
+    ) -> AxResult {
+        let released: Vec<_> = self
+            .ranges
+            .iter()
+            .filter(|(_, value)| {
+                value
+                    .ctx
+                    .upgrade()
+                    .is_none_or(|other| Arc::ptr_eq(&other, ctx))
+            })
+            .map(|(range, _)| range)
+            .collect();
+        for range in released {
+            self.ranges.remove(range.start, range.end);
+            self.write_protect(proc_data, aspace, range.start, range.end, false)?;
+        }
+        Ok(())
+    }
+
+    /// Gives the write-protected pages back their original flags in `aspace`,
+    /// a copy of the address space made by fork, which does not inherit the
+    /// registrations.
+    pub fn restore_protection(&self, aspace: &mut AddrSpace) -> AxResult {
+        for (range, &flags) in self.wp_pages.iter() {
+            protect_entry(aspace, range.start, flags)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the registrations and write protection within `[start, end)`,
+    /// for memory that is unmapped.
+    pub fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.unregister(start, end);
+        self.wp_pages.remove(start, end);
+    }
+
+    /// Forgets all registrations.
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+        self.wp_pages.clear();
+    }
+}
@@ -0,0 +1,598 @@
+//! The mappings of an address space as user space sees them, for
+//! `/proc/[pid]/maps` and `/proc/[pid]/smaps`.
+
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::ops::Range;
+
+use axfs_ng_vfs::Location;
+use axhal::paging::{MappingFlags, PageSize};
+use axmm::{AddrSpace, backend::Backend};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+
+use super::{
+    pagemap::{
+        KPF_ANON, KPF_COMPOUND_HEAD, KPF_COMPOUND_TAIL, KPF_DIRTY, KPF_HUGE, KPF_LRU, KPF_MMAP,
+        KPF_SWAPBACKED, KPF_THP, KPF_UPTODATE,
+    },
+    range_map::{RangeMap, RangeValue},
+};
+use crate::{
+    config::{SIGNAL_TRAMPOLINE, USER_STACK_TOP},
+    task::{ProcessData, processes},
+};
+
+/// The file, or the name of the anonymous memory, a range is mapped from.
+#[derive(Clone)]
+pub struct MappedFile {
+    /// The path shown in `/proc/[pid]/maps`
+    pub path: String,
+    /// The offset within the file of the start of the range
+    pub offset: u64,
+    /// The device of the file
+    pub device: u64,
+    /// The inode of the file
+    pub inode: u64,
+    /// The regular file mapped with `mmap`, whose size bounds the pages that
+    /// can be accessed
+    pub location: Option<Location>,
+}
+
+impl MappedFile {
+    /// Names memory that isn't backed by a file, e.g. `/dev/zero (deleted)`
+    /// for shared anonymous mappings.
+    pub fn anonymous(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            offset: 0,
+            device: 0,
+            inode: 0,
+            location: None,
+        }
+    }
+}
+
+impl RangeValue for MappedFile {
+    /// Anonymous memory has no offset to move along.
+    fn advance(&self, offset: usize) -> Self {
+        let mut file = self.clone();
+        if file.inode != 0 {
+            file.offset += offset as u64;
+        }
+        file
+    }
+}
+
+/// The files mapped into an address space.
+///
+/// The address space only knows the backend of an area, not where it came
+/// from, so the names are tracked here.
+#[derive(Default, Clone)]
+pub struct MappedFiles(RangeMap<MappedFile>);
+
+impl MappedFiles {
+    /// Records that `file` is mapped at `[start, end)`.
+    pub fn insert(&mut self, start: VirtAddr, end: VirtAddr, file: MappedFile) {
+        self.0.insert(start, end, file);
+    }
+
+    /// Forgets the mappings within `[start, end)`, splitting ranges that
+    /// straddle its ends.
+    pub fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.0.remove(start, end);
+    }
+
+    /// Returns whether `vaddr` lies in a page of a mapped file that starts at
+    /// or beyond the end of the file, so that nothing backs it.
+    pub fn beyond_eof(&self, vaddr: VirtAddr) -> bool {
+        self.find(vaddr).is_some_and(|file| {
+            file.location
+                .as_ref()
+                .and_then(|loc| loc.len().ok())
+                .is_some_and(|len| {
+                    file.offset & !(PAGE_SIZE_4K as u64 - 1)
+                        >= len.next_multiple_of(PAGE_SIZE_4K as u64)
+                })
+        })
+    }
+
+    /// Returns the file mapped at `vaddr`, with the offset adjusted to
+    /// `vaddr`.
+    pub fn find(&self, vaddr: VirtAddr) -> Option<MappedFile> {
+        let (range, file) = self.0.find(vaddr)?;
+        Some(file.advance(vaddr - range.start))
+    }
+
+    /// Forgets all mappings.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// What the pages of a [`Vma`] are backed by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VmaKind {
+    /// Private anonymous memory
+    Anonymous,
+    /// Private copy-on-write mapping of a file
+    PrivateFile,
+    /// Shared memory without a file, e.g. `MAP_SHARED | MAP_ANONYMOUS`
+    SharedMemory,
+    /// Shared mapping of a file
+    SharedFile,
+    /// Physical memory that doesn't belong to the process
+    Linear,
+}
+
+/// A contiguous range of an address space with the same permissions and
+/// backing, one line of `/proc/[pid]/maps`.
+#[derive(Debug, Clone)]
+pub struct Vma {
+    /// The first address
+    pub start: VirtAddr,
+    /// The address after the last
+    pub end: VirtAddr,
+    /// The permissions
+    pub flags: MappingFlags,
+    /// Whether writes are shared with the backing memory
+    pub shared: bool,
+    /// The offset within the file of the start of the range
+    pub offset: u64,
+    /// The device of the file
+    pub device: u64,
+    /// The inode of the file
+    pub inode: u64,
+    /// The path or pseudo-path such as `[heap]`, empty for anonymous memory
+    pub name: String,
+    kind: VmaKind,
+}
+
+impl Vma {
+    /// Returns whether the range maps physical memory that doesn't belong to
+    /// the process, such as a device or the signal trampoline.
+    pub fn is_linear(&self) -> bool {
+        self.kind == VmaKind::Linear
+    }
+
+    /// Returns whether a present page of the range with the page table
+    /// `flags` is anonymous memory of the process.
+    pub(crate) fn is_anonymous_page(&self, flags: MappingFlags) -> bool {
+        match self.kind {
+            VmaKind::Anonymous => true,
+            // Writable pages of a private file mapping have been copied.
+            VmaKind::PrivateFile => flags.contains(MappingFlags::WRITE),
+            _ => false,
+        }
+    }
+}
+
+/// Lists the mappings of `aspace`, which belongs to `proc_data`.
+///
+/// Adjacent areas with the same permissions that continue each other, such as
+/// the pieces a transparent huge page mapping is split into, are merged.
+pub fn vmas(proc_data: &ProcessData, aspace: &AddrSpace) -> Vec<Vma> {
+    let files = proc_data.mapped_files.lock();
+    let heap_bottom = VirtAddr::from(proc_data.get_heap_bottom());
+    let mut vmas: Vec<Vma> = Vec::new();
+    for area in aspace.areas() {
+        let file = files.find(area.start());
+        let kind = match area.backend() {
+            Backend::Linear { .. } => VmaKind::Linear,
+            Backend::Shared(_) if file.as_ref().is_some_and(|file| file.inode != 0) => {
+                VmaKind::SharedFile
+            }
+            Backend::Shared(_) => VmaKind::SharedMemory,
+            Backend::File(_) => VmaKind::SharedFile,
+            _ if file.is_some() => VmaKind::PrivateFile,
+            _ => VmaKind::Anonymous,
+        };
+        let shared = match kind {
+            VmaKind::SharedFile | VmaKind::SharedMemory => true,
+            // Device memory and secret memory are named, the signal
+            // trampoline isn't.
+            VmaKind::Linear => file.is_some(),
+            _ => false,
+        };
+        let flags =
+            area.flags() & (MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE);
+
+        let file = file.unwrap_or_else(|| MappedFile::anonymous(""));
+        if let Some(last) = vmas.last_mut()
+            && last.kind == kind
+            && last.end == area.start()
+            && last.flags == flags
+            && (kind == VmaKind::Anonymous
+                || (file.inode != 0
+                    && last.inode == file.inode
+                    && last.device == file.device
+                    && last.offset + (last.end - last.start) as u64 == file.offset))
+        {
+            last.end = area.end();
+            continue;
+        }
+
+        vmas.push(Vma {
+            start: area.start(),
+            end: area.end(),
+            flags,
+            shared,
+            offset: file.offset,
+            device: file.device,
+            inode: file.inode,
+            name: file.path,
+            kind,
+        });
+    }
+
+    for vma in &mut vmas {
+        if !vma.name.is_empty() {
+            continue;
+        }
+        if vma.start == VirtAddr::from(SIGNAL_TRAMPOLINE) {
+            vma.name = "[vdso]".to_string();
+        } else if vma.start == heap_bottom {
+            vma.name = "[heap]".to_string();
+        } else if (vma.start..vma.end).contains(&VirtAddr::from(USER_STACK_TOP - 1)) {
+            vma.name = "[stack]".to_string();
+        }
+    }
+    vmas
+}
+
+/// What is known about a physical page from the address spaces mapping it.
+#[derive(Debug, Default, Clone, Copy)]
+struct FrameInfo {
+    /// The number of times the page is mapped
+    count: usize,
+    /// `KPF_*` flags
+    flags: u64,
+}
+
+/// The number of times each physical page is mapped by user address spaces
+/// and the `/proc/kpageflags` flags derived from its mappings, keyed by the
+/// page frame number.
+pub struct PageMapCounts(BTreeMap<usize, FrameInfo>);
+
+impl PageMapCounts {
+    /// Counts the mappings of every physical page in all address spaces.
+    ///
+    /// The address spaces are locked one at a time, so the caller must not
+    /// hold any of them.
+    pub fn collect() -> Self {
+        Self::collect_for(|_| true)
+    }
+
+    /// Counts the mappings of the physical pages that `proc_data` maps within
+    /// the virtual address range `range`, in all address spaces.
+    ///
+    /// The address spaces are locked one at a time, so the caller must not
+    /// hold any of them.
+    pub fn collect_mapped(proc_data: &ProcessData, range: Range<usize>) -> Self {
+        let mut pfns = BTreeSet::new();
+        {
+            let aspace = proc_data.aspace.lock();
+            for area in aspace.areas() {
+                if matches!(area.backend(), Backend::Linear { .. }) {
+                    continue;
+                }
+                let start = area.start().max(VirtAddr::from(range.start));
+                let end = area.end().min(VirtAddr::from(range.end));
+                for_each_page(&aspace, start, end, |_, paddr, _, size| {
+                    let pfn = paddr / PAGE_SIZE_4K;
+                    pfns.extend(pfn..pfn + size as usize / PAGE_SIZE_4K);
+                });
+            }
+        }
+        if pfns.is_empty() {
+            return Self(BTreeMap::new());
+        }
+        Self::collect_for(|pfn| pfns.contains(&pfn))
+    }
+
+    /// Counts the mappings of the physical pages whose frame number `wanted`
+    /// holds for, in all address spaces.
+    ///
+    /// The address spaces are locked one at a time, so the caller must not
+    /// hold any of them.
+    pub fn collect_for(wanted: impl Fn(usize) -> bool) -> Self {
+        let mut owners: Vec<Arc<ProcessData>> = Vec::new();
+        for proc_data in processes() {
+            if !owners
+                .iter()
+                .any(|owner| Arc::ptr_eq(&owner.aspace, &proc_data.aspace))
+            {
+                owners.push(proc_data);
+            }
+        }
+
+        let mut frames = BTreeMap::new();
+        for proc_data in owners {
+            let aspace = proc_data.aspace.lock();
+            let thp = proc_data.thp.lock();
+            for vma in vmas(&proc_data, &aspace) {
+                if vma.kind == VmaKind::Linear {
+                    continue;
+                }
+                for_each_page(&aspace, vma.start, vma.end, |vaddr, paddr, flags, size| {
+                    let mut page_flags = KPF_UPTODATE | KPF_LRU | KPF_MMAP;
+                    if vma.is_anonymous_page(flags) {
+                        page_flags |= KPF_ANON | KPF_SWAPBACKED | KPF_DIRTY;
+                    } else if vma.kind == VmaKind::SharedMemory {
+                        page_flags |= KPF_SWAPBACKED | KPF_DIRTY;
+                    }
+                    if size != PageSize::Size4K {
+                        page_flags |= if thp.is_huge(vaddr) {
+                            KPF_THP
+                        } else {
+                            KPF_HUGE
+                        };
+                    }
+                    let pfn = paddr / PAGE_SIZE_4K;
+                    for i in 0..size as usize / PAGE_SIZE_4K {
+                        if !wanted(pfn + i) {
+                            continue;
+                        }
+                        let frame: &mut FrameInfo = frames.entry(pfn + i).or_default();
+                        frame.count += 1;
+                        frame.flags |= page_flags;
+                        if size != PageSize::Size4K {
+                            frame.flags |= if i == 0 {
+                                KPF_COMPOUND_HEAD
+                            } else {
+                                KPF_COMPOUND_TAIL
+                            };
+                        }
+                    }
+                });
+            }
+        }
+        Self(frames)
+    }
+
+    /// Returns how many times the page at `paddr` is mapped.
+    pub fn get(&self, paddr: usize) -> usize {
+        self.count(paddr / PAGE_SIZE_4K)
+    }
+
+    /// Returns how many times the page with frame number `pfn` is mapped.
+    pub fn count(&self, pfn: usize) -> usize {
+        self.0.get(&pfn).map_or(0, |frame| frame.count)
+    }
+
+    /// Returns the `KPF_*` flags of the page with frame number `pfn`.
+    pub fn flags(&self, pfn: usize) -> u64 {
+        self.0.get(&pfn).map_or(0, |frame| frame.flags)
+    }
+
+    /// Returns the number of mapped pages that have all of the `KPF_*`
+    /// `flags`.
+    pub(crate) fn frames_with(&self, flags: u64) -> usize {
+        self.0
+            .values()
+            .filter(|frame| frame.flags & flags == flags)
+            .count()
+    }
+}
+
+/// Calls `f` with the virtual address, physical address, flags and size of
+/// every present page within `[start, end)`.
+pub(crate) fn for_each_page(
+    aspace: &AddrSpace,
+    start: VirtAddr,
+    end: VirtAddr,
+    mut f: impl FnMut(VirtAddr, usize, MappingFlags, PageSize),
+) {
+    let pt = aspace.page_table();
+    let mut addr = start;
+    while addr < end {
+        match pt.query(addr) {
+            Ok((paddr, flags, size)) => {
+                let offset = addr.align_offset(size as usize);
+                f(addr - offset, paddr.as_usize() - offset, flags, size);
+                addr = addr - offset + size as usize;
+            }
+            Err(_) => addr += PAGE_SIZE_4K,
+        }
+    }
+}
+
+/// The PSS values are kept with this many fractional bits.
+const PSS_SHIFT: u32 = 12;
+
+/// Memory usage of a [`Vma`], in bytes, as reported by `/proc/[pid]/smaps`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VmaUsage {
+    /// Resident memory
+    pub rss: usize,
+    /// Proportional share of the resident memory, with [`PSS_SHIFT`]
+    /// fractional bits
+    pss: u64,
+    /// Proportional share of the dirty resident memory, with [`PSS_SHIFT`]
+    /// fractional bits
+    pss_dirty: u64,
+    /// Resident memory that is clean and also mapped elsewhere
+    pub shared_clean: usize,
+    /// Resident memory that is dirty and also mapped elsewhere
+    pub shared_dirty: usize,
+    /// Resident memory that is clean and only mapped here
+    pub private_clean: usize,
+    /// Resident memory that is dirty and only mapped here
+    pub private_dirty: usize,
+    /// Resident anonymous memory
+    pub anonymous: usize,
+    /// Resident anonymous memory backed by transparent huge pages
+    pub anon_huge: usize,
+    /// Swapped-out memory
+    pub swap: usize,
+    /// Proportional share of the swapped-out memory, with [`PSS_SHIFT`]
+    /// fractional bits
+    swap_pss: u64,
+}
+
+impl VmaUsage {
+    /// Measures the memory usage of `vma`.
+    ///
+    /// There is no dirty bit to look at, so anonymous pages and pages copied
+    /// from a file on write count as dirty and file pages as clean.
+    pub fn measure(
+        proc_data: &ProcessData,
+        aspace: &AddrSpace,
+        vma: &Vma,
+        counts: &PageMapCounts,
+    ) -> Self {
+        let mut usage = Self::default();
+        if vma.kind == VmaKind::Linear {
+            return usage;
+        }
+        for_each_page(aspace, vma.start, vma.end, |_, paddr, flags, size| {
+            let size = size as usize;
+            let anonymous = vma.is_anonymous_page(flags);
+            let dirty = anonymous || vma.kind == VmaKind::SharedMemory;
+            let count = counts.get(paddr).max(1);
+            let pss = ((size as u64) << PSS_SHIFT) / count as u64;
+
+            usage.rss += size;
+            usage.pss += pss;
+            match (count > 1, dirty) {
+                (true, false) => usage.shared_clean += size,
+                (true, true) => usage.shared_dirty += size,
+                (false, false) => usage.private_clean += size,
+                (false, true) => usage.private_dirty += size,
+            }
+            if dirty {
+                usage.pss_dirty += pss;
+            }
+            if anonymous {
+                usage.anonymous += size;
+                if size > PAGE_SIZE_4K {
+                    usage.anon_huge += size;
+                }
+            }
+        });
+        for sharers in proc_data.swap.lock().sharers(vma.start, vma.end) {
+            usage.swap += PAGE_SIZE_4K;
+            usage.swap_pss += ((PAGE_SIZE_4K as u64) << PSS_SHIFT) / sharers as u64;
+        }
+        usage
+    }
+
+    /// Returns the proportional share of the resident memory.
+    pub fn pss(&self) -> usize {
+        (self.pss >> PSS_SHIFT) as usize
+    }
+
+    /// Returns the proportional share of the dirty resident memory.
+    pub fn pss_dirty(&self) -> usize {
+        (self.pss_dirty >> PSS_SHIFT) as usize
+    }
+
+    /// Returns the proportional share of the swapped-out memory.
+    pub fn swap_pss(&self) -> usize {
+        (self.swap_pss >> PSS_SHIFT) as usize
+    }
+
+    /// Adds up the usage of two ranges, for `/proc/[pid]/smaps_rollup`.
+    pub fn add(&mut self, other: &Self) {
+        self.rss += other.rss;
+        self.pss += other.pss;
+        self.pss_dirty += other.pss_dirty;
+        self.shared_clean += other.shared_clean;
+        self.shared_dirty += other.shared_dirty;
+        self.private_clean += other.private_clean;
+        self.private_dirty += other.private_dirty;
+        self.anonymous += other.anonymous;
+        self.anon_huge += other.anon_huge;
+        self.swap += other.swap;
+        self.swap_pss += other.swap_pss;
+    }
+}
+
+/// The memory usage of a process, in bytes, as reported by
+/// `/proc/[pid]/status`, `/proc/[pid]/statm` and `/proc/[pid]/stat`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcessMemory {
+    /// Mapped memory
+    pub size: usize,
+    /// Resident anonymous memory
+    pub rss_anon: usize,
+    /// Resident file pages
+    pub rss_file: usize,
+    /// Resident shared memory
+    pub rss_shmem: usize,
+    /// Private writable memory other than the stack
+    pub data: usize,
+    /// The main thread's stack
+    pub stack: usize,
+    /// Executable mappings of the program
+    pub exe: usize,
+    /// Executable mappings of other files, such as shared libraries
+    pub lib: usize,
+    /// Page tables, estimated as a last-level table for every huge-page-sized
+    /// range with resident base pages
+    pub pte: usize,
+    /// Swapped-out memory
+    pub swap: usize,
+    /// The first address of the executable mappings of the program
+    pub start_code: usize,
+    /// The address after the executable mappings of the program
+    pub end_code: usize,
+}
+
+impl ProcessMemory {
+    /// Measures the memory usage of `proc_data`, whose address space must not
+    /// be locked by the caller.
+    pub fn measure(proc_data: &ProcessData) -> Self {
+        let aspace = proc_data.aspace.lock();
+        let exe_path = proc_data.exe_path.read().clone();
+        let mut usage = Self::default();
+        let mut tables = BTreeSet::new();
+        for vma in vmas(proc_data, &aspace) {
+            let size = vma.end - vma.start;
+            usage.size += size;
+            if vma.kind == VmaKind::Linear {
+                continue;
+            }
+            if vma.name == "[stack]" {
+                usage.stack += size;
+            } else if vma.flags.contains(MappingFlags::WRITE) && !vma.shared {
+                usage.data += size;
+            }
+            if vma.flags.contains(MappingFlags::EXECUTE) && vma.inode != 0 {
+                if vma.name == exe_path {
+                    usage.exe += size;
+                    if usage.start_code == 0 {
+                        usage.start_code = vma.start.as_usize();
+                    }
+                    usage.end_code = vma.end.as_usize();
+                } else {
+                    usage.lib += size;
+                }
+            }
+            for_each_page(&aspace, vma.start, vma.end, |vaddr, _, flags, page_size| {
+                let page_size = page_size as usize;
+                if vma.is_anonymous_page(flags) {
+                    usage.rss_anon += page_size;
+                } else if vma.kind == VmaKind::SharedMemory {
+                    usage.rss_shmem += page_size;
+                } else {
+                    usage.rss_file += page_size;
+                }
+                if page_size == PAGE_SIZE_4K {
+                    tables.insert(vaddr.align_down(PageSize::Size2M).as_usize());
+                }
+            });
+        }
+        usage.pte = tables.len() * PAGE_SIZE_4K;
+        usage.swap = proc_data.swap.lock().len() * PAGE_SIZE_4K;
+        usage
+    }
+
+    /// Returns the resident memory.
+    pub fn rss(&self) -> usize {
+        self.rss_anon + self.rss_file + self.rss_shmem
+    }
+}
@@ -0,0 +1,178 @@
+//! System-wide memory statistics, for `/proc/meminfo`, `/proc/vmstat` and
+//! `sysinfo`.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axalloc::UsageKind;
+use axhal::paging::MappingFlags;
+use axmm::{AddrSpace, backend::Backend};
+use axsync::Mutex;
+use memory_addr::PAGE_SIZE_4K;
+
+use super::{
+    hugetlb::{hugepages_free, hugepages_total},
+    pagemap::{KPF_ANON, KPF_COMPOUND_HEAD, KPF_MMAP, KPF_SWAPBACKED, KPF_THP},
+    swap::swap_areas,
+    thp::HPAGE_SIZE,
+    vma::PageMapCounts,
+};
+use crate::task::{processes, tasks};
+
+/// A memory management event counted in `/proc/vmstat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum VmEvent {
+    /// A page was allocated for user memory.
+    PageAlloc,
+    /// A page fault was serviced.
+    PageFault,
+    /// A page fault had to read from a file.
+    MajorFault,
+    /// A page was read back from swap.
+    SwapIn,
+    /// A page was written out to swap.
+    SwapOut,
+    /// A transparent huge page was allocated on a fault.
+    ThpFaultAlloc,
+    /// A transparent huge page was split into base pages.
+    ThpSplitPage,
+    /// The OOM killer killed a process.
+    OomKill,
+}
+
+/// The names of the [`VmEvent`]s in `/proc/vmstat`, in order.
+const VM_EVENT_NAMES: [&str; 8] = [
+    "pgalloc_normal",
+    "pgfault",
+    "pgmajfault",
+    "pswpin",
+    "pswpout",
+    "thp_fault_alloc",
+    "thp_split_page",
+    "oom_kill",
+];
+
+static VM_EVENTS: [AtomicUsize; VM_EVENT_NAMES.len()] =
+    [const { AtomicUsize::new(0) }; VM_EVENT_NAMES.len()];
+
+/// Counts `count` occurrences of `event`.
+pub fn count_vm_events(event: VmEvent, count: usize) {
+    VM_EVENTS[event as usize].fetch_add(count, Ordering::Relaxed);
+}
+
+/// Counts an occurrence of `event`.
+pub fn count_vm_event(event: VmEvent) {
+    count_vm_events(event, 1);
+}
+
+/// Returns the name and count of every [`VmEvent`].
+pub fn vm_events() -> impl Iterator<Item = (&'static str, usize)> {
+    VM_EVENT_NAMES
+        .iter()
+        .zip(&VM_EVENTS)
+        .map(|(name, count)| (*name, count.load(Ordering::Relaxed)))
+}
+
+/// A snapshot of system-wide memory usage, in bytes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemInfo {
+    /// Memory managed by the page allocator
+    pub total: usize,
+    /// Memory not allocated
+    pub free: usize,
+    /// Memory that can be allocated without swapping: the free memory and
+    /// the page cache
+    pub available: usize,
+    /// The page cache
+    pub cached: usize,
+    /// Anonymous memory mapped by processes
+    pub anon: usize,
+    /// File pages mapped by processes
+    pub mapped: usize,
+    /// Shared memory mapped by processes
+    pub shmem: usize,
+    /// The kernel heap
+    pub slab: usize,
+    /// Kernel stacks of all tasks
+    pub kernel_stack: usize,
+    /// Page tables
+    pub page_tables: usize,
+    /// Writable private memory of all address spaces, whether resident or not
+    pub committed: usize,
+    /// Anonymous memory backed by transparent huge pages
+    pub anon_huge: usize,
+    /// Total swap space
+    pub swap_total: usize,
+    /// Unused swap space
+    pub swap_free: usize,
+    /// Memory of the hugetlb pool
+    pub hugetlb_total: usize,
+    /// Memory of the hugetlb pool not in use
+    pub hugetlb_free: usize,
+}
+
+impl MemInfo {
+    /// Measures the memory usage of the whole system.
+    ///
+    /// Address spaces are locked one at a time, so the caller must not hold
+    /// any of them.
+    pub fn collect() -> Self {
+        let allocator = axalloc::global_allocator();
+        let usages = allocator.usage_stats();
+        let counts = PageMapCounts::collect();
+        let (swap_total, swap_used) = swap_areas().iter().fold((0, 0), |(total, used), area| {
+            (total + area.pages, used + area.used)
+        });
+
+        let anon = counts.frames_with(KPF_ANON);
+        let free = allocator.available_pages() * PAGE_SIZE_4K;
+        let cached = usages.get(UsageKind::PageCache);
+        Self {
+            total: (allocator.used_pages() + allocator.available_pages()) * PAGE_SIZE_4K,
+            free,
+            available: free + cached,
+            cached,
+            anon: anon * PAGE_SIZE_4K,
+            mapped: (counts.frames_with(KPF_MMAP) - anon) * PAGE_SIZE_4K,
+            shmem: (counts.frames_with(KPF_SWAPBACKED) - anon) * PAGE_SIZE_4K,
+            slab: usages.get(UsageKind::RustHeap),
+            kernel_stack: tasks().len() * axconfig::TASK_STACK_SIZE,
+            page_tables: usages.get(UsageKind::PageTable),
+            committed: committed_memory(),
+            anon_huge: counts.frames_with(KPF_ANON | KPF_THP | KPF_COMPOUND_HEAD) * HPAGE_SIZE,
+            swap_total: swap_total * PAGE_SIZE_4K,
+            swap_free: (swap_total - swap_used) * PAGE_SIZE_4K,
+            hugetlb_total: hugepages_total() * HPAGE_SIZE,
+            hugetlb_free: hugepages_free() * HPAGE_SIZE,
+        }
+    }
+}
+
+/// Adds up the writable private areas of all address spaces, the memory that
+/// would have to be backed if it were all touched.
+fn committed_memory() -> usize {
+    let mut aspaces: Vec<Arc<Mutex<AddrSpace>>> = Vec::new();
+    for proc_data in processes() {
+        if !aspaces
+            .iter()
+            .any(|aspace| Arc::ptr_eq(aspace, &proc_data.aspace))
+        {
+            aspaces.push(proc_data.aspace.clone());
+        }
+    }
+    aspaces
+        .iter()
+        .map(|aspace| {
+            aspace
+                .lock()
+                .areas()
+                .filter(|area| {
+                    matches!(area.backend(), Backend::Cow { .. })
+                        && area.flags().contains(MappingFlags::WRITE)
+                })
+                .map(|area| area.size())
+                .sum::<usize>()
+        })
+        .sum()
+}
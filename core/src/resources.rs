@@ -1,14 +1,20 @@
-//! Resource limits.
+//! Resource limits and usage.
 
 use core::ops::{Index, IndexMut};
 
-use linux_raw_sys::general::{RLIM_NLIMITS, RLIMIT_NOFILE, RLIMIT_STACK};
+use axhal::time::TimeValue;
+use linux_raw_sys::general::{
+    RLIM_NLIMITS, RLIMIT_CORE, RLIMIT_NOFILE, RLIMIT_SIGPENDING, RLIMIT_STACK,
+};
 
 /// The maximum number of open files
 pub const AX_FILE_LIMIT: usize = 1024;
 
+/// The maximum number of signals that may be queued
+pub const AX_SIGPENDING_LIMIT: usize = 4096;
+
 /// The limit for a specific resource
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Rlimit {
     /// The current limit for the resource (soft)
     pub current: u64,
@@ -36,6 +42,7 @@ impl From<u64> for Rlimit {
 }
 
 /// Process resource limits
+#[derive(Clone)]
 pub struct Rlimits([Rlimit; RLIM_NLIMITS as usize]);
 
 impl Default for Rlimits {
@@ -43,6 +50,9 @@ impl Default for Rlimits {
         let mut result = Self(Default::default());
         result[RLIMIT_STACK] = (crate::config::USER_STACK_SIZE as u64).into();
         result[RLIMIT_NOFILE] = (AX_FILE_LIMIT as u64).into();
+        result[RLIMIT_SIGPENDING] = (AX_SIGPENDING_LIMIT as u64).into();
+        // Core dumps are off until raised with `ulimit -c`.
+        result[RLIMIT_CORE] = Rlimit::new(0, u64::MAX);
         result
     }
 }
@@ -60,3 +70,39 @@ impl IndexMut<u32> for Rlimits {
         &mut self.0[index as usize]
     }
 }
+
+/// Resource usage statistics, as reported by `getrusage` and `wait4`.
+#[derive(Default, Clone, Copy)]
+pub struct Rusage {
+    /// User CPU time used
+    pub utime: TimeValue,
+    /// System CPU time used
+    pub stime: TimeValue,
+    /// Maximum resident set size, in kilobytes
+    pub maxrss: usize,
+    /// Page faults serviced without any I/O activity
+    pub minflt: u64,
+    /// Page faults serviced that required I/O activity
+    pub majflt: u64,
+    /// Voluntary context switches
+    pub nvcsw: u64,
+    /// Involuntary context switches
+    pub nivcsw: u64,
+}
+
+impl Rusage {
+    /// Merges two usage records.
+    ///
+    /// Times and counters are summed, while `maxrss` takes the larger of the
+    /// two, matching how Linux reports the largest child rather than a total.
+    pub fn collate(mut self, other: Rusage) -> Self {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.maxrss = self.maxrss.max(other.maxrss);
+        self.minflt += other.minflt;
+        self.majflt += other.majflt;
+        self.nvcsw += other.nvcsw;
+        self.nivcsw += other.nivcsw;
+        self
+    }
+}
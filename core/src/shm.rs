@@ -3,16 +3,21 @@
 use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
 
 use axerrno::{AxError, AxResult};
-use axhal::{paging::MappingFlags, time::monotonic_time_nanos};
+use axhal::{
+    paging::{MappingFlags, PageSize},
+    time::monotonic_time_nanos,
+};
 use axmm::backend::SharedPages;
 use axsync::Mutex;
 use linux_raw_sys::{
     ctypes::{c_long, c_ushort},
     general::*,
 };
-use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use starry_process::Pid;
 
+use crate::mm::HugePageReservation;
+
 /// Data structure used to pass permission information to IPC operations.
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -86,6 +91,10 @@ pub struct ShmInner {
     va_range: BTreeMap<Pid, VirtAddrRange>,
     /// physical pages
     pub phys_pages: Option<Arc<SharedPages>>,
+    /// Page size of the physical pages
+    pub page_size: PageSize,
+    /// Huge pages taken from the pool for a `SHM_HUGETLB` segment
+    hugetlb: Option<HugePageReservation>,
     /// whether remove on last detach, see shm_ctl
     pub rmid: bool,
     /// Mapping flags used for this shared memory segment.
@@ -102,6 +111,8 @@ impl ShmInner {
             page_num: memory_addr::align_up_4k(size) / PAGE_SIZE_4K,
             va_range: BTreeMap::new(),
             phys_pages: None,
+            page_size: PageSize::Size4K,
+            hugetlb: None,
             rmid: false,
             mapping_flags,
             shmid_ds: ShmidDs::new(
@@ -130,6 +141,16 @@ impl ShmInner {
         Ok(self.shmid as isize)
     }
 
+    /// Backs this shared memory segment with huge pages of `page_size` taken
+    /// from the huge page pool.
+    pub fn use_huge_pages(&mut self, page_size: PageSize) -> AxResult {
+        let size = (self.page_num * PAGE_SIZE_4K).align_up(page_size);
+        self.hugetlb = Some(HugePageReservation::new(size)?);
+        self.page_num = size / PAGE_SIZE_4K;
+        self.page_size = page_size;
+        Ok(())
+    }
+
     /// Maps the given physical shared pages to this shared memory segment.
     pub fn map_to_phys(&mut self, phys_pages: Arc<SharedPages>) {
         self.phys_pages = Some(phys_pages);
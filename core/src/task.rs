@@ -1,9 +1,14 @@
 //! User task management.
 
+mod membarrier;
+mod reaper;
+mod sigqueue;
 mod stat;
+mod stop;
 
 use alloc::{
     boxed::Box,
+    collections::btree_map::BTreeMap,
     string::String,
     sync::{Arc, Weak},
     vec::Vec,
@@ -11,30 +16,46 @@ use alloc::{
 use core::{
     cell::RefCell,
     ops::Deref,
-    sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, AtomicUsize, Ordering},
 };
 
 use axerrno::{AxError, AxResult};
+use axhal::{paging::MappingFlags, uspace::UserContext};
 use axmm::AddrSpace;
 use axpoll::PollSet;
 use axsync::{Mutex, spin::SpinNoIrq};
-use axtask::{AxTaskRef, TaskExt, TaskInner, WeakAxTaskRef, current};
+use axtask::{AxTaskRef, TaskExt, TaskInner, TaskState, WeakAxTaskRef, current};
 use extern_trait::extern_trait;
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
+use linux_raw_sys::general::{CLD_DUMPED, CLD_EXITED, CLD_KILLED, SA_NOCLDSTOP};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
 use scope_local::{ActiveScope, Scope};
 use spin::RwLock;
 use starry_process::{Pid, Process, ProcessGroup, Session};
 use starry_signal::{
-    SignalInfo, Signo,
+    SignalActionFlags, SignalInfo, SignalSet, Signo,
     api::{ProcessSignalManager, SignalActions, ThreadSignalManager},
 };
 use weak_map::WeakMap;
 
-pub use self::stat::TaskStat;
+pub use self::{
+    membarrier::{
+        MembarrierState, cpus_running_aspace, cpus_running_registered, refresh_membarrier_state,
+    },
+    reaper::reparent_orphans,
+    sigqueue::{QueuedSignals, check_queue_limit, queued_signal_count},
+    stat::TaskStat,
+    stop::{StopEvent, StopState},
+};
 use crate::{
     futex::{FutexKey, FutexTable},
-    resources::Rlimits,
+    mm::{
+        HPAGE_SIZE, HugetlbAreas, INIT_PKRU, MappedFiles, ProtectionKeys, SecretAreas,
+        SoftDirtyPages, SwapEntries, ThpAreas, UserFaultRanges, VmEvent, count_vm_event, read_pkru,
+        resident_pages, resident_pages_in, restore_pkru,
+    },
+    resources::{Rlimits, Rusage},
     time::{TimeManager, TimerState},
 };
 
@@ -52,6 +73,12 @@ impl<T> Deref for AssumeSync<T> {
     }
 }
 
+/// The scheduler priority of a thread that no one waits for
+const DEFAULT_PRIORITY: isize = 0;
+/// The scheduler priority of a thread that owns a priority-inheritance futex
+/// others wait for
+const PI_BOOST_PRIORITY: isize = -20;
+
 /// The inner data of a thread.
 pub struct ThreadInner {
     /// The process data shared by all threads in the process.
@@ -77,8 +104,31 @@ pub struct ThreadInner {
     /// context switches, which is exclusive to the current thread.
     pub time: AssumeSync<RefCell<TimeManager>>,
 
-    /// The OOM score adjustment value.
-    oom_score_adj: AtomicI32,
+    /// Page faults serviced without I/O
+    minflt: AtomicU64,
+    /// Page faults serviced with I/O
+    majflt: AtomicU64,
+    /// Voluntary context switches
+    nvcsw: AtomicU64,
+    /// Involuntary context switches
+    nivcsw: AtomicU64,
+
+    /// The protection key rights register, while the thread is switched out
+    pkru: AtomicU32,
+
+    /// The user registers as of the last entry into the kernel, for core dumps
+    user_context: SpinNoIrq<Option<UserContext>>,
+
+    /// Threads blocked on priority-inheritance futexes this thread owns,
+    /// directly or down a chain of owners that wait themselves
+    pi_waiters: AtomicUsize,
+    /// Whether the thread runs with the priority it inherited from them
+    pi_boosted: AtomicBool,
+    /// The owner of the priority-inheritance futex the thread waits for, or 0
+    pi_blocked_on: AtomicU32,
+    /// The addresses of the priority-inheritance futexes the thread is known
+    /// to own
+    pi_futexes: SpinNoIrq<Vec<usize>>,
 
     /// Ready to exit
     exit: AtomicBool,
@@ -93,7 +143,16 @@ impl ThreadInner {
             clear_child_tid: AtomicUsize::new(0),
             robust_list_head: AtomicUsize::new(0),
             time: AssumeSync(RefCell::new(TimeManager::new())),
-            oom_score_adj: AtomicI32::new(200),
+            minflt: AtomicU64::new(0),
+            majflt: AtomicU64::new(0),
+            nvcsw: AtomicU64::new(0),
+            nivcsw: AtomicU64::new(0),
+            pkru: AtomicU32::new(INIT_PKRU),
+            user_context: SpinNoIrq::new(None),
+            pi_waiters: AtomicUsize::new(0),
+            pi_boosted: AtomicBool::new(false),
+            pi_blocked_on: AtomicU32::new(0),
+            pi_futexes: SpinNoIrq::new(Vec::new()),
             exit: AtomicBool::new(false),
         }
     }
@@ -120,14 +179,122 @@ impl ThreadInner {
             .store(robust_list_head, Ordering::SeqCst);
     }
 
-    /// Get the oom score adjustment value.
-    pub fn oom_score_adj(&self) -> i32 {
-        self.oom_score_adj.load(Ordering::SeqCst)
+    /// Records a page fault that was successfully serviced.
+    pub fn record_page_fault(&self, major: bool) {
+        count_vm_event(VmEvent::PageFault);
+        if major {
+            count_vm_event(VmEvent::MajorFault);
+            self.majflt.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.minflt.fetch_add(1, Ordering::Relaxed);
+        }
     }
 
-    /// Set the oom score adjustment value.
-    pub fn set_oom_score_adj(&self, value: i32) {
-        self.oom_score_adj.store(value, Ordering::SeqCst);
+    /// Sets the protection key rights the thread starts with, inherited from
+    /// the thread that created it.
+    pub fn set_pkru(&self, pkru: u32) {
+        self.pkru.store(pkru, Ordering::Relaxed);
+    }
+
+    /// Records the user registers the thread entered the kernel with.
+    pub fn save_user_context(&self, uctx: &UserContext) {
+        *self.user_context.lock() = Some(*uctx);
+    }
+
+    /// Returns the user registers the thread last entered the kernel with.
+    pub fn user_context(&self) -> Option<UserContext> {
+        *self.user_context.lock()
+    }
+
+    /// Lends the priority of a thread that blocks on a priority-inheritance
+    /// futex this thread owns, or on one owned by a thread waiting for this
+    /// one.
+    pub fn boost_priority(&self) {
+        self.pi_waiters.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Takes back the priority lent by [`Thread::boost_priority`] once the
+    /// waiter stops waiting.
+    pub fn unboost_priority(&self) {
+        self.pi_waiters.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// Returns the priority the thread runs with, lower being higher.
+    ///
+    /// Threads have no priority of their own, so one waited for inherits the
+    /// highest priority of its waiters, which is the boosted one.
+    pub fn effective_priority(&self) -> isize {
+        if self.pi_waiters.load(Ordering::Acquire) > 0 {
+            PI_BOOST_PRIORITY
+        } else {
+            DEFAULT_PRIORITY
+        }
+    }
+
+    /// Hands the priority the thread inherits, if it changed, to the
+    /// scheduler.
+    ///
+    /// Only the current task's priority can be set, so an owner lent a
+    /// priority while it runs takes it up as it next leaves the kernel. An
+    /// owner that is blocked has no use for it until it is woken.
+    pub fn update_priority(&self) {
+        let priority = self.effective_priority();
+        let boosted = priority != DEFAULT_PRIORITY;
+        if self.pi_boosted.swap(boosted, Ordering::AcqRel) != boosted {
+            axtask::set_priority(priority);
+        }
+    }
+
+    /// Returns the owner of the priority-inheritance futex the thread waits
+    /// for.
+    pub fn pi_blocked_on(&self) -> Option<u32> {
+        match self.pi_blocked_on.load(Ordering::Acquire) {
+            0 => None,
+            owner => Some(owner),
+        }
+    }
+
+    /// Sets the owner of the priority-inheritance futex the thread waits for.
+    pub fn set_pi_blocked_on(&self, owner: Option<u32>) {
+        self.pi_blocked_on
+            .store(owner.unwrap_or(0), Ordering::Release);
+    }
+
+    /// Records that the thread owns the priority-inheritance futex at
+    /// `address`.
+    pub fn add_pi_futex(&self, address: usize) {
+        let mut futexes = self.pi_futexes.lock();
+        if !futexes.contains(&address) {
+            futexes.push(address);
+        }
+    }
+
+    /// Records that the thread released the priority-inheritance futex at
+    /// `address`.
+    pub fn remove_pi_futex(&self, address: usize) {
+        self.pi_futexes.lock().retain(|addr| *addr != address);
+    }
+
+    /// Takes the addresses of the priority-inheritance futexes the thread is
+    /// known to own, for them to be released as it exits.
+    pub fn take_pi_futexes(&self) -> Vec<usize> {
+        core::mem::take(&mut *self.pi_futexes.lock())
+    }
+
+    /// Get the resource usage of this thread.
+    ///
+    /// `maxrss` is a per-process value and is left as zero here.
+    pub fn rusage(&self) -> Rusage {
+        let (utime, stime) = self.time.borrow().output();
+        Rusage {
+            utime,
+            stime,
+            maxrss: 0,
+            minflt: self.minflt.load(Ordering::Relaxed),
+            majflt: self.majflt.load(Ordering::Relaxed),
+            nvcsw: self.nvcsw.load(Ordering::Relaxed),
+            nivcsw: self.nivcsw.load(Ordering::Relaxed),
+        }
     }
 
     /// Check if the thread is ready to exit.
@@ -158,9 +325,23 @@ unsafe impl TaskExt for Thread {
         let scope = self.proc_data.scope.read();
         unsafe { ActiveScope::set(&scope) };
         core::mem::forget(scope);
+        restore_pkru(self.pkru.load(Ordering::Relaxed));
+        membarrier::enter_cpu(&self.proc_data);
     }
 
     fn on_leave(&self) {
+        // A task that gives up the CPU because it blocks switches voluntarily;
+        // anything else (preemption, yielding) counts as involuntary.
+        if matches!(current().state(), TaskState::Blocked) {
+            self.nvcsw.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.nivcsw.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(pkru) = read_pkru() {
+            self.pkru.store(pkru, Ordering::Relaxed);
+        }
+        membarrier::leave_cpu();
+
         ActiveScope::set_global();
         unsafe { self.proc_data.scope.force_read_decrement() };
     }
@@ -198,9 +379,27 @@ pub struct ProcessData {
     pub exe_path: RwLock<String>,
     /// The command line arguments
     pub cmdline: RwLock<Arc<Vec<String>>>,
+    /// The auxiliary vector the program was started with
+    pub auxv: RwLock<Arc<Vec<u8>>>,
     /// The virtual memory address space.
     // TODO: scopify
     pub aspace: Arc<Mutex<AddrSpace>>,
+    /// Transparent huge page bookkeeping of the address space
+    pub thp: Mutex<ThpAreas>,
+    /// Huge pages reserved for anonymous `MAP_HUGETLB` mappings
+    pub hugetlb: Mutex<HugetlbAreas>,
+    /// Pages of the address space that are swapped out
+    pub swap: Mutex<SwapEntries>,
+    /// Ranges of the address space registered with userfaultfd
+    pub uffd: Mutex<UserFaultRanges>,
+    /// Secret memory mapped by `memfd_secret`
+    pub secret: Mutex<SecretAreas>,
+    /// Files mapped into the address space, for `/proc/[pid]/maps`
+    pub mapped_files: Mutex<MappedFiles>,
+    /// Pages not written since the soft-dirty bits were last cleared
+    pub soft_dirty: Mutex<SoftDirtyPages>,
+    /// Protection keys of the address space
+    pub pkeys: Mutex<ProtectionKeys>,
     /// The resource scope
     pub scope: RwLock<Scope>,
     /// The user heap bottom
@@ -220,12 +419,55 @@ pub struct ProcessData {
 
     /// The process signal manager
     pub signal: Arc<ProcessSignalManager>,
+    /// Whether the process is stopped by a job control signal
+    pub stop: StopState,
+    /// The number of queued instances of real-time signals
+    pub queued: QueuedSignals,
+    /// The wait status of the process once a thread started a group exit
+    group_exit_code: SpinNoIrq<Option<i32>>,
 
     /// The futex table.
     futex_table: Arc<FutexTable>,
 
     /// The default mask for file permissions.
     umask: AtomicU32,
+
+    /// The OOM score adjustment value.
+    oom_score_adj: AtomicI32,
+    /// Whether the OOM killer has chosen this process as a victim
+    oom_victim: AtomicBool,
+    /// Whether the process may dump core, as set by `PR_SET_DUMPABLE`
+    dumpable: AtomicBool,
+    /// Whether the process is released as soon as it exits instead of being
+    /// left for its parent to reap, for helpers the kernel starts
+    autoreap: AtomicBool,
+    /// The signal sent when the parent exits, as set by `PR_SET_PDEATHSIG`
+    pdeath_signal: SpinNoIrq<Option<Signo>>,
+    /// The `membarrier` commands the process registered for
+    membarrier: AtomicU32,
+
+    /// The resident set size as last accounted, in pages
+    rss: AtomicUsize,
+    /// The peak resident set size observed, in pages
+    maxrss: AtomicUsize,
+    /// The peak size of the address space observed, in bytes
+    vm_peak: AtomicUsize,
+    /// Resource usage of threads that have already exited
+    exited_rusage: SpinNoIrq<Rusage>,
+    /// Resource usage of reaped children
+    children_rusage: SpinNoIrq<Rusage>,
+    /// Exited but not yet reaped children
+    zombies: SpinNoIrq<BTreeMap<Pid, Zombie>>,
+}
+
+/// What a parent keeps of an exited child until it is reaped, since the
+/// child's [`ProcessData`] may be gone by then.
+#[derive(Default, Clone, Copy)]
+struct Zombie {
+    /// The final resource usage
+    rusage: Rusage,
+    /// Whether it is a "clone" child
+    clone_child: bool,
 }
 
 impl ProcessData {
@@ -242,7 +484,16 @@ impl ProcessData {
             proc,
             exe_path: RwLock::new(exe_path),
             cmdline: RwLock::new(cmdline),
+            auxv: RwLock::default(),
             aspace,
+            thp: Mutex::new(ThpAreas::default()),
+            hugetlb: Mutex::new(HugetlbAreas::default()),
+            swap: Mutex::new(SwapEntries::default()),
+            uffd: Mutex::new(UserFaultRanges::default()),
+            secret: Mutex::new(SecretAreas::default()),
+            mapped_files: Mutex::new(MappedFiles::default()),
+            soft_dirty: Mutex::new(SoftDirtyPages::default()),
+            pkeys: Mutex::new(ProtectionKeys::default()),
             scope: RwLock::new(Scope::new()),
             heap_bottom: AtomicUsize::new(crate::config::USER_HEAP_BASE),
             heap_top: AtomicUsize::new(crate::config::USER_HEAP_BASE),
@@ -257,10 +508,27 @@ impl ProcessData {
                 signal_actions,
                 crate::config::SIGNAL_TRAMPOLINE,
             )),
+            stop: StopState::default(),
+            queued: QueuedSignals::default(),
+            group_exit_code: SpinNoIrq::new(None),
 
             futex_table: Arc::new(FutexTable::new()),
 
             umask: AtomicU32::new(0o022),
+
+            oom_score_adj: AtomicI32::new(0),
+            oom_victim: AtomicBool::new(false),
+            dumpable: AtomicBool::new(true),
+            autoreap: AtomicBool::new(false),
+            pdeath_signal: SpinNoIrq::new(None),
+            membarrier: AtomicU32::new(0),
+
+            rss: AtomicUsize::new(0),
+            maxrss: AtomicUsize::new(0),
+            vm_peak: AtomicUsize::new(0),
+            exited_rusage: SpinNoIrq::new(Rusage::default()),
+            children_rusage: SpinNoIrq::new(Rusage::default()),
+            zombies: SpinNoIrq::new(BTreeMap::new()),
         })
     }
 
@@ -318,6 +586,242 @@ impl ProcessData {
     pub fn replace_umask(&self, umask: u32) -> u32 {
         self.umask.swap(umask, Ordering::SeqCst)
     }
+
+    /// Get the oom score adjustment value.
+    pub fn oom_score_adj(&self) -> i32 {
+        self.oom_score_adj.load(Ordering::SeqCst)
+    }
+
+    /// Set the oom score adjustment value.
+    pub fn set_oom_score_adj(&self, value: i32) {
+        self.oom_score_adj.store(value, Ordering::SeqCst);
+    }
+
+    /// Returns whether the OOM killer has chosen this process as a victim.
+    pub fn is_oom_victim(&self) -> bool {
+        self.oom_victim.load(Ordering::Acquire)
+    }
+
+    /// Marks this process as chosen by the OOM killer.
+    pub fn set_oom_victim(&self) {
+        self.oom_victim.store(true, Ordering::Release);
+    }
+
+    /// Returns whether the process may dump core.
+    pub fn is_dumpable(&self) -> bool {
+        self.dumpable.load(Ordering::Relaxed)
+    }
+
+    /// Sets whether the process may dump core.
+    pub fn set_dumpable(&self, dumpable: bool) {
+        self.dumpable.store(dumpable, Ordering::Relaxed);
+    }
+
+    /// Returns whether the process is a child subreaper.
+    pub fn is_child_subreaper(&self) -> bool {
+        self.proc.is_child_subreaper()
+    }
+
+    /// Sets whether the process is a child subreaper.
+    ///
+    /// [`Process::exit`] hands orphaned descendants to the nearest living
+    /// subreaper among their ancestors.
+    pub fn set_child_subreaper(&self, subreaper: bool) {
+        self.proc.set_child_subreaper(subreaper);
+    }
+
+    /// Returns whether the process is released as soon as it exits.
+    pub fn is_autoreap(&self) -> bool {
+        self.autoreap.load(Ordering::Relaxed)
+    }
+
+    /// Makes the process be released as soon as it exits, with no zombie left
+    /// for its parent to reap.
+    pub fn set_autoreap(&self) {
+        self.autoreap.store(true, Ordering::Relaxed);
+    }
+
+    /// Get the signal the process gets when its parent exits.
+    pub fn pdeath_signal(&self) -> Option<Signo> {
+        *self.pdeath_signal.lock()
+    }
+
+    /// Set the signal the process gets when its parent exits.
+    pub fn set_pdeath_signal(&self, signo: Option<Signo>) {
+        *self.pdeath_signal.lock() = signo;
+    }
+
+    /// Accounts `pages` pages that became resident and updates the recorded
+    /// peak.
+    pub fn add_rss(&self, pages: usize) {
+        let rss = self.rss.fetch_add(pages, Ordering::Relaxed) + pages;
+        self.maxrss.fetch_max(rss, Ordering::Relaxed);
+    }
+
+    /// Accounts `pages` pages that are no longer resident.
+    pub fn sub_rss(&self, pages: usize) {
+        let _ = self
+            .rss
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |rss| {
+                Some(rss.saturating_sub(pages))
+            });
+    }
+
+    /// Recounts the resident set size of `aspace`, the address space of the
+    /// process, and updates the recorded peak.
+    pub fn sync_rss(&self, aspace: &AddrSpace) {
+        let rss = resident_pages(aspace);
+        self.rss.store(rss, Ordering::Relaxed);
+        self.maxrss.fetch_max(rss, Ordering::Relaxed);
+    }
+
+    /// Forgets what is tracked about `[start, end)` of `aspace`, the address
+    /// space of the process, after it is unmapped.
+    ///
+    /// Huge pages split at the ends of the range were remapped without their
+    /// protection keys, so the keys are applied to them again.
+    pub fn forget_range(&self, aspace: &mut AddrSpace, start: VirtAddr, end: VirtAddr) {
+        self.thp.lock().remove(start, end);
+        self.hugetlb.lock().remove(start, end);
+        self.swap.lock().remove(start, end);
+        self.uffd.lock().remove(start, end);
+        self.secret.lock().remove(start, end);
+        self.mapped_files.lock().remove(start, end);
+        self.soft_dirty.lock().remove(start, end);
+        let mut pkeys = self.pkeys.lock();
+        pkeys.remove(start, end);
+        pkeys.apply(self, aspace, start.align_down(HPAGE_SIZE), start);
+        pkeys.apply(self, aspace, end, end.align_up(HPAGE_SIZE));
+    }
+
+    /// Forgets what is tracked about the address space of the process, after
+    /// it is replaced by a new image.
+    pub fn clear_mm(&self) {
+        self.thp.lock().clear();
+        self.hugetlb.lock().clear();
+        self.swap.lock().clear();
+        self.uffd.lock().clear();
+        self.secret.lock().clear();
+        self.mapped_files.lock().clear();
+        self.soft_dirty.lock().clear();
+        self.pkeys.lock().clear();
+    }
+
+    /// Populates `[start, start + size)` of `aspace`, the address space of the
+    /// process, accounting the pages that become resident.
+    pub fn populate_area(
+        &self,
+        aspace: &mut AddrSpace,
+        start: VirtAddr,
+        size: usize,
+        access_flags: MappingFlags,
+    ) -> AxResult {
+        let resident = resident_pages_in(aspace, start, start + size);
+        aspace.populate_area(start, size, access_flags)?;
+        self.add_rss(resident_pages_in(aspace, start, start + size).saturating_sub(resident));
+        Ok(())
+    }
+
+    /// Samples the current resident set size and updates the recorded peak.
+    pub fn update_maxrss(&self) {
+        self.sync_rss(&self.aspace.lock());
+    }
+
+    /// Resets the recorded peak resident set size to the current one.
+    pub fn reset_maxrss(&self) {
+        let aspace = self.aspace.lock();
+        self.sync_rss(&aspace);
+        self.maxrss
+            .store(self.rss.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    /// Records the current size of the address space and returns the peak
+    /// size observed.
+    pub fn update_vm_peak(&self, size: usize) -> usize {
+        self.vm_peak.fetch_max(size, Ordering::Relaxed).max(size)
+    }
+
+    /// Forgets the peak size of the address space, which is replaced by exec.
+    pub fn reset_vm_peak(&self) {
+        self.vm_peak.store(0, Ordering::Relaxed);
+    }
+
+    /// Get the resource usage of the process itself, including threads that
+    /// have already exited.
+    pub fn self_rusage(&self) -> Rusage {
+        self.update_maxrss();
+        let live = self
+            .proc
+            .threads()
+            .into_iter()
+            .filter_map(|tid| get_task(tid).ok())
+            .fold(Rusage::default(), |acc, task| {
+                acc.collate(task.as_thread().rusage())
+            });
+        let mut usage = self.exited_rusage.lock().collate(live);
+        usage.maxrss = self.maxrss.load(Ordering::Relaxed) * PAGE_SIZE_4K / 1024;
+        usage
+    }
+
+    /// Get the accumulated resource usage of all reaped children.
+    pub fn children_rusage(&self) -> Rusage {
+        *self.children_rusage.lock()
+    }
+
+    /// Accounts the resource usage of an exiting thread to the process.
+    pub fn add_exited_thread(&self, thread: &Thread) {
+        let mut exited = self.exited_rusage.lock();
+        *exited = exited.collate(thread.rusage());
+    }
+
+    /// Records the final resource usage of an exited child until it is
+    /// reaped.
+    pub fn add_zombie(&self, pid: Pid, usage: Rusage, clone_child: bool) {
+        self.zombies.lock().insert(
+            pid,
+            Zombie {
+                rusage: usage,
+                clone_child,
+            },
+        );
+    }
+
+    /// Returns whether the exited child `pid` is a "clone" child.
+    pub fn is_clone_zombie(&self, pid: Pid) -> bool {
+        self.zombies
+            .lock()
+            .get(&pid)
+            .is_some_and(|zombie| zombie.clone_child)
+    }
+
+    /// Get the recorded resource usage of an exited child.
+    ///
+    /// If `reap` is set, the usage is also moved into
+    /// [`ProcessData::children_rusage`].
+    pub fn take_zombie_rusage(&self, pid: Pid, reap: bool) -> Rusage {
+        let mut zombies = self.zombies.lock();
+        let usage = if reap {
+            zombies.remove(&pid)
+        } else {
+            zombies.get(&pid).copied()
+        }
+        .unwrap_or_default()
+        .rusage;
+        if reap {
+            let mut children = self.children_rusage.lock();
+            *children = children.collate(usage);
+        }
+        usage
+    }
+
+    /// Returns the wait status the process exits with when a thread exits
+    /// the whole group with `code`.
+    ///
+    /// The first thread to start a group exit decides the status, so that
+    /// the threads it kills don't report `SIGKILL`.
+    pub fn group_exit_code(&self, code: i32) -> i32 {
+        *self.group_exit_code.lock().get_or_insert(code)
+    }
 }
 
 struct FutexTables {
@@ -469,10 +973,118 @@ pub fn set_timer_state(task: &TaskInner, state: TimerState) {
     time.set_state(state);
 }
 
+/// The offset of `si_status` in the `siginfo_t` of `SIGCHLD`
+const SI_STATUS_OFFSET: usize = 24;
+
+/// The signals whose default action stops the process
+const STOP_SIGNALS: [Signo; 4] = [
+    Signo::SIGSTOP,
+    Signo::SIGTSTP,
+    Signo::SIGTTIN,
+    Signo::SIGTTOU,
+];
+
+/// Encodes a normal exit with `code` as a wait status.
+pub fn exited_status(code: i32) -> i32 {
+    (code & 0xff) << 8
+}
+
+/// Encodes death by `signo` as a wait status.
+pub fn signaled_status(signo: Signo, core_dumped: bool) -> i32 {
+    signo as i32 | if core_dumped { 0x80 } else { 0 }
+}
+
+/// Splits the wait status of a dead child into the `si_code` and `si_status`
+/// that describe it in a `siginfo_t`.
+pub fn exit_status_info(status: i32) -> (u32, i32) {
+    match status & 0x7f {
+        0 => (CLD_EXITED, (status >> 8) & 0xff),
+        signo if status & 0x80 != 0 => (CLD_DUMPED, signo),
+        signo => (CLD_KILLED, signo),
+    }
+}
+
+/// Builds the `siginfo_t` describing a change of state of the child `pid`,
+/// as sent with `SIGCHLD` and returned by `waitid`.
+pub fn child_signal_info(signo: Signo, code: u32, pid: Pid, status: i32) -> SignalInfo {
+    let mut sig = SignalInfo::new_user(signo, code as i32, pid);
+    unsafe {
+        (&raw mut sig.0)
+            .cast::<u8>()
+            .add(SI_STATUS_OFFSET)
+            .cast::<i32>()
+            .write(status);
+    }
+    sig
+}
+
+/// Tells the parent of a process that it was stopped or continued.
+pub fn notify_parent_stop(proc_data: &ProcessData, event: StopEvent) {
+    let Some(parent) = proc_data.proc.parent() else {
+        return;
+    };
+    let Ok(data) = get_process_data(parent.pid()) else {
+        return;
+    };
+    // `SA_NOCLDSTOP` only suppresses the signal; the parent can still wait for
+    // the change.
+    let nocldstop = data.signal.actions.lock()[Signo::SIGCHLD]
+        .flags
+        .contains(SignalActionFlags::from_bits_retain(SA_NOCLDSTOP as _));
+    if !nocldstop {
+        let (code, status) = event.child_info();
+        let sig = child_signal_info(Signo::SIGCHLD, code, proc_data.proc.pid(), status);
+        let _ = send_signal_to_process(parent.pid(), Some(sig));
+    }
+    data.child_exit_event.wake();
+}
+
+/// Drops the pending signals of a process that are in `signals`.
+fn discard_pending(proc_data: &ProcessData, signals: &[Signo]) {
+    let mut set = SignalSet::default();
+    for signo in signals {
+        set.add(*signo);
+    }
+    for tid in proc_data.proc.threads() {
+        if let Ok(task) = get_task(tid)
+            && let Some(thr) = task.try_as_thread()
+        {
+            while let Some(sig) = thr.signal.dequeue_signal(&set) {
+                proc_data.queued.remove(sig.signo());
+            }
+        }
+    }
+}
+
+/// Applies the effects a signal has as soon as it is sent rather than when
+/// it is delivered.
+///
+/// Stop signals and `SIGCONT` cancel each other out, `SIGCONT` continues a
+/// stopped process even if it is blocked or ignored, and `SIGKILL` wakes a
+/// stopped process so it can die.
+fn prepare_signal(proc_data: &ProcessData, signo: Signo) {
+    match signo {
+        Signo::SIGCONT => {
+            discard_pending(proc_data, &STOP_SIGNALS);
+            if proc_data.stop.resume(true) {
+                notify_parent_stop(proc_data, StopEvent::Continued);
+            }
+        }
+        Signo::SIGKILL => {
+            proc_data.stop.resume(false);
+        }
+        _ if STOP_SIGNALS.contains(&signo) => discard_pending(proc_data, &[Signo::SIGCONT]),
+        _ => {}
+    }
+}
+
 fn send_signal_thread_inner(task: &TaskInner, thr: &Thread, sig: SignalInfo) {
+    let signo = sig.signo();
+    thr.proc_data.queued.add(signo);
     if thr.signal.send_signal(sig) {
         task.interrupt();
     }
+    thr.proc_data.reconcile_queued(signo);
 }
 
 /// Sends a signal to a thread.
@@ -485,6 +1097,8 @@ pub fn send_signal_to_thread(tgid: Option<Pid>, tid: Pid, sig: Option<SignalInfo
 
     if let Some(sig) = sig {
         info!("Send signal {:?} to thread {}", sig.signo(), tid);
+        check_queue_limit(&thread.proc_data, &sig)?;
+        prepare_signal(&thread.proc_data, sig.signo());
         send_signal_thread_inner(&task, thread, sig);
     }
 
@@ -498,11 +1112,15 @@ pub fn send_signal_to_process(pid: Pid, sig: Option<SignalInfo>) -> AxResult<()>
     if let Some(sig) = sig {
         let signo = sig.signo();
         info!("Send signal {signo:?} to process {pid}");
+        check_queue_limit(&proc_data, &sig)?;
+        prepare_signal(&proc_data, signo);
+        proc_data.queued.add(signo);
         if let Some(tid) = proc_data.signal.send_signal(sig)
             && let Ok(task) = get_task(tid)
         {
             task.interrupt();
         }
+        proc_data.reconcile_queued(signo);
     }
 
     Ok(())
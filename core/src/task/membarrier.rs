@@ -0,0 +1,137 @@
+//! Bookkeeping for `membarrier`: the commands each process registered for,
+//! and what each CPU runs, so that barriers only interrupt the CPUs that run
+//! threads they concern.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering, fence};
+
+use axconfig::plat::CPU_NUM;
+use axhal::percpu::this_cpu_id;
+use bitflags::bitflags;
+
+use super::ProcessData;
+
+bitflags! {
+    /// The expedited `membarrier` commands a process registered for.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct MembarrierState: u32 {
+        /// Other processes may issue global expedited barriers it takes part
+        /// in.
+        const GLOBAL_EXPEDITED = 1 << 0;
+        /// It may issue private expedited barriers.
+        const PRIVATE_EXPEDITED = 1 << 1;
+        /// It may issue private expedited barriers that also serialize the
+        /// instruction stream.
+        const PRIVATE_EXPEDITED_SYNC_CORE = 1 << 2;
+        /// It may issue private expedited barriers that also restart `rseq`
+        /// critical sections.
+        const PRIVATE_EXPEDITED_RSEQ = 1 << 3;
+    }
+}
+
+/// What a CPU runs.
+struct RunningCpu {
+    /// The address of the address space of the user thread it runs, or 0
+    aspace: AtomicUsize,
+    /// The registrations of the process of that thread
+    state: AtomicU32,
+}
+
+impl RunningCpu {
+    const fn new() -> Self {
+        Self {
+            aspace: AtomicUsize::new(0),
+            state: AtomicU32::new(0),
+        }
+    }
+}
+
+static RUNNING: [RunningCpu; CPU_NUM] = [const { RunningCpu::new() }; CPU_NUM];
+
+/// Returns the address identifying the address space of `proc_data`, which
+/// processes created with `CLONE_VM` share.
+fn aspace_id(proc_data: &ProcessData) -> usize {
+    Arc::as_ptr(&proc_data.aspace) as usize
+}
+
+/// Records that the current CPU switches to a thread of `proc_data`.
+pub(super) fn enter_cpu(proc_data: &ProcessData) {
+    let cpu = &RUNNING[this_cpu_id()];
+    cpu.state.store(
+        proc_data.membarrier.load(Ordering::Acquire),
+        Ordering::Release,
+    );
+    cpu.aspace.store(aspace_id(proc_data), Ordering::Release);
+    // Orders the store against the user memory accesses of the thread, so
+    // that a barrier that doesn't see this CPU running the address space
+    // runs before any of them.
+    fence(Ordering::SeqCst);
+}
+
+/// Records that the current CPU switches away from a user thread.
+pub(super) fn leave_cpu() {
+    RUNNING[this_cpu_id()].aspace.store(0, Ordering::Release);
+}
+
+impl ProcessData {
+    /// Returns the `membarrier` commands the process registered for.
+    pub fn membarrier_state(&self) -> MembarrierState {
+        MembarrierState::from_bits_truncate(self.membarrier.load(Ordering::Acquire))
+    }
+
+    /// Registers the process for the `membarrier` commands in `state`.
+    ///
+    /// The current CPU sees the registration at once; the others see it the
+    /// next time they switch to a thread of the process, or when
+    /// [`refresh_membarrier_state`] runs on them.
+    pub fn register_membarrier(&self, state: MembarrierState) {
+        self.membarrier.fetch_or(state.bits(), Ordering::AcqRel);
+        refresh_membarrier_state(self);
+    }
+
+    /// Drops the `membarrier` registrations, as the address space they were
+    /// made for is replaced.
+    pub fn clear_membarrier(&self) {
+        self.membarrier.store(0, Ordering::Release);
+        refresh_membarrier_state(self);
+    }
+}
+
+/// Updates the registrations the current CPU records for `proc_data` if it
+/// runs a thread of the process.
+pub fn refresh_membarrier_state(proc_data: &ProcessData) {
+    let cpu = &RUNNING[this_cpu_id()];
+    if cpu.aspace.load(Ordering::Acquire) == aspace_id(proc_data) {
+        cpu.state.store(
+            proc_data.membarrier.load(Ordering::Acquire),
+            Ordering::Release,
+        );
+    }
+}
+
+/// Returns the CPUs other than the current one that run a user thread, of
+/// the address space of `proc_data` if given.
+pub fn cpus_running_aspace(proc_data: Option<&ProcessData>) -> Vec<usize> {
+    let target = proc_data.map(aspace_id);
+    running_cpus(|aspace, _| target.is_none_or(|target| aspace == target))
+}
+
+/// Returns the CPUs other than the current one that run a thread of a
+/// process registered for `state`.
+pub fn cpus_running_registered(state: MembarrierState) -> Vec<usize> {
+    running_cpus(|_, registered| registered.contains(state))
+}
+
+fn running_cpus(filter: impl Fn(usize, MembarrierState) -> bool) -> Vec<usize> {
+    let this_cpu = this_cpu_id();
+    RUNNING
+        .iter()
+        .enumerate()
+        .filter(|(id, cpu)| {
+            let aspace = cpu.aspace.load(Ordering::Acquire);
+            let state = MembarrierState::from_bits_truncate(cpu.state.load(Ordering::Acquire));
+            *id != this_cpu && aspace != 0 && filter(aspace, state)
+        })
+        .map(|(id, _)| id)
+        .collect()
+}
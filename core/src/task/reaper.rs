@@ -0,0 +1,108 @@
+//! What happens to the children of an exiting process: [`Process::exit`]
+//! hands them to its nearest living ancestor that is a child subreaper, or to
+//! init, which reap them like their own children. Process groups left
+//! orphaned with stopped members are hung up.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use linux_raw_sys::general::SI_USER;
+use starry_process::{Process, ProcessGroup};
+use starry_signal::{SignalInfo, Signo};
+
+use super::{
+    ProcessData, child_signal_info, exit_status_info, get_process_data, send_signal_to_process,
+    send_signal_to_process_group,
+};
+
+/// Tells the new parents of `orphans`, the children the process of
+/// `proc_data` had when it exited, about them, once [`Process::exit`] handed
+/// them over.
+///
+/// The bookkeeping of orphans that already exited moves along, and the new
+/// parent is sent `SIGCHLD` so that it can reap them. Living orphans that
+/// asked for a signal on the death of their parent get it, and so do process
+/// groups the exit orphans with stopped members.
+pub fn reparent_orphans(proc_data: &ProcessData, orphans: &[Arc<Process>]) {
+    for orphan in orphans {
+        let pid = orphan.pid();
+        if orphan.is_zombie() {
+            let zombie = proc_data.zombies.lock().remove(&pid);
+            let Some(reaper) = orphan.parent() else {
+                continue;
+            };
+            if let Ok(data) = get_process_data(reaper.pid()) {
+                data.zombies.lock().insert(pid, zombie.unwrap_or_default());
+                let (code, status) = exit_status_info(orphan.exit_code());
+                let sig = child_signal_info(Signo::SIGCHLD, code, pid, status);
+                let _ = send_signal_to_process(reaper.pid(), Some(sig));
+                data.child_exit_event.wake();
+            }
+        } else if let Ok(data) = get_process_data(pid)
+            && let Some(signo) = data.pdeath_signal()
+        {
+            let sig = SignalInfo::new_user(signo, SI_USER as i32, proc_data.proc.pid());
+            let _ = send_signal_to_process(pid, Some(sig));
+        }
+    }
+    kill_orphaned_pgrps(&proc_data.proc, orphans);
+}
+
+/// Returns whether the process group `pg` is orphaned, disregarding `ignore`:
+/// no member has a parent in another group of the same session.
+///
+/// The members of an orphaned group have nobody left to continue them when
+/// they stop.
+fn is_orphaned_pgrp(pg: &ProcessGroup, ignore: &Process) -> bool {
+    pg.processes().into_iter().all(|proc| {
+        if core::ptr::eq(&*proc, ignore) || proc.is_zombie() {
+            return true;
+        }
+        proc.parent().is_none_or(|parent| {
+            parent.is_init()
+                || Arc::ptr_eq(&parent.group(), &proc.group())
+                || !Arc::ptr_eq(&parent.group().session(), &pg.session())
+        })
+    })
+}
+
+/// Returns whether a member of the process group `pg` is stopped.
+fn has_stopped_jobs(pg: &ProcessGroup) -> bool {
+    pg.processes()
+        .into_iter()
+        .any(|proc| get_process_data(proc.pid()).is_ok_and(|data| data.stop.is_stopped()))
+}
+
+/// Sends `SIGHUP` and `SIGCONT` to the process groups that the exit of `proc`
+/// orphans while they have stopped members, which would otherwise stay
+/// stopped forever.
+///
+/// These are the group of `proc` if its parent kept it from being orphaned,
+/// and the groups of its children, `orphans`, in other groups of its session.
+fn kill_orphaned_pgrps(proc: &Process, orphans: &[Arc<Process>]) {
+    let pg = proc.group();
+    let mut groups = Vec::new();
+    if let Some(parent) = proc.parent()
+        && !Arc::ptr_eq(&parent.group(), &pg)
+        && Arc::ptr_eq(&parent.group().session(), &pg.session())
+    {
+        groups.push(pg.clone());
+    }
+    for orphan in orphans {
+        let child_pg = orphan.group();
+        if !Arc::ptr_eq(&child_pg, &pg)
+            && Arc::ptr_eq(&child_pg.session(), &pg.session())
+            && !groups.iter().any(|group| Arc::ptr_eq(group, &child_pg))
+        {
+            groups.push(child_pg);
+        }
+    }
+
+    for group in groups {
+        if is_orphaned_pgrp(&group, proc) && has_stopped_jobs(&group) {
+            for signo in [Signo::SIGHUP, Signo::SIGCONT] {
+                let _ =
+                    send_signal_to_process_group(group.pgid(), Some(SignalInfo::new_kernel(signo)));
+            }
+        }
+    }
+}
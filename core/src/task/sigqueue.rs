@@ -0,0 +1,127 @@
+//! Accounting of queued signals against `RLIMIT_SIGPENDING`.
+//!
+//! A real-time signal takes one entry per send until it is delivered. The
+//! entries are counted per process, and all processes belong to the same
+//! user, so a single counter sums them up for the limit. Standard signals are
+//! coalesced into at most one entry each and aren't counted.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axerrno::{AxError, AxResult};
+use kspin::SpinNoIrq;
+use linux_raw_sys::general::RLIMIT_SIGPENDING;
+use starry_signal::{SignalInfo, SignalSet, Signo};
+
+use super::{AsThread, ProcessData, get_task};
+
+/// The first real-time signal
+const SIGRTMIN: u8 = 32;
+/// The number of real-time signals
+const RT_SIGNALS: usize = 33;
+
+/// The signal queue entries taken up by all processes
+static USER_QUEUED: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns whether `signo` is a real-time signal.
+fn is_realtime(signo: Signo) -> bool {
+    signo as u8 >= SIGRTMIN
+}
+
+/// The number of queued instances of each real-time signal of a process,
+/// whether directed at the process or at one of its threads.
+///
+/// The queues themselves belong to the signal managers, which also drop the
+/// instances of ignored signals. The counts are therefore reconciled with
+/// what is pending after each send.
+pub struct QueuedSignals(SpinNoIrq<[u32; RT_SIGNALS]>);
+
+impl Default for QueuedSignals {
+    fn default() -> Self {
+        Self(SpinNoIrq::new([0; RT_SIGNALS]))
+    }
+}
+
+impl Drop for QueuedSignals {
+    fn drop(&mut self) {
+        let total = self.0.get_mut().iter().map(|count| *count as usize).sum();
+        USER_QUEUED.fetch_sub(total, Ordering::Relaxed);
+    }
+}
+
+impl QueuedSignals {
+    /// Records that an instance of `signo` was queued.
+    pub fn add(&self, signo: Signo) {
+        if is_realtime(signo) {
+            self.0.lock()[(signo as u8 - SIGRTMIN) as usize] += 1;
+            USER_QUEUED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records that an instance of `signo` was dequeued.
+    pub fn remove(&self, signo: Signo) {
+        if is_realtime(signo) {
+            let count = &mut self.0.lock()[(signo as u8 - SIGRTMIN) as usize];
+            if *count > 0 {
+                *count -= 1;
+                USER_QUEUED.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Forgets the instances of the real-time signals that are no longer in
+    /// `pending`, which the signal managers dropped without them being
+    /// dequeued.
+    fn reconcile(&self, pending: SignalSet) {
+        let mut counts = self.0.lock();
+        let dropped = (SIGRTMIN..SIGRTMIN + RT_SIGNALS as u8)
+            .filter_map(Signo::from_repr)
+            .zip(counts.iter_mut())
+            .filter(|(signo, _)| !pending.has(*signo))
+            .map(|(_, count)| core::mem::take(count) as usize)
+            .sum();
+        USER_QUEUED.fetch_sub(dropped, Ordering::Relaxed);
+    }
+}
+
+impl ProcessData {
+    /// Returns the signals pending for the process or any of its threads.
+    fn pending_signals(&self) -> SignalSet {
+        self.proc
+            .threads()
+            .into_iter()
+            .filter_map(|tid| get_task(tid).ok())
+            .filter_map(|task| task.try_as_thread().map(|thr| thr.signal.pending()))
+            .fold(self.signal.pending(), |acc, pending| acc | pending)
+    }
+
+    /// Brings the count of queued real-time signals back in line with what is
+    /// pending, after `signo` was sent to the process or one of its threads.
+    pub(super) fn reconcile_queued(&self, signo: Signo) {
+        if is_realtime(signo) {
+            self.queued.reconcile(self.pending_signals());
+        }
+    }
+}
+
+/// Returns the number of signal queue entries taken up by all processes,
+/// which all belong to the same user.
+pub fn queued_signal_count() -> usize {
+    USER_QUEUED.load(Ordering::Relaxed)
+}
+
+/// Checks whether `sig` may be queued for `target` under its
+/// `RLIMIT_SIGPENDING`.
+///
+/// As on Linux, only real-time signals sent with a `si_code` user space
+/// supplies, as by `sigqueue` or `tgkill`, are subject to the limit; they
+/// fail with `EAGAIN` once it is reached.
+pub fn check_queue_limit(target: &ProcessData, sig: &SignalInfo) -> AxResult<()> {
+    if !is_realtime(sig.signo()) || sig.code() >= 0 {
+        return Ok(());
+    }
+    let limit = target.rlim.read()[RLIMIT_SIGPENDING].current;
+    if queued_signal_count() as u64 >= limit {
+        return Err(AxError::WouldBlock);
+    }
+    Ok(())
+}
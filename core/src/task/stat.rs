@@ -2,9 +2,11 @@ use alloc::{borrow::ToOwned, fmt, string::String};
 
 use axerrno::AxResult;
 use axtask::{TaskInner, TaskState};
+use linux_raw_sys::general::RLIMIT_RSS;
+use memory_addr::PAGE_SIZE_4K;
 use starry_signal::Signo;
 
-use crate::task::AsThread;
+use crate::{mm::ProcessMemory, task::AsThread};
 
 /// Represents the `/proc/[pid]/stat` file.
 ///
@@ -77,6 +79,11 @@ impl TaskStat {
         let comm = task.name();
         let comm = comm[..comm.len().min(16)].to_owned();
         let state = match task.state() {
+            TaskState::Running | TaskState::Ready | TaskState::Blocked
+                if proc_data.stop.is_stopped() =>
+            {
+                'T'
+            }
             TaskState::Running | TaskState::Ready => 'R',
             TaskState::Blocked => 'S',
             TaskState::Exited => 'Z',
@@ -84,6 +91,7 @@ impl TaskStat {
         let ppid = proc.parent().map_or(0, |p| p.pid());
         let pgrp = proc.group().pgid();
         let session = proc.group().session().sid();
+        let mem = ProcessMemory::measure(proc_data);
         Ok(Self {
             pid,
             comm: comm.to_owned(),
@@ -92,6 +100,12 @@ impl TaskStat {
             pgrp,
             session,
             num_threads: proc.threads().len() as u32,
+            vsize: mem.size as u64,
+            rss: (mem.rss() / PAGE_SIZE_4K) as i64,
+            rsslim: proc_data.rlim.read()[RLIMIT_RSS].current,
+            start_code: mem.start_code as u64,
+            end_code: mem.end_code as u64,
+            start_brk: proc_data.get_heap_bottom() as u64,
             exit_signal: proc_data.exit_signal.unwrap_or(Signo::SIGCHLD) as u8,
             exit_code: proc.exit_code(),
             ..Default::default()
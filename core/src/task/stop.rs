@@ -0,0 +1,126 @@
+//! Job control stops: `SIGSTOP`, `SIGTSTP`, `SIGTTIN` and `SIGTTOU` stop every
+//! thread of a process until `SIGCONT` continues it.
+
+use core::{future::poll_fn, task::Poll};
+
+use axpoll::PollSet;
+use axtask::future::block_on;
+use kspin::SpinNoIrq;
+use linux_raw_sys::general::{CLD_CONTINUED, CLD_STOPPED};
+use starry_signal::Signo;
+
+/// A change of the job control state of a process, reported to its parent
+/// through `wait`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopEvent {
+    /// The process was stopped by the signal.
+    Stopped(Signo),
+    /// The process was continued by `SIGCONT`.
+    Continued,
+}
+
+impl StopEvent {
+    /// Returns the wait status of the event.
+    pub fn wait_status(&self) -> i32 {
+        match self {
+            Self::Stopped(signo) => ((*signo as i32) << 8) | 0x7f,
+            Self::Continued => 0xffff,
+        }
+    }
+
+    /// Returns the `si_code` and `si_status` that describe the event in a
+    /// `siginfo_t`.
+    pub fn child_info(&self) -> (u32, i32) {
+        match self {
+            Self::Stopped(signo) => (CLD_STOPPED, *signo as i32),
+            Self::Continued => (CLD_CONTINUED, Signo::SIGCONT as i32),
+        }
+    }
+}
+
+#[derive(Default)]
+struct StopInner {
+    /// The signal that stopped the process, while it is stopped
+    stopped: Option<Signo>,
+    /// The last change not yet collected by the parent
+    event: Option<StopEvent>,
+}
+
+/// The job control state of a process.
+#[derive(Default)]
+pub struct StopState {
+    inner: SpinNoIrq<StopInner>,
+    /// Woken when the process is continued
+    resumed: PollSet,
+}
+
+impl StopState {
+    /// Stops the process because of `signo`.
+    ///
+    /// Returns `false` if it was already stopped.
+    pub fn stop(&self, signo: Signo) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.stopped.is_some() {
+            return false;
+        }
+        inner.stopped = Some(signo);
+        inner.event = Some(StopEvent::Stopped(signo));
+        true
+    }
+
+    /// Continues the process, waking its stopped threads.
+    ///
+    /// Returns whether it was stopped. The parent is told about it only if
+    /// `report`.
+    pub fn resume(&self, report: bool) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.stopped.take().is_none() {
+            return false;
+        }
+        inner.event = report.then_some(StopEvent::Continued);
+        drop(inner);
+        self.resumed.wake();
+        true
+    }
+
+    /// Returns whether the process is stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.inner.lock().stopped.is_some()
+    }
+
+    /// Blocks the current thread while the process is stopped.
+    ///
+    /// Returns whether it had to wait.
+    pub fn wait_resumed(&self) -> bool {
+        if !self.is_stopped() {
+            return false;
+        }
+        block_on(poll_fn(|cx| {
+            if !self.is_stopped() {
+                return Poll::Ready(());
+            }
+            self.resumed.register(cx.waker());
+            if self.is_stopped() {
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        }));
+        true
+    }
+
+    /// Returns the change not yet collected by the parent if `wanted` accepts
+    /// it, and forgets it if `consume`.
+    pub fn take_event(
+        &self,
+        wanted: impl FnOnce(&StopEvent) -> bool,
+        consume: bool,
+    ) -> Option<StopEvent> {
+        let mut inner = self.inner.lock();
+        let event = inner.event.filter(wanted)?;
+        if consume {
+            inner.event = None;
+        }
+        Some(event)
+    }
+}
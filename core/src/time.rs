@@ -5,7 +5,7 @@ use core::{mem, time::Duration};
 
 use axhal::time::{NANOS_PER_SEC, TimeValue, monotonic_time_nanos, wall_time};
 use axtask::{
-    WeakAxTaskRef, current,
+    TaskState, WeakAxTaskRef, current,
     future::{block_on, timeout_at},
 };
 use event_listener::{Event, listener};
@@ -14,7 +14,7 @@ use spin::Mutex;
 use starry_signal::Signo;
 use strum::FromRepr;
 
-use crate::task::poll_timer;
+use crate::task::{poll_timer, tasks};
 
 fn time_value_from_nanos(nanos: usize) -> TimeValue {
     let secs = nanos as u64 / NANOS_PER_SEC;
@@ -274,3 +274,41 @@ pub fn spawn_alarm_task() {
         axconfig::TASK_STACK_SIZE,
     );
 }
+
+/// The number of fractional bits of the load averages.
+pub const LOAD_SHIFT: u32 = 16;
+/// The interval at which the load averages are sampled.
+const LOAD_FREQ: Duration = Duration::from_secs(5);
+/// The decay of the 1, 5 and 15 minute load averages per sample, with
+/// [`LOAD_SHIFT`] fractional bits: `exp(-5s / period)`.
+const LOAD_DECAY: [u64; 3] = [60296, 64453, 65173];
+
+/// The load averages and the time of the last sample.
+static LOAD_AVG: Mutex<(Duration, [u64; 3])> = Mutex::new((Duration::ZERO, [0; 3]));
+
+/// Returns the 1, 5 and 15 minute load averages with [`LOAD_SHIFT`]
+/// fractional bits.
+///
+/// The averages are sampled lazily: every interval since the last call is
+/// taken to have had as many runnable tasks as there are now.
+pub fn load_average() -> [u64; 3] {
+    let now = Duration::from_nanos(monotonic_time_nanos());
+    let mut guard = LOAD_AVG.lock();
+    let (last, loads) = &mut *guard;
+    let samples = ((now - *last).as_nanos() / LOAD_FREQ.as_nanos()) as u32;
+    if samples > 0 {
+        let active = tasks()
+            .iter()
+            .filter(|task| matches!(task.state(), TaskState::Running | TaskState::Ready))
+            .count() as u64;
+        let active = active << LOAD_SHIFT;
+        for (load, decay) in loads.iter_mut().zip(LOAD_DECAY) {
+            // After enough samples the average has converged anyway.
+            for _ in 0..samples.min(1000) {
+                *load = (*load * decay + active * ((1 << LOAD_SHIFT) - decay)) >> LOAD_SHIFT;
+            }
+        }
+        *last += LOAD_FREQ * samples;
+    }
+    *loads
+}
@@ -1,80 +1,48 @@
-use alloc::{
-    string::{String, ToString},
-    sync::Arc,
-};
+use alloc::{string::String, vec::Vec};
+use core::{future::poll_fn, task::Poll, time::Duration};
 
-use axfs_ng::FS_CONTEXT;
-use axhal::uspace::UserContext;
-use axsync::Mutex;
-use axtask::{TaskExtProxy, spawn_task};
-use starry_api::{file::FD_TABLE, task::new_user_task, vfs::dev::tty::N_TTY};
-use starry_core::{
-    mm::{copy_from_kernel, load_user_app, new_user_aspace_empty},
-    task::{ProcessData, Thread, add_task_to_table},
-};
-use starry_process::{Pid, Process};
+use axtask::future::block_on;
+use starry_api::{file::FD_TABLE, task::spawn_user_process, vfs::dev::tty::N_TTY};
+use starry_core::task::{AsThread, processes, send_signal_to_process};
+use starry_process::Process;
+use starry_signal::{SignalInfo, Signo};
 
 pub fn run_initproc(args: &[String], envs: &[String]) -> i32 {
-    let mut uspace = new_user_aspace_empty()
-        .and_then(|mut it| {
-            copy_from_kernel(&mut it)?;
-            Ok(it)
-        })
-        .expect("Failed to create user address space");
-
-    warn!("[entry] Attempting to resolve: {}", &args[0]);
-    let loc = FS_CONTEXT
-        .lock()
-        .resolve(&args[0])
-        .map_err(|e| {
-            error!("[entry] Failed to resolve '{}': {:?}", &args[0], e);
-            e
-        })
-        .expect("Failed to resolve executable path");
-    warn!("[entry] Successfully resolved, getting absolute path...");
-    let path = loc
-        .absolute_path()
-        .map_err(|e| {
-            error!("[entry] Failed to get absolute path: {:?}", e);
-            e
-        })
-        .expect("Failed to get executable absolute path");
-    let name = loc.name();
-
-    let (entry_vaddr, ustack_top) = load_user_app(&mut uspace, None, args, envs)
-        .unwrap_or_else(|e| panic!("Failed to load user app: {}", e));
-
-    let uctx = UserContext::new(entry_vaddr.into(), ustack_top, 0);
-
-    let mut task = new_user_task(name, uctx, None);
-    task.ctx_mut().set_page_table_root(uspace.page_table_root());
-
-    let pid = task.id().as_u64() as Pid;
-    let proc = Process::new_init(pid);
-    proc.add_thread(pid);
-
-    N_TTY.bind_to(&proc).expect("Failed to bind ntty");
-
-    let proc_data = ProcessData::new(
-        proc,
-        path.to_string(),
-        Arc::new(args.to_vec()),
-        Arc::new(Mutex::new(uspace)),
-        Arc::default(),
-        None,
-    );
-    {
+    let task = spawn_user_process(args, envs, Process::new_init, None, |proc_data| {
+        N_TTY.bind_to(&proc_data.proc)?;
         let mut scope = proc_data.scope.write();
         starry_api::file::add_stdio(&mut FD_TABLE.scope_mut(&mut scope).write())
-            .expect("Failed to add stdio");
+    })
+    .unwrap_or_else(|e| panic!("Failed to start init {:?}: {:?}", args[0], e));
+
+    // Init is done once all of its threads are, not just the first one.
+    let init = task.as_thread().proc_data.clone();
+    block_on(poll_fn(|cx| {
+        if init.proc.is_zombie() {
+            Poll::Ready(())
+        } else {
+            init.exit_event.register(cx.waker());
+            Poll::Pending
+        }
+    }));
+    let exit_code = init.proc.exit_code();
+    drop(task);
+
+    // Nobody is left to adopt and reap the processes init leaves behind, so
+    // they are killed and waited for before the system shuts down.
+    loop {
+        let remaining: Vec<_> = processes()
+            .into_iter()
+            .filter(|proc_data| !proc_data.proc.is_zombie())
+            .collect();
+        if remaining.is_empty() {
+            break;
+        }
+        for proc_data in remaining {
+            let sig = SignalInfo::new_kernel(Signo::SIGKILL);
+            let _ = send_signal_to_process(proc_data.proc.pid(), Some(sig));
+        }
+        axtask::sleep(Duration::from_millis(10));
     }
-    let thr = Thread::new(pid, proc_data);
-
-    *task.task_ext_mut() = Some(unsafe { TaskExtProxy::from_impl(thr) });
-
-    let task = spawn_task(task);
-    add_task_to_table(&task);
-
-    // TODO: wait for all processes to finish
-    task.join()
+    exit_code
 }